@@ -0,0 +1,91 @@
+//! How fast the CPU core runs, reported in emulated MHz.
+//! Run it with `cargo bench --bench throughput` before and after touching the core,
+//! the NES CPU runs at about 1.79 MHz so anything far above that is headroom.
+//!
+//! It works like a small criterion: a warm up, then a number of timed samples
+//! that each run a fixed budget of cycles, and the spread of the samples
+
+use std::time::{Duration, Instant};
+
+use nes_emulator::cpu::cpu::CPU;
+use nes_emulator::cpu::memory::{Mem, MemoryMap};
+use nes_emulator::cpu::step::StopReason;
+
+const WARM_UP: Duration = Duration::from_secs(1);
+const SAMPLES: usize = 20;
+const CYCLES_PER_SAMPLE: u64 = 5_000_000;
+
+/// NTSC 2A03 clock
+const NES_MHZ: f64 = 1.789773;
+
+/// A program that never stops, it runs a mix of loads, adds, stores, a read-modify-write,
+/// the stack and a subroutine call over and over
+const PROGRAM: &[u8] = &[
+    0xA2, 0x00, // 0600 LDX #$00
+    0xBD, 0x00, 0x02, // 0602 LDA $0200,X
+    0x69, 0x01, // 0605 ADC #$01
+    0x9D, 0x00, 0x03, // 0607 STA $0300,X
+    0xFE, 0x00, 0x04, // 060A INC $0400,X
+    0x48, // 060D PHA
+    0x20, 0x18, 0x06, // 060E JSR $0618
+    0x68, // 0611 PLA
+    0xE8, // 0612 INX
+    0xD0, 0xED, // 0613 BNE $0602
+    0x4C, 0x00, 0x06, // 0615 JMP $0600
+    0xB1, 0x10, // 0618 LDA ($10),Y
+    0x60, // 061A RTS
+];
+
+fn cpu() -> CPU<MemoryMap> {
+    let mut memory = MemoryMap::new();
+    memory.memory[0x0600..0x0600 + PROGRAM.len()].copy_from_slice(PROGRAM);
+    memory.write_mem_u16(0xFFFC, 0x0600);
+
+    let mut cpu = CPU::new(memory);
+    cpu.reset();
+    cpu
+}
+
+/// Runs one budget of cycles and returns how many emulated MHz it came out at
+fn sample(cpu: &mut CPU<MemoryMap>) -> f64 {
+    let start_cycle = cpu.current_cycle;
+    let start = Instant::now();
+
+    let reason = cpu.run_for_cycles(CYCLES_PER_SAMPLE).unwrap();
+    assert_eq!(reason, StopReason::CycleBudget);
+
+    let elapsed = start.elapsed().as_secs_f64();
+    (cpu.current_cycle - start_cycle) as f64 / elapsed / 1_000_000.0
+}
+
+fn main() {
+    let mut cpu = cpu();
+
+    let warm_up = Instant::now();
+    while warm_up.elapsed() < WARM_UP {
+        sample(&mut cpu);
+    }
+
+    let mut samples: Vec<f64> = (0..SAMPLES).map(|_| sample(&mut cpu)).collect();
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mean = samples.iter().sum::<f64>() / SAMPLES as f64;
+    let median = samples[SAMPLES / 2];
+    let deviation =
+        (samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / SAMPLES as f64).sqrt();
+
+    println!(
+        "cpu throughput          [{:.2} MHz {:.2} MHz {:.2} MHz]",
+        samples[0],
+        median,
+        samples[SAMPLES - 1]
+    );
+    println!(
+        "                        mean {:.2} MHz +/- {:.2}, {:.0}x a real NES, {} samples of {} cycles",
+        mean,
+        deviation,
+        mean / NES_MHZ,
+        SAMPLES,
+        CYCLES_PER_SAMPLE
+    );
+}
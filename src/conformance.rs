@@ -0,0 +1,183 @@
+use std::fmt;
+
+use crate::cpu::bus::Bus;
+use crate::cpu::cpu::CPU;
+use crate::cpu::memory::Mem;
+use crate::cpu::rom::Rom;
+use crate::cpu::trace::trace;
+
+/// How many of the reference lines before a mismatch get shown with it
+const CONTEXT_LINES: usize = 3;
+
+/// Where the test ROM leaves its result codes, both are 0 when every test passed
+const RESULT_ADDR: u16 = 0x0002;
+
+/// Everything matched the reference log
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    /// How many instructions were compared
+    pub lines: usize,
+
+    /// What the ROM wrote to $02 and $03
+    pub result: (u8, u8),
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.result == (0, 0)
+    }
+}
+
+/// The first instruction where the trace and the reference log went different ways
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    /// 1 based line number in the reference log
+    pub line: usize,
+
+    /// The reference lines leading up to the mismatch
+    pub context: Vec<String>,
+
+    pub expected: String,
+    pub actual: String,
+
+    /// What the ROM wrote to $02 and $03 up to this point
+    pub result: (u8, u8),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "first mismatch on line {} of the reference log",
+            self.line
+        )?;
+        for line in &self.context {
+            writeln!(f, "          {}", line)?;
+        }
+        writeln!(f, "expected: {}", self.expected)?;
+        writeln!(f, "actual:   {}", self.actual)?;
+        write!(
+            f,
+            "result codes: ${:02X} ${:02X}",
+            self.result.0, self.result.1
+        )
+    }
+}
+
+/// Puts the CPU in the state the reference log starts from.
+/// The ROM is started straight at the address of the first line, that is the automation mode
+/// of nestest and it does not need a PPU to get going
+pub fn boot(rom: Rom, reference: &str) -> CPU {
+    let mut cpu = CPU::new(Bus::new(rom));
+    cpu.reset();
+
+    if let Some(start) = reference
+        .lines()
+        .next()
+        .and_then(|line| line.get(0..4))
+        .and_then(|addr| u16::from_str_radix(addr, 16).ok())
+    {
+        cpu.program_counter = start;
+    }
+
+    cpu
+}
+
+/// Runs the ROM headless and compares the trace of every instruction against the reference log
+pub fn run(rom: Rom, reference: &str) -> Result<Report, Mismatch> {
+    let mut cpu = boot(rom, reference);
+    let expected: Vec<&str> = reference.lines().collect();
+
+    let mut error = None;
+
+    for (i, line) in expected.iter().enumerate() {
+        // an instruction that errored out has no next line to compare,
+        // the error takes its place in the report
+        let actual = match error.take() {
+            Some(error) => format!("{}", error),
+            None => trace(&cpu),
+        };
+
+        if actual != *line {
+            return Err(Mismatch {
+                line: i + 1,
+                context: expected[i.saturating_sub(CONTEXT_LINES)..i]
+                    .iter()
+                    .map(|line| line.to_string())
+                    .collect(),
+                expected: line.to_string(),
+                actual,
+                result: result_codes(&cpu),
+            });
+        }
+
+        error = cpu.step().err();
+    }
+
+    Ok(Report {
+        lines: expected.len(),
+        result: result_codes(&cpu),
+    })
+}
+
+fn result_codes(cpu: &CPU) -> (u8, u8) {
+    (
+        cpu.read_mem_u8(RESULT_ADDR),
+        cpu.read_mem_u8(RESULT_ADDR + 1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::rom::Mirroring;
+
+    /// A 16kb cartridge with the program at $C000
+    fn rom_with_program(program: &[u8]) -> Rom {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[..program.len()].copy_from_slice(program);
+
+        Rom {
+            prg_rom,
+            chr_rom: vec![],
+            mapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+        }
+    }
+
+    // LDA #$00; STA $02; LDA #$81; STA $03; NOP
+    const PROGRAM: [u8; 9] = [0xA9, 0x00, 0x85, 0x02, 0xA9, 0x81, 0x85, 0x03, 0xEA];
+
+    fn reference_log() -> String {
+        let mut cpu = boot(rom_with_program(&PROGRAM), "C000");
+        let mut lines = vec![];
+        for _ in 0..5 {
+            lines.push(trace(&cpu));
+            cpu.step().unwrap();
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn run_matches_reference() {
+        let report = run(rom_with_program(&PROGRAM), &reference_log()).unwrap();
+
+        assert_eq!(report.lines, 5);
+        assert_eq!(report.result, (0x00, 0x81));
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn run_reports_first_mismatch() {
+        let reference = reference_log().replace("A:81", "A:82");
+
+        let mismatch = run(rom_with_program(&PROGRAM), &reference).unwrap_err();
+
+        assert_eq!(mismatch.line, 4);
+        assert_eq!(mismatch.context.len(), 3);
+        assert!(mismatch.context[0].starts_with("C000  A9 00     LDA #$00"));
+        assert!(mismatch.expected.contains("A:82"));
+        assert!(mismatch.actual.contains("A:81"));
+        assert_eq!(mismatch.result, (0x00, 0x00));
+    }
+}
@@ -0,0 +1,723 @@
+//! A small 6502 assembler for the tests and for patching ROMs.
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! SCREEN = $0200          ; constants
+//! .org $0600
+//! start:  LDX #<SCREEN    ; labels, < and > take the low and high byte
+//!         STA SCREEN+1,X  ; expressions can add and subtract
+//!         BNE start
+//!         JMP (vector)
+//! vector: .word start, * + 2
+//!         .byte $01, %10, 3, "text"
+//! ```
+//!
+//! Opcodes are looked up in the same table the CPU decodes with, so the two can't disagree.
+//! It is two passes: the first works out where everything goes, the second writes the bytes.
+//! An operand that is known to fit in a byte by the first pass uses zero page,
+//! a label defined further down always gets the absolute form
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::cpu::AddressingMode;
+use crate::cpu::opcodes::{self, OpCode};
+use crate::cpu::rom::Rom;
+
+/// Something wrong with the source, the line numbers start at 1.
+/// The line is 0 for problems with the program as a whole, like overlapping `.org`s
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A run of bytes that goes at one address, every `.org` starts a new one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// Assembles the source starting at `origin` into one run of bytes.
+/// A `.org` further on leaves a gap of zeroes, going backwards is an error
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let mut bytes = Vec::new();
+
+    for segment in assemble_segments(source, origin)? {
+        let offset = (segment.origin as usize)
+            .checked_sub(origin as usize)
+            .filter(|offset| *offset >= bytes.len())
+            .ok_or_else(|| AsmError {
+                line: 0,
+                message: format!(
+                    ".org ${:04X} goes back over what is already assembled",
+                    segment.origin
+                ),
+            })?;
+
+        bytes.resize(offset, 0);
+        bytes.extend(segment.bytes);
+    }
+
+    Ok(bytes)
+}
+
+/// Assembles the source starting at `origin`, each `.org` gives a new segment
+pub fn assemble_segments(source: &str, origin: u16) -> Result<Vec<Segment>, AsmError> {
+    let statements = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            parse_line(line).map_err(|message| AsmError {
+                line: i + 1,
+                message,
+            })
+        })
+        .collect::<Result<Vec<Line>, AsmError>>()?;
+
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        pc: origin,
+    };
+
+    // the first pass works out the addresses and picks the addressing modes
+    let mut modes = Vec::with_capacity(statements.len());
+    for (i, line) in statements.iter().enumerate() {
+        let mode = assembler.layout(line).map_err(|message| AsmError {
+            line: i + 1,
+            message,
+        })?;
+        modes.push(mode);
+    }
+
+    assembler.pc = origin;
+    let mut segments = vec![Segment {
+        origin,
+        bytes: Vec::new(),
+    }];
+    for (i, (line, mode)) in statements.iter().zip(modes).enumerate() {
+        assembler
+            .emit(line, mode, &mut segments)
+            .map_err(|message| AsmError {
+                line: i + 1,
+                message,
+            })?;
+    }
+
+    segments.retain(|segment| !segment.bytes.is_empty());
+    Ok(segments)
+}
+
+/// Assembles a patch and writes it over the PRG ROM. The addresses are where the CPU
+/// sees the code, a single 16K bank is mirrored at $8000 and $C000 like the bus does
+pub fn patch_rom(rom: &mut Rom, source: &str) -> Result<(), AsmError> {
+    for segment in assemble_segments(source, 0x8000)? {
+        for (i, byte) in segment.bytes.iter().enumerate() {
+            let addr = segment.origin as usize + i;
+            let offset = addr
+                .checked_sub(0x8000)
+                .map(|offset| offset % rom.prg_rom.len().max(1))
+                .filter(|_| addr <= 0xFFFF)
+                .ok_or_else(|| AsmError {
+                    line: 0,
+                    message: format!("${:04X} is not in PRG ROM", addr),
+                })?;
+            rom.prg_rom[offset] = *byte;
+        }
+    }
+
+    Ok(())
+}
+
+/// One line of source with the label and comment taken off
+struct Line {
+    label: Option<String>,
+    statement: Statement,
+}
+
+enum Statement {
+    Empty,
+    Constant(String, String),
+    Org(String),
+    Byte(Vec<String>),
+    Word(Vec<String>),
+    Instruction(String, Operand),
+}
+
+/// The operand as written, the expressions in it are worked out later
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(String),
+    Direct(String, Index),
+    Indirect(String),
+    IndirectX(String),
+    IndirectY(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    None,
+    X,
+    Y,
+}
+
+fn parse_line(line: &str) -> Result<Line, String> {
+    let mut rest = strip_comment(line).trim();
+
+    let mut label = None;
+    if let Some(colon) = rest.find(':') {
+        let name = rest[..colon].trim();
+        if is_identifier(name) {
+            label = Some(name.to_string());
+            rest = rest[colon + 1..].trim();
+        }
+    }
+
+    if rest.is_empty() {
+        return Ok(Line {
+            label,
+            statement: Statement::Empty,
+        });
+    }
+
+    if let Some((name, value)) = rest.split_once('=') {
+        let name = name.trim();
+        if !is_identifier(name) {
+            return Err(format!("'{}' is not a name a constant can have", name));
+        }
+        return Ok(Line {
+            label,
+            statement: Statement::Constant(name.to_string(), value.trim().to_string()),
+        });
+    }
+
+    let (word, operand) = match rest.split_once(char::is_whitespace) {
+        Some((word, operand)) => (word, operand.trim()),
+        None => (rest, ""),
+    };
+
+    let statement = match word.to_ascii_lowercase().as_str() {
+        ".org" => Statement::Org(operand.to_string()),
+        ".byte" | ".db" => Statement::Byte(split_list(operand)),
+        ".word" | ".dw" => Statement::Word(split_list(operand)),
+        directive if directive.starts_with('.') => {
+            return Err(format!("unknown directive {}", word))
+        }
+        _ => Statement::Instruction(word.to_ascii_uppercase(), parse_operand(operand)?),
+    };
+
+    Ok(Line { label, statement })
+}
+
+/// Drops everything after a `;` that isn't inside quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits a `.byte` or `.word` list on the commas outside of quotes
+fn split_list(list: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in list.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => items.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() {
+        items.push(current.trim().to_string());
+    }
+
+    items
+}
+
+fn parse_operand(operand: &str) -> Result<Operand, String> {
+    if operand.is_empty() {
+        return Ok(Operand::None);
+    }
+    if operand.eq_ignore_ascii_case("A") {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(value) = operand.strip_prefix('#') {
+        return Ok(Operand::Immediate(value.trim().to_string()));
+    }
+
+    if operand.starts_with('(') {
+        // the expression keeps its case, labels are case sensitive
+        let compact: String = operand.chars().filter(|c| !c.is_whitespace()).collect();
+        let upper = compact.to_ascii_uppercase();
+        let inner = |suffix_len: usize| compact[1..compact.len() - suffix_len].to_string();
+
+        return if upper.ends_with(",X)") {
+            Ok(Operand::IndirectX(inner(3)))
+        } else if upper.ends_with("),Y") {
+            Ok(Operand::IndirectY(inner(3)))
+        } else if upper.ends_with(')') {
+            Ok(Operand::Indirect(inner(1)))
+        } else {
+            Err(format!("can't make sense of the operand {}", operand))
+        };
+    }
+
+    let (value, index) = match operand.rsplit_once(',') {
+        Some((value, index)) => match index.trim().to_ascii_uppercase().as_str() {
+            "X" => (value, Index::X),
+            "Y" => (value, Index::Y),
+            _ => return Err(format!("{} is not an index register", index.trim())),
+        },
+        None => (operand, Index::None),
+    };
+
+    Ok(Operand::Direct(value.trim().to_string(), index))
+}
+
+struct Assembler {
+    symbols: HashMap<String, u16>,
+    pc: u16,
+}
+
+impl Assembler {
+    /// The first pass, defines the labels and moves the program counter along.
+    /// Returns the addressing mode picked for an instruction
+    fn layout(&mut self, line: &Line) -> Result<Option<AddressingMode>, String> {
+        if let Some(label) = &line.label {
+            self.define(label, self.pc)?;
+        }
+
+        match &line.statement {
+            Statement::Empty => {}
+            Statement::Constant(name, value) => {
+                let value = self
+                    .eval(value)?
+                    .ok_or_else(|| format!("{} has to be defined before {}", value, name))?;
+                self.define(name, value)?;
+            }
+            Statement::Org(value) => {
+                self.pc = self
+                    .eval(value)?
+                    .ok_or_else(|| format!(".org {} has to be known up front", value))?;
+            }
+            Statement::Byte(items) => {
+                let size: usize = items
+                    .iter()
+                    .map(|item| string_literal(item).map_or(1, |s| s.len()))
+                    .sum();
+                self.pc = self.pc.wrapping_add(size as u16);
+            }
+            Statement::Word(items) => self.pc = self.pc.wrapping_add(2 * items.len() as u16),
+            Statement::Instruction(mnemonic, operand) => {
+                let opcode = self.choose(mnemonic, operand)?;
+                self.pc = self.pc.wrapping_add(opcode.bytes as u16);
+                return Ok(Some(opcode.addressing_mode));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The second pass, every expression has to be known now
+    fn emit(
+        &mut self,
+        line: &Line,
+        mode: Option<AddressingMode>,
+        segments: &mut Vec<Segment>,
+    ) -> Result<(), String> {
+        let mut out = Vec::new();
+
+        match &line.statement {
+            Statement::Empty | Statement::Constant(..) => {}
+            Statement::Org(value) => {
+                self.pc = self.resolve(value)?;
+                segments.push(Segment {
+                    origin: self.pc,
+                    bytes: Vec::new(),
+                });
+            }
+            Statement::Byte(items) => {
+                for item in items {
+                    match string_literal(item) {
+                        Some(text) => out.extend(text.bytes()),
+                        None => out.push(self.byte(item)?),
+                    }
+                }
+            }
+            Statement::Word(items) => {
+                for item in items {
+                    let value = self.resolve(item)?;
+                    out.extend([value as u8, (value >> 8) as u8]);
+                }
+            }
+            Statement::Instruction(mnemonic, operand) => {
+                let mode = mode.expect("the first pass picks a mode for every instruction");
+                let opcode = find(mnemonic, mode)?;
+                out.push(opcode.code);
+
+                let value = match operand {
+                    Operand::None | Operand::Accumulator => None,
+                    Operand::Immediate(value)
+                    | Operand::Direct(value, _)
+                    | Operand::Indirect(value)
+                    | Operand::IndirectX(value)
+                    | Operand::IndirectY(value) => Some(value),
+                };
+
+                match (mode, value) {
+                    (AddressingMode::Relative, Some(value)) => {
+                        let target = self.resolve(value)? as i32;
+                        let offset = target - (self.pc as i32 + 2);
+                        if !(-128..=127).contains(&offset) {
+                            return Err(format!(
+                                "{} is {} bytes away, too far to branch",
+                                value, offset
+                            ));
+                        }
+                        out.push(offset as i8 as u8);
+                    }
+                    (_, Some(value)) if opcode.bytes == 2 => out.push(self.byte(value)?),
+                    (_, Some(value)) => {
+                        let value = self.resolve(value)?;
+                        out.extend([value as u8, (value >> 8) as u8]);
+                    }
+                    (_, None) => {}
+                }
+            }
+        }
+
+        self.pc = self.pc.wrapping_add(out.len() as u16);
+        segments
+            .last_mut()
+            .expect("there is always a segment")
+            .bytes
+            .extend(out);
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: u16) -> Result<(), String> {
+        match self.symbols.insert(name.to_string(), value) {
+            Some(old) if old != value => Err(format!("{} is defined twice", name)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Picks the opcode for an instruction and its operand,
+    /// zero page is used when the first pass already knows the value fits
+    fn choose(&self, mnemonic: &str, operand: &Operand) -> Result<&'static OpCode, String> {
+        let modes: &[AddressingMode] = match operand {
+            Operand::None => &[AddressingMode::NoneAddressing, AddressingMode::Accumulator],
+            Operand::Accumulator => &[AddressingMode::Accumulator],
+            Operand::Immediate(_) => &[AddressingMode::Immediate],
+            Operand::Indirect(_) => &[AddressingMode::Indirect],
+            Operand::IndirectX(_) => &[AddressingMode::IndirectX],
+            Operand::IndirectY(_) => &[AddressingMode::IndirectY],
+            Operand::Direct(value, index) => {
+                let zero_page = matches!(self.eval(value), Ok(Some(value)) if value <= 0xFF);
+                match (index, zero_page) {
+                    (Index::None, true) => &[
+                        AddressingMode::Relative,
+                        AddressingMode::ZeroPage,
+                        AddressingMode::Absolute,
+                    ],
+                    (Index::None, false) => &[
+                        AddressingMode::Relative,
+                        AddressingMode::Absolute,
+                        AddressingMode::ZeroPage,
+                    ],
+                    (Index::X, true) => &[AddressingMode::ZeroPageX, AddressingMode::AbsoluteX],
+                    (Index::X, false) => &[AddressingMode::AbsoluteX, AddressingMode::ZeroPageX],
+                    (Index::Y, true) => &[AddressingMode::ZeroPageY, AddressingMode::AbsoluteY],
+                    (Index::Y, false) => &[AddressingMode::AbsoluteY, AddressingMode::ZeroPageY],
+                }
+            }
+        };
+
+        modes
+            .iter()
+            .find_map(|mode| find(mnemonic, *mode).ok())
+            .ok_or_else(|| format!("{} can't take that operand", mnemonic))
+    }
+
+    fn resolve(&self, expr: &str) -> Result<u16, String> {
+        self.eval(expr)?
+            .ok_or_else(|| format!("{} is not defined", expr))
+    }
+
+    fn byte(&self, expr: &str) -> Result<u8, String> {
+        let value = self.resolve(expr)?;
+        // negative numbers come out of eval wrapped around
+        if value > 0xFF && value < 0xFF80 {
+            return Err(format!(
+                "{} is ${:04X}, that doesn't fit in a byte",
+                expr, value
+            ));
+        }
+        Ok(value as u8)
+    }
+
+    /// Works out an expression, None when it uses a label that isn't defined yet.
+    /// Terms are added and subtracted left to right
+    fn eval(&self, expr: &str) -> Result<Option<u16>, String> {
+        let expr = expr.trim();
+        if expr.is_empty() {
+            return Err("missing a value".to_string());
+        }
+
+        if let Some(rest) = expr.strip_prefix('<') {
+            return Ok(self.eval(rest)?.map(|value| value & 0xFF));
+        }
+        if let Some(rest) = expr.strip_prefix('>') {
+            return Ok(self.eval(rest)?.map(|value| value >> 8));
+        }
+
+        let mut total: Option<u16> = Some(0);
+        let mut sign = '+';
+        let mut term = String::new();
+        let mut chars = expr.chars().peekable();
+
+        loop {
+            let c = chars.next();
+            // a + or - right at the start of a term is a sign, not an operator
+            let operator = matches!(c, Some('+') | Some('-')) && !term.trim().is_empty();
+            if let Some(c) = c.filter(|_| !operator) {
+                term.push(c);
+                continue;
+            }
+
+            let value = self.term(term.trim())?;
+            total = match (total, value) {
+                (Some(total), Some(value)) if sign == '+' => Some(total.wrapping_add(value)),
+                (Some(total), Some(value)) => Some(total.wrapping_sub(value)),
+                _ => None,
+            };
+            match c {
+                Some(op) => {
+                    sign = op;
+                    term.clear();
+                }
+                None => return Ok(total),
+            }
+        }
+    }
+
+    fn term(&self, term: &str) -> Result<Option<u16>, String> {
+        if let Some(rest) = term.strip_prefix('-') {
+            return Ok(self.term(rest.trim())?.map(|value| value.wrapping_neg()));
+        }
+
+        let parsed = if term == "*" {
+            Ok(self.pc as u32)
+        } else if let Some(hex) = term.strip_prefix('$') {
+            u32::from_str_radix(hex, 16)
+        } else if let Some(binary) = term.strip_prefix('%') {
+            u32::from_str_radix(binary, 2)
+        } else if term.len() == 3 && term.starts_with('\'') && term.ends_with('\'') {
+            Ok(term.as_bytes()[1] as u32)
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse::<u32>()
+        } else if is_identifier(term) {
+            return Ok(self.symbols.get(term).copied());
+        } else {
+            return Err(format!("can't make sense of {}", term));
+        };
+
+        match parsed {
+            Ok(value) if value <= 0xFFFF => Ok(Some(value as u16)),
+            Ok(_) => Err(format!("{} doesn't fit in 16 bits", term)),
+            Err(_) => Err(format!("{} is not a number", term)),
+        }
+    }
+}
+
+fn string_literal(item: &str) -> Option<&str> {
+    item.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn find(mnemonic: &str, mode: AddressingMode) -> Result<&'static OpCode, String> {
+    opcodes::find(mnemonic, mode).ok_or_else(|| format!("{} has no {:?} form", mnemonic, mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::disasm;
+    use crate::cpu::memory::MemoryMap;
+    use crate::cpu::rom::Mirroring;
+
+    #[test]
+    fn assembles_every_addressing_mode() {
+        let program = assemble(
+            "
+            LDA #$05        ; immediate
+            LDA $10         ; zero page
+            LDA $10,X
+            LDX $10,Y
+            LDA $1234
+            LDA $1234,X
+            LDA $1234,Y
+            JMP ($0200)
+            LDA ($20,X)
+            LDA ($20),Y
+            ASL A
+            ASL
+            BRK
+            ",
+            0x0600,
+        )
+        .unwrap();
+
+        assert_eq!(
+            program,
+            vec![
+                0xA9, 0x05, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xBD, 0x34, 0x12,
+                0xB9, 0x34, 0x12, 0x6C, 0x00, 0x02, 0xA1, 0x20, 0xB1, 0x20, 0x0A, 0x0A, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_and_expressions() {
+        let program = assemble(
+            "
+            SCREEN = $0200
+            start:  LDX #<SCREEN + 1
+            loop:   STA SCREEN+1,X
+                    DEX
+                    BNE loop
+                    JSR sub
+                    JMP start
+            sub:    LDA #>SCREEN
+                    RTS
+            table:  .word start, sub, * - 2
+                    .byte 1, %10, $03, 'a', \"hi\", -1
+            ",
+            0x0600,
+        )
+        .unwrap();
+
+        assert_eq!(
+            program,
+            vec![
+                0xA2, 0x01, // LDX #$01
+                0x9D, 0x01, 0x02, // STA $0201,X
+                0xCA, // DEX
+                0xD0, 0xFA, // BNE loop
+                0x20, 0x0E, 0x06, // JSR sub
+                0x4C, 0x00, 0x06, // JMP start
+                0xA9, 0x02, // LDA #$02
+                0x60, // RTS
+                0x00, 0x06, 0x0E, 0x06, 0x0F, 0x06, // .word
+                0x01, 0x02, 0x03, 0x61, 0x68, 0x69, 0xFF, // .byte
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_references_use_absolute() {
+        // data isn't known on the first pass so it can't go in zero page
+        let program = assemble("LDA data\nBRK\ndata = $10", 0x0600).unwrap();
+        assert_eq!(program, vec![0xAD, 0x10, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn org_makes_segments() {
+        let segments = assemble_segments(".org $C000\nNOP\n.org $FFFC\n.word $C000", 0).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                Segment {
+                    origin: 0xC000,
+                    bytes: vec![0xEA]
+                },
+                Segment {
+                    origin: 0xFFFC,
+                    bytes: vec![0x00, 0xC0]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let error = assemble("NOP\nLDA ($10),X\n", 0).unwrap_err();
+        assert_eq!(error.line, 2);
+
+        let error = assemble("LDX $1234,X", 0).unwrap_err();
+        assert_eq!(error.to_string(), "line 1: LDX can't take that operand");
+
+        let error = assemble("loop: BNE far\n.org $0200\nfar: RTS", 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1: far is 510 bytes away, too far to branch"
+        );
+
+        let error = assemble("JMP nowhere", 0).unwrap_err();
+        assert_eq!(error.to_string(), "line 1: nowhere is not defined");
+    }
+
+    #[test]
+    fn disassembling_gives_the_source_back() {
+        let source = [
+            "LDA ($20),Y",
+            "STA $0300,X",
+            "INC $10",
+            "ROR A",
+            "SBC #$01",
+            "BNE $0600",
+            "JSR $1234",
+        ];
+        let program = assemble(&source.join("\n"), 0x0600).unwrap();
+
+        let mut memory = MemoryMap::new();
+        memory.memory[0x0600..0x0600 + program.len()].copy_from_slice(&program);
+        let lines: Vec<String> =
+            disasm::disassemble(&memory, 0x0600, 0x0600 + program.len() as u16 - 1)
+                .iter()
+                .map(|line| line.to_string())
+                .collect();
+
+        assert_eq!(lines, source);
+    }
+
+    #[test]
+    fn patch_rom_writes_through_the_mirror() {
+        let mut rom = Rom {
+            prg_rom: vec![0; 0x4000],
+            chr_rom: vec![],
+            mapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+        };
+
+        patch_rom(&mut rom, ".org $8010\nNOP\n.org $FFFC\n.word $C010").unwrap();
+        assert_eq!(rom.prg_rom[0x0010], 0xEA);
+        assert_eq!(&rom.prg_rom[0x3FFC..0x3FFE], &[0x10, 0xC0]);
+    }
+}
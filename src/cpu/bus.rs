@@ -1,4 +1,5 @@
-use crate::cpu::memory::Mem;
+use crate::cpu::cdl::CodeDataLog;
+use crate::cpu::memory::{BusFault, Mem, ReadKind, Snapshot};
 use crate::cpu::rom::Rom;
 
 /// The bus is a wiring between devices
@@ -6,7 +7,13 @@ use crate::cpu::rom::Rom;
 /// also the bus is mirrored 3 times
 pub struct Bus {
     cpu_vram: [u8; 2048],
-    rom: Rom
+    rom: Rom,
+
+    /// Set when an access failed, the CPU takes it after the instruction
+    fault: Option<BusFault>,
+
+    /// When there is one every PRG-ROM byte the CPU reads gets logged in it
+    pub cdl: Option<CodeDataLog>,
 }
 
 const RAM: u16 = 0x0000;
@@ -19,16 +26,32 @@ impl Bus {
         Bus {
             cpu_vram: [0; 2048],
             rom,
+            fault: None,
+            cdl: None,
         }
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    /// Where the address lands in the PRG-ROM
+    fn prg_rom_offset(&self, mut addr: u16) -> usize {
         addr -= 0x8000;
         if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
             //mirror if needed
-            addr = addr % 0x4000;
+            addr %= 0x4000;
         }
-        self.rom.prg_rom[addr as usize]
+        addr as usize
+    }
+
+    /// Which 16K PRG bank the CPU sees at the address, None outside the cartridge
+    pub fn prg_bank(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_rom_offset(addr) / 0x4000)
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.rom.prg_rom[self.prg_rom_offset(addr)]
     }
 }
 
@@ -49,12 +72,23 @@ impl Mem for Bus {
             0x8000..=0xFFFF => self.read_prg_rom(addr),
 
             _ => {
-                println!("Ignoring mem access at {}", addr);
+                log::warn!("Ignoring mem access at {}", addr);
                 0
             }
         }
     }
 
+    /// The CPU's own reads, the ones from PRG-ROM go in the code/data log
+    fn read_for(&mut self, addr: u16, kind: ReadKind) -> u8 {
+        if addr >= 0x8000 {
+            let offset = self.prg_rom_offset(addr);
+            if let Some(cdl) = &mut self.cdl {
+                cdl.log_prg(offset, addr, kind);
+            }
+        }
+        self.read_mem_u8(addr)
+    }
+
     fn write_mem_u8(&mut self, addr: u16, data: u8) {
         match addr {
             RAM..=RAM_MIRRORS_END => {
@@ -67,13 +101,30 @@ impl Mem for Bus {
             }
 
             0x8000..=0xFFFF => {
-                println!("cant write here {:#x}", addr);
-                panic!("Attempt to write to Cartridge ROM space")
+                self.fault = Some(BusFault::RomWrite { addr, data });
             }
 
             _ => {
-                println!("Ignoring mem write-access at {}", addr);
+                log::warn!("Ignoring mem write-access at {}", addr);
             }
         }
     }
+
+    fn take_fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+}
+
+/// Only the RAM changes, the PPU and APU registers aren't backed by anything yet.
+/// The code/data log is left alone, it keeps what the program has done whatever happens
+impl Snapshot for Bus {
+    type State = [u8; 2048];
+
+    fn save_state(&self) -> Self::State {
+        self.cpu_vram
+    }
+
+    fn load_state(&mut self, state: &Self::State) {
+        self.cpu_vram = *state;
+    }
 }
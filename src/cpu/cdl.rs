@@ -0,0 +1,223 @@
+//! A code/data logger, it marks every byte of the cartridge with how the program used it.
+//!
+//! The log is kept and saved in the `.cdl` format of FCEUX, which Mesen and the
+//! disassemblers that take a CDL file read too. The file is one byte for every PRG-ROM
+//! byte followed by one for every CHR-ROM byte, no header. A PRG byte is
+//!
+//! ```text
+//! xPdcAADC   C  executed, the opcode or an operand
+//!            D  read as data
+//!            AA which 8K slot the byte was read through, $8000 $A000 $C000 or $E000
+//!            c  the instruction a JMP ($nnnn) went to
+//!            d  read through a pointer by the ($nn),Y or ($nn,X) modes
+//!            P  played as DMC samples
+//! ```
+//!
+//! and a CHR byte is `xxxxxxRD`, D drawn on the screen and R read through $2007.
+//! The CHR bits and P stay clear until there is a PPU and an APU to set them.
+//! The format has no bit for opcodes so those are only kept in memory, see `is_opcode`
+
+use std::io;
+
+use crate::cpu::memory::ReadKind;
+use crate::cpu::rom::Rom;
+
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+pub const BANK: u8 = 0x0C;
+pub const INDIRECT_CODE: u8 = 0x10;
+pub const INDIRECT_DATA: u8 = 0x20;
+pub const PCM: u8 = 0x40;
+
+pub const CHR_RENDERED: u8 = 0x01;
+pub const CHR_READ: u8 = 0x02;
+
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+
+    /// Set for the PRG bytes that were executed as the first byte of an instruction
+    opcodes: Vec<bool>,
+}
+
+/// How much of the PRG-ROM the log has seen so far, in bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coverage {
+    pub code: usize,
+    pub data: usize,
+    pub unused: usize,
+}
+
+impl CodeDataLog {
+    /// An empty log the size of the cartridge
+    pub fn new(rom: &Rom) -> Self {
+        CodeDataLog {
+            prg: vec![0; rom.prg_rom.len()],
+            chr: vec![0; rom.chr_rom.len()],
+            opcodes: vec![false; rom.prg_rom.len()],
+        }
+    }
+
+    /// Picks up a log saved earlier, so more runs can add to it
+    pub fn from_bytes(rom: &Rom, bytes: &[u8]) -> Result<Self, String> {
+        let size = rom.prg_rom.len() + rom.chr_rom.len();
+        if bytes.len() != size {
+            return Err(format!(
+                "the log is {} bytes, the cartridge needs {}",
+                bytes.len(),
+                size
+            ));
+        }
+
+        let (prg, chr) = bytes.split_at(rom.prg_rom.len());
+        Ok(CodeDataLog {
+            prg: prg.to_vec(),
+            chr: chr.to_vec(),
+            opcodes: vec![false; prg.len()],
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.prg.clone();
+        bytes.extend(&self.chr);
+        bytes
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    /// Marks the PRG byte at `offset` in the ROM, which the CPU read at `addr`
+    pub fn log_prg(&mut self, offset: usize, addr: u16, kind: ReadKind) {
+        let flags = match kind {
+            ReadKind::Opcode | ReadKind::Operand => CODE,
+            ReadKind::IndirectOpcode => CODE | INDIRECT_CODE,
+            ReadKind::Data => DATA,
+            ReadKind::IndirectData => DATA | INDIRECT_DATA,
+            ReadKind::Dummy => return,
+        };
+
+        let bank = ((addr >> 13) as u8 & 0b11) << 2;
+        self.prg[offset] = (self.prg[offset] & !BANK) | bank | flags;
+        if let ReadKind::Opcode | ReadKind::IndirectOpcode = kind {
+            self.opcodes[offset] = true;
+        }
+    }
+
+    /// Marks the CHR byte at `offset` with CHR_RENDERED or CHR_READ
+    pub fn log_chr(&mut self, offset: usize, flags: u8) {
+        self.chr[offset] |= flags;
+    }
+
+    /// True when the PRG byte started an instruction during this run
+    pub fn is_opcode(&self, offset: usize) -> bool {
+        self.opcodes[offset]
+    }
+
+    pub fn coverage(&self) -> Coverage {
+        let count = |flag: u8| self.prg.iter().filter(|&&byte| byte & flag != 0).count();
+        Coverage {
+            code: count(CODE),
+            data: count(DATA),
+            unused: self.prg.iter().filter(|&&byte| byte == 0).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::asm;
+    use crate::cpu::bus::Bus;
+    use crate::cpu::cpu::CPU;
+    use crate::cpu::memory::Mem;
+
+    const PROGRAM: &str = "
+        reset:  LDA #<table
+                STA $00
+                LDA #>table
+                STA $01
+                LDY #$01
+                LDA ($00),Y
+                LDA table
+                JMP ($0002)
+        here:   NOP
+                JMP here
+        table:  .byte $11, $22, $33
+    ";
+
+    const TABLE: usize = 0x16;
+
+    fn cpu() -> CPU {
+        let mut prg = asm::assemble(PROGRAM, 0x8000).unwrap();
+        prg.resize(0x4000, 0);
+        prg[0x3FFA..].copy_from_slice(&[0x12, 0x80, 0x00, 0x80, 0x12, 0x80]);
+
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        let rom = Rom::new(&raw).unwrap();
+
+        let mut bus = Bus::new(rom);
+        bus.cdl = Some(CodeDataLog::new(bus.rom()));
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        // the pointer JMP ($0002) goes through, to `here`
+        cpu.write_mem_u16(0x0002, 0x8012);
+        cpu
+    }
+
+    #[test]
+    fn marks_code_and_data() {
+        let mut cpu = cpu();
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+        let cdl = cpu.bus.cdl.as_ref().unwrap();
+
+        // LDA #<table, the opcode and the operand
+        assert_eq!(cdl.prg[0], CODE);
+        assert_eq!(cdl.prg[1], CODE);
+        assert!(cdl.is_opcode(0));
+        assert!(!cdl.is_opcode(1));
+
+        // read through the pointer at $00, then straight from LDA table
+        assert_eq!(cdl.prg[TABLE], DATA);
+        assert_eq!(cdl.prg[TABLE + 1], DATA | INDIRECT_DATA);
+        assert_eq!(cdl.prg[TABLE + 2], 0);
+
+        assert_eq!(cdl.prg[0x12], CODE | INDIRECT_CODE);
+        assert_eq!(cdl.prg[0x13], CODE);
+        assert!(cdl.is_opcode(0x12));
+    }
+
+    #[test]
+    fn marks_the_bank_slot() {
+        let mut cpu = cpu();
+        cpu.step().unwrap();
+        cpu.trigger_nmi();
+        cpu.step().unwrap();
+        let cdl = cpu.bus.cdl.as_ref().unwrap();
+
+        // the NMI vector is read through the $E000 slot of the mirrored 16K
+        assert_eq!(cdl.prg[0x3FFA], DATA | 0b1100);
+        assert_eq!(cdl.prg[0x3FFB], DATA | 0b1100);
+        assert_eq!(cdl.prg[0x12], CODE);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let mut cpu = cpu();
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+        let cdl = cpu.bus.cdl.as_ref().unwrap();
+        let bytes = cdl.to_bytes();
+        assert_eq!(bytes.len(), 0x4000 + 0x2000);
+
+        let loaded = CodeDataLog::from_bytes(cpu.bus.rom(), &bytes).unwrap();
+        assert_eq!(loaded.prg, cdl.prg);
+        assert_eq!(loaded.coverage(), cdl.coverage());
+        assert!(CodeDataLog::from_bytes(cpu.bus.rom(), &bytes[1..]).is_err());
+    }
+}
@@ -1,61 +1,29 @@
 // as defined in http://www.6502.org/users/obelisk/6502/registers.html
 
-use std::vec;
-
-use log::{trace, warn};
-
-// use crate::cpu::memory_map::MemoryMap;
 use crate::cpu::bus::Bus;
-use crate::cpu::memory::Mem;
+use crate::cpu::error::{CpuState, EmuError};
+use crate::cpu::interrupt::{self, Interrupt, InterruptType, IrqSource};
+use crate::cpu::memory::{BusFault, Mem, ReadKind, Snapshot};
+use crate::cpu::opcodes::OpCode;
 use crate::cpu::processor_status::{ProcessorStatus, ProcessorStatusFlags};
+use crate::cpu::step::{Step, StopReason};
+use crate::cpu::trace::{self, TraceBuffer, TraceEntry};
+use std::collections::HashSet;
 
-use super::opcodes::{self, OpCode, OPCODES_MAP}; // 1.3.4
-
-/// This defines the memory
-/// and has some implementations for managing that memory
-/// This holds the memory. All of it <3.
-/// The first 256 byte page of memory (0x0000 - 0x00FF) is Zero Page
-/// The second page (0x0100-0x01FF) is the system stack
-/// The other reserved parts of the memory map is 0xFFFA to 0xFFFF
-/// that part has to be programed with the interrupt handler (0xFFFA/B)
-/// the power reset location and the BRK/interrupt request handler
-/*
-pub struct MemoryMap {
-    pub memory: [u8; 0xFFFF],
-}
-
-impl MemoryMap {
-    pub fn new() -> MemoryMap {
-        MemoryMap {
-            memory: [0; 0xFFFF],
-        }
-    }
-}
-
-impl Mem for MemoryMap {
-    fn read_mem_u8(&self, loc: u16) -> u8 {
-        return self.memory[loc as usize];
-    }
+use super::opcodes::{self, Instruction, OPCODES};
 
-    fn write_mem_u8(&mut self, loc: u16, data: u8) {
-        self.memory[loc as usize] = data;
-    }
-}
-*/
+/// The stack always lives in the second page of memory
+const STACK: u16 = 0x0100;
 
 /// Defines the state of a 6502 CPU
 /// just a reminder that the CPU will store data little endian <3
-pub struct CPU {
+pub struct CPU<M: Mem = Bus> {
     /// Points to the next program to be executed
     pub program_counter: u16,
 
-    /// Points to a 256 byte stack located between 0x0100 and 0x01FF
-    /// Starts at 0x01FF and decreases down
-    pub stack_pointer: u16,
-
-    /// This is the offset that we SUBTRACT ( the stack moves DOWN )
-    /// from the stack pointer in order to find were to place the next byte of memory
-    stack_base: u8,
+    /// The S register, the low byte of the next free slot in the stack at 0x0100-0x01FF.
+    /// Pushes write then decrement, pulls increment then read, both wrap inside the page
+    pub stack_pointer: u8,
 
     /// Used for arethmetic operations
     pub accumulator: u8,
@@ -66,18 +34,51 @@ pub struct CPU {
     /// same as x
     pub index_register_y: u8,
 
-    pub current_cycle: u32,
+    /// How many cycles the CPU has run since power on
+    pub current_cycle: u64,
 
     /// Holds flags for when operations are done
     /// this u8 is controlled with the processor status flags enum
     pub processor_status: ProcessorStatus,
 
-    /// Provides an interface for memory access
-    pub bus: Bus,
+    /// Provides an interface for memory access, the NES bus or a flat 64K of RAM
+    pub bus: M,
+
+    /// When set the unofficial opcodes are rejected instead of executed
+    pub strict_opcodes: bool,
+
+    /// When set ADC and SBC honour the DecimalMode flag like a stock 6502.
+    /// The 2A03 in the NES has the BCD circuit cut so it is off by default
+    pub decimal_mode: bool,
+
+    /// Addresses the run functions stop at before executing the instruction there
+    pub breakpoints: HashSet<u16>,
+
+    /// The last instructions run, for a look at what led up to an error or a crash
+    pub trace_buffer: TraceBuffer,
+
+    /// Set by trigger_nmi, the NMI is edge triggered so it stays pending until it is serviced
+    nmi_pending: bool,
+
+    /// One bit per IrqSource currently holding the IRQ line
+    irq_lines: u8,
+
+    /// The IRQ line as it was polled at the end of the last instruction
+    irq_pending: bool,
+
+    /// Set by the JAM opcodes, only a reset gets the CPU going again
+    jammed: bool,
+
+    /// Set by `JMP ($nnnn)`, the opcode it lands on is read as an IndirectOpcode
+    jumped_indirect: bool,
+
+    /// A mode operand_address was asked for that doesn't address memory.
+    /// It is reported once the instruction is done
+    invalid_mode: Option<AddressingMode>,
 }
 
 /// Forward all memory operations to the bus
-impl Mem for CPU {
+impl<M: Mem> Mem for CPU<M> {
     fn read_mem_u8(&self, addr: u16) -> u8 {
         self.bus.read_mem_u8(addr)
     }
@@ -87,8 +88,68 @@ impl Mem for CPU {
     }
 }
 
+/// Everything running a program changes, the registers, the interrupt lines and what
+/// the bus keeps. The settings like strict_opcodes and the breakpoints are not in it
+#[derive(Clone)]
+pub struct CpuSnapshot<S> {
+    program_counter: u16,
+    stack_pointer: u8,
+    accumulator: u8,
+    index_register_x: u8,
+    index_register_y: u8,
+    current_cycle: u64,
+    processor_status: u8,
+    nmi_pending: bool,
+    irq_lines: u8,
+    irq_pending: bool,
+    jammed: bool,
+    jumped_indirect: bool,
+    bus: S,
+}
+
+impl<M: Mem + Snapshot> Snapshot for CPU<M> {
+    type State = CpuSnapshot<M::State>;
+
+    fn save_state(&self) -> Self::State {
+        CpuSnapshot {
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            accumulator: self.accumulator,
+            index_register_x: self.index_register_x,
+            index_register_y: self.index_register_y,
+            current_cycle: self.current_cycle,
+            processor_status: self.processor_status.0,
+            nmi_pending: self.nmi_pending,
+            irq_lines: self.irq_lines,
+            irq_pending: self.irq_pending,
+            jammed: self.jammed,
+            jumped_indirect: self.jumped_indirect,
+            bus: self.bus.save_state(),
+        }
+    }
+
+    fn load_state(&mut self, state: &Self::State) {
+        self.program_counter = state.program_counter;
+        self.stack_pointer = state.stack_pointer;
+        self.accumulator = state.accumulator;
+        self.index_register_x = state.index_register_x;
+        self.index_register_y = state.index_register_y;
+        self.current_cycle = state.current_cycle;
+        self.processor_status.0 = state.processor_status;
+        self.nmi_pending = state.nmi_pending;
+        self.irq_lines = state.irq_lines;
+        self.irq_pending = state.irq_pending;
+        self.jammed = state.jammed;
+        self.jumped_indirect = state.jumped_indirect;
+        self.invalid_mode = None;
+        self.bus.load_state(&state.bus);
+        // what is in it happened after the state, or in a run that was gone back on
+        self.trace_buffer.clear();
+    }
+}
+
 // These are the different ways that an instruction can address data
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum AddressingMode {
     NoneAddressing,
 
@@ -135,131 +196,278 @@ pub enum AddressingMode {
     IndirectY,
 }
 
-impl std::fmt::Display for CPU {
+/// Prints the instruction about to run in the nestest log format
+impl<M: Mem> std::fmt::Display for CPU<M> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let code = self.read_mem_u8(self.program_counter);
-        let opcode = OPCODES_MAP
-            .get(&code)
-            .expect(&format!("OpCode {:x} is not in the OPCODES_MAP\n", code));
-
-        // let mut print_strings: Vec<String> = Vec::new();
-
-        write!(f, "{:#06x} | ", self.program_counter).unwrap();
-
-        for param in 0..opcode.bytes as u16 {
-            write!(
-                f,
-                "{:#04x} ",
-                self.read_mem_u8(self.program_counter + param) as u8
-            )
-            .unwrap();
-        }
-
-        write!(f, "{}", "     ".repeat(3 - opcode.bytes as usize)).unwrap();
-
-        // format this better
-        // print_strings.push(opcode.mnemonic.to_string());
-        write!(f, "| {} ", opcode.mnemonic.to_string()).unwrap();
-        for param in 1..opcode.bytes as u16 {
-            write!(
-                f,
-                " {:#04x}",
-                self.read_mem_u8(self.program_counter + param) as u8
-            )
-            .unwrap();
-        }
-        write!(f, "{}", "     ".repeat(3 - opcode.bytes as usize)).unwrap();
-
-        write!(f, " | A:{:#04x}   ", self.accumulator).unwrap();
-        write!(f, "X:{:#04x}   ", self.index_register_x).unwrap();
-        write!(f, "Y:{:#04x}   ", self.index_register_y).unwrap();
-        write!(
-            f,
-            "SP:{:#06x} |",
-            self.stack_pointer - self.stack_base as u16
-        )
-        .unwrap();
-
-        write!(f, " CYC:{}", self.current_cycle).unwrap();
-
-        write!(f, "")
+        write!(f, "{}", trace::trace(self))
     }
 }
 
-impl CPU {
-    pub fn new(bus: Bus) -> CPU {
-        return CPU {
+impl<M: Mem> CPU<M> {
+    pub fn new(bus: M) -> Self {
+        CPU {
             program_counter: 0,
-            stack_pointer: 0x01FF,
-            stack_base: 0,
+            stack_pointer: 0,
             accumulator: 0,
             index_register_x: 0,
             index_register_y: 0,
             current_cycle: 0,
             processor_status: ProcessorStatus(ProcessorStatusFlags::Default as u8),
             bus,
-            // memory: MemoryMap::new(),
-        };
+            strict_opcodes: false,
+            decimal_mode: false,
+            breakpoints: HashSet::new(),
+            trace_buffer: TraceBuffer::new(trace::TRACE_BUFFER_LENGTH),
+            nmi_pending: false,
+            irq_lines: 0,
+            irq_pending: false,
+            jammed: false,
+            jumped_indirect: false,
+            invalid_mode: None,
+        }
     }
 
-    /// Assumes the next part of the program counter is an address
-    /// this function gets the address from that address using the specified addressing mode
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    /// Works out the address an operand sitting at `addr` points to without touching the bus,
+    /// the trace uses this to look at an instruction before it runs
+    pub fn get_absolute_address(&self, mode: &AddressingMode, addr: u16) -> u16 {
         match mode {
-            AddressingMode::Immediate => self.program_counter,
-            AddressingMode::Relative => self.program_counter,
-            AddressingMode::ZeroPage => self.read_mem_u8(self.program_counter) as u16,
+            AddressingMode::Immediate => addr,
+            AddressingMode::Relative => addr,
+            AddressingMode::ZeroPage => self.read_mem_u8(addr) as u16,
             AddressingMode::ZeroPageX => {
-                self.read_mem_u8(self.program_counter)
-                    .wrapping_add(self.index_register_x) as u16
+                self.read_mem_u8(addr).wrapping_add(self.index_register_x) as u16
             }
             AddressingMode::ZeroPageY => {
-                self.read_mem_u8(self.program_counter)
-                    .wrapping_add(self.index_register_y) as u16
+                self.read_mem_u8(addr).wrapping_add(self.index_register_y) as u16
             }
-            AddressingMode::Absolute => self.read_mem_u16(self.program_counter),
+            AddressingMode::Absolute => self.read_mem_u16(addr),
             AddressingMode::AbsoluteX => self
-                .read_mem_u16(self.program_counter)
+                .read_mem_u16(addr)
                 .wrapping_add(self.index_register_x as u16),
             AddressingMode::AbsoluteY => self
-                .read_mem_u16(self.program_counter)
+                .read_mem_u16(addr)
                 .wrapping_add(self.index_register_y as u16),
             AddressingMode::Indirect => {
-                let ptr = self.read_mem_u8(self.program_counter);
+                let ptr = self.read_mem_u8(addr);
                 let lo = self.read_mem_u8(ptr as u16);
                 let hi = self.read_mem_u8(ptr.wrapping_add(1) as u16);
                 (hi as u16) << 8 | (lo as u16)
             }
             AddressingMode::IndirectX => {
-                let base = self.read_mem_u8(self.program_counter);
-                let ptr: u8 = (base as u8).wrapping_add(self.index_register_x);
+                let base = self.read_mem_u8(addr);
+                let ptr: u8 = base.wrapping_add(self.index_register_x);
                 let lo = self.read_mem_u8(ptr as u16);
                 let hi = self.read_mem_u8(ptr.wrapping_add(1) as u16);
                 (hi as u16) << 8 | (lo as u16)
             }
             AddressingMode::IndirectY => {
-                let base = self.read_mem_u8(self.program_counter);
+                let base = self.read_mem_u8(addr);
                 let lo = self.read_mem_u8(base as u16);
-                let hi = self.read_mem_u8((base as u8).wrapping_add(1) as u16);
+                let hi = self.read_mem_u8(base.wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.index_register_y as u16);
-                deref
+                deref_base.wrapping_add(self.index_register_y as u16)
+            }
+
+            // there is no memory operand
+            AddressingMode::NoneAddressing | AddressingMode::Accumulator => 0,
+        }
+    }
+
+    /// One bus cycle that reads memory. Everything the CPU does on the bus while it runs
+    /// goes through read and write, so the cycle count and the bus ticks follow the accesses
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_as(addr, ReadKind::Data)
+    }
+
+    /// A read that tells the bus what the byte is for, read is the one for plain data
+    fn read_as(&mut self, addr: u16, kind: ReadKind) -> u8 {
+        self.current_cycle += 1;
+        self.bus.tick();
+        let data = self.bus.read_for(addr, kind);
+        if let ReadKind::Opcode | ReadKind::IndirectOpcode | ReadKind::Operand = kind {
+            self.trace_buffer.fetched(addr, data);
+        }
+        data
+    }
+
+    /// One bus cycle that writes memory
+    fn write(&mut self, addr: u16, data: u8) {
+        self.current_cycle += 1;
+        self.bus.tick();
+        self.bus.write_mem_u8(addr, data);
+    }
+
+    /// A read the CPU makes only because it can't help it, the value is thrown away.
+    /// The fast-cpu feature keeps the cycle but skips the access
+    fn dummy_read(&mut self, addr: u16) {
+        if cfg!(feature = "fast-cpu") {
+            self.current_cycle += 1;
+            self.bus.tick();
+        } else {
+            self.read_as(addr, ReadKind::Dummy);
+        }
+    }
+
+    /// Read-modify-write instructions write the value they read straight back
+    /// while they work out the new one
+    fn dummy_write(&mut self, addr: u16, data: u8) {
+        if cfg!(feature = "fast-cpu") {
+            self.current_cycle += 1;
+            self.bus.tick();
+        } else {
+            self.write(addr, data);
+        }
+    }
+
+    /// Reads the byte under the program counter and moves past it
+    fn fetch(&mut self) -> u8 {
+        let data = self.read_as(self.program_counter, ReadKind::Operand);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        data
+    }
+
+    fn fetch_u16(&mut self) -> u16 {
+        let lo = self.fetch() as u16;
+        let hi = self.fetch() as u16;
+        hi << 8 | lo
+    }
+
+    /// Runs the addressing cycles of the indexed modes that can cross a page.
+    /// Returns the address with the high byte not fixed up yet, which the CPU reads
+    /// from first, and the real one
+    fn indexed_address(&mut self, mode: &AddressingMode) -> (u16, u16) {
+        let (base, index) = match mode {
+            AddressingMode::AbsoluteX => (self.fetch_u16(), self.index_register_x),
+            AddressingMode::AbsoluteY => (self.fetch_u16(), self.index_register_y),
+            AddressingMode::IndirectY => {
+                let ptr = self.fetch();
+                let lo = self.read(ptr as u16) as u16;
+                let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
+                (hi << 8 | lo, self.index_register_y)
             }
+            _ => unreachable!("mode {:?} is not indexed", mode),
+        };
+
+        let addr = base.wrapping_add(index as u16);
+        ((base & 0xFF00) | (addr & 0x00FF), addr)
+    }
 
+    /// Runs the addressing cycles for the mode and returns the address the operand is at.
+    /// The indexed modes always take the extra cycle to fix up the high byte,
+    /// that is what the stores and read-modify-write instructions do
+    fn operand_address(&mut self, mode: &AddressingMode) -> u16 {
+        match mode {
+            AddressingMode::Immediate | AddressingMode::Relative => {
+                let addr = self.program_counter;
+                self.program_counter = self.program_counter.wrapping_add(1);
+                addr
+            }
+            AddressingMode::ZeroPage => self.fetch() as u16,
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let base = self.fetch();
+                self.dummy_read(base as u16);
+                let index = if *mode == AddressingMode::ZeroPageX {
+                    self.index_register_x
+                } else {
+                    self.index_register_y
+                };
+                base.wrapping_add(index) as u16
+            }
+            AddressingMode::Absolute => self.fetch_u16(),
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY => {
+                let (unfixed, addr) = self.indexed_address(mode);
+                self.dummy_read(unfixed);
+                addr
+            }
+            AddressingMode::Indirect => {
+                // same page wrap bug as JMP ($xxFF)
+                let ptr = self.fetch_u16();
+                let lo = self.read(ptr) as u16;
+                let hi = self.read((ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF)) as u16;
+                hi << 8 | lo
+            }
+            AddressingMode::IndirectX => {
+                let base = self.fetch();
+                self.dummy_read(base as u16);
+                let ptr = base.wrapping_add(self.index_register_x);
+                let lo = self.read(ptr as u16) as u16;
+                let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
+                hi << 8 | lo
+            }
             AddressingMode::NoneAddressing | AddressingMode::Accumulator => {
-                panic!("mode {:?} is not supported", mode);
+                self.invalid_mode = Some(*mode);
+                0
+            }
+        }
+    }
+
+    /// Reads the operand for the instructions that only read memory.
+    /// These are the ones that pay the page crossing penalty. They read from the address
+    /// before its high byte is fixed up and only go again when that was the wrong one
+    fn read_operand(&mut self, mode: &AddressingMode) -> u8 {
+        let kind = Self::data_kind(mode);
+        match mode {
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY => {
+                let (unfixed, addr) = self.indexed_address(mode);
+                if unfixed == addr {
+                    self.read_as(addr, kind)
+                } else {
+                    self.read_as(unfixed, ReadKind::Dummy);
+                    self.read_as(addr, kind)
+                }
+            }
+            _ => {
+                let addr = self.operand_address(mode);
+                self.read_as(addr, kind)
             }
         }
     }
 
+    /// How the bus sees the operand read, an immediate is part of the instruction
+    /// and data the indirect modes find through a pointer is told apart
+    fn data_kind(mode: &AddressingMode) -> ReadKind {
+        match mode {
+            AddressingMode::Immediate => ReadKind::Operand,
+            AddressingMode::IndirectX | AddressingMode::IndirectY => ReadKind::IndirectData,
+            _ => ReadKind::Data,
+        }
+    }
+
+    /// The first half of a read-modify-write instruction.
+    /// Returns the address and the value that was there
+    fn read_for_modify(&mut self, mode: &AddressingMode) -> (u16, u8) {
+        let addr = self.operand_address(mode);
+        let data = self.read_as(addr, Self::data_kind(mode));
+        self.dummy_write(addr, data);
+        (addr, data)
+    }
+
     /// Resets all the registers and gets the first instruction of the program
     pub fn reset(&mut self) {
         self.index_register_x = 0;
         self.index_register_y = 0;
-        self.processor_status.reset_flags();
+        // interrupts start disabled, the program has to CLI once it is ready for them
+        self.processor_status = ProcessorStatus(ProcessorStatusFlags::Default as u8);
+
+        self.nmi_pending = false;
+        self.irq_lines = 0;
+        self.irq_pending = false;
+        self.jammed = false;
+        self.jumped_indirect = false;
+
+        // reset runs the interrupt sequence with the writes turned into reads,
+        // so nothing lands on the stack but S still moves down by 3
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+        for _ in 0..3 {
+            self.dummy_read_stack();
+            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        }
 
         // Get the start of the program from the program address
-        self.program_counter = self.read_mem_u16(0xFFFC);
+        let lo = self.read(0xFFFC) as u16;
+        let hi = self.read(0xFFFD) as u16;
+        self.program_counter = hi << 8 | lo;
     }
 
     /// Loads the program at 0x8000 in the address space.
@@ -274,256 +482,260 @@ impl CPU {
     }
 
     // The tests use this function
-    pub fn load_and_run_program(&mut self, program: Vec<u8>) {
+    pub fn load_and_run_program(&mut self, program: Vec<u8>) -> Result<(), EmuError> {
         self.load_program(program);
         self.reset();
         self.program_counter = 0x0600;
-        self.run();
+        self.run()
     }
 
     // The tests use this function
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
+    pub fn run(&mut self) -> Result<(), EmuError> {
+        self.run_with_callback(|_| {})
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    /// Runs until a BRK, calling back before every instruction
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<(), EmuError>
     where
-        F: FnMut(&mut CPU),
+        F: FnMut(&mut CPU<M>),
     {
         loop {
             callback(self);
 
-            let code = self.read_mem_u8(self.program_counter);
-            let opcode = OPCODES_MAP.get(&code).expect(&format!(
-                "OpCode {:x} is not in the OPCODES_MAP. dumping CPU\n {}",
-                code, self
-            ));
-
-            self.program_counter += 1; // consume the read instruction and point to the next
-
-            // used to preform a check to see if the program counter has incremented
-            let program_counter_state = self.program_counter;
+            if self.step()?.opcode.code == 0x00 {
+                return Ok(());
+            }
+        }
+    }
 
-            self.current_cycle += opcode.cycles as u32;
+    /// Executes one instruction, servicing a pending interrupt first
+    pub fn step(&mut self) -> Result<Step, EmuError> {
+        if self.jammed {
+            return Err(EmuError::Jammed {
+                state: self.state(),
+            });
+        }
 
-            match code {
-                /* ------ LOAD INSTRUCTIONS ------ */
-                // LDA opcode
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.lda(&opcode.addressing_mode);
-                }
+        let cycles_before = self.current_cycle;
+        let interrupt = self.poll_interrupts();
+        let address = self.program_counter;
+        self.trace_buffer.push(TraceEntry::new(self));
+        let opcode = self.execute_instruction()?;
 
-                // ldx
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
-                    self.ldx(&opcode.addressing_mode);
-                }
+        Ok(Step {
+            address,
+            opcode,
+            interrupt,
+            cycles: self.current_cycle - cycles_before,
+        })
+    }
 
-                // ldy
-                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
-                    self.ldy(&opcode.addressing_mode);
-                }
+    /// Runs until at least `cycles` more cycles have gone by,
+    /// the last instruction can take the CPU a few cycles past the budget
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<StopReason, EmuError> {
+        let end = self.current_cycle + cycles;
+        self.run_until_stopped(|cpu| (cpu.current_cycle >= end).then_some(StopReason::CycleBudget))
+    }
 
-                /* ------ ADDING + SUBTRACTING (WITH CARRY) INSTRUCTIONS ------ */
-                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.addressing_mode)
-                }
+    /// Runs until the predicate returns true, it is checked after every instruction
+    pub fn run_until<F>(&mut self, mut predicate: F) -> Result<StopReason, EmuError>
+    where
+        F: FnMut(&CPU<M>) -> bool,
+    {
+        self.run_until_stopped(|cpu| predicate(cpu).then_some(StopReason::Condition))
+    }
 
-                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                    self.sbc(&opcode.addressing_mode)
-                }
+    /// Runs until the PPU would move on to the next frame
+    pub fn run_frame(&mut self) -> Result<StopReason, EmuError> {
+        let frame = trace::ppu_frame(self.current_cycle);
+        self.run_until_stopped(|cpu| {
+            (trace::ppu_frame(cpu.current_cycle) != frame).then_some(StopReason::FrameComplete)
+        })
+    }
 
-                /* ------ LOGICAL BIT OPERATIONS ------ */
-                // and opcode
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.addressing_mode)
-                }
+    /// The loop behind the run functions. Breakpoints are checked before every instruction
+    /// except the first one so a run that stopped on a breakpoint can be resumed from it
+    fn run_until_stopped<F>(&mut self, mut stop: F) -> Result<StopReason, EmuError>
+    where
+        F: FnMut(&CPU<M>) -> Option<StopReason>,
+    {
+        let mut first = true;
 
-                // right bit shift
-                0x4A => self.lsr_acc(),
-                0x46 | 0x56 | 0x4E | 0x5E => self.lsr(&opcode.addressing_mode),
+        loop {
+            if !first && self.breakpoints.contains(&self.program_counter) {
+                return Ok(StopReason::Breakpoint(self.program_counter));
+            }
+            first = false;
 
-                // left bit shift
-                0x0A => self.asl_acc(),
-                0x06 | 0x16 | 0x0E | 0x1E => self.asl(&opcode.addressing_mode),
+            let step = self.step()?;
 
-                // Or operation
-                0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&opcode.addressing_mode)
-                }
+            if self.jammed {
+                return Ok(StopReason::Jam(step.address));
+            }
 
-                // Exclusive or
-                0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&opcode.addressing_mode)
-                }
+            if step.opcode.code == 0x00 {
+                return Ok(StopReason::Brk);
+            }
 
-                /* ------ BRANCH OPERATIONS ------ */
-                // Branch if flag set/not set crap
-                0xB0 => self.bcs(),
-                0xF0 => self.beq(),
-                0xD0 => self.bne(),
-                0x10 => self.bpl(),
-                0x30 => self.bmi(),
-                0x50 => self.bvc(),
-                0x70 => self.bvs(),
-                0x90 => self.bcc(),
-
-                // Absolute addressing
-                0x4C => {
-                    let mem_address = self.read_mem_u16(self.program_counter);
-                    self.program_counter = mem_address;
-                }
+            if let Some(reason) = stop(self) {
+                return Ok(reason);
+            }
+        }
+    }
 
-                // Indirect addressing
-                0x6C => {
-                    let mem_address = self.read_mem_u16(self.program_counter);
-                    //6502 bug mode with with page boundary:
-                    //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
-                    // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
-                    // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
-
-                    let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                        let lo = self.read_mem_u8(mem_address);
-                        let hi = self.read_mem_u8(mem_address & 0xFF00);
-                        (hi as u16) << 8 | (lo as u16)
-                    } else {
-                        self.read_mem_u16(mem_address)
-                    };
-
-                    self.program_counter = indirect_ref;
-                }
+    /// True after a JAM opcode locked the CPU up
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
 
-                // jump and return from subroutine
-                // 0x20 => self.jsr(&AddressingMode::Absolute),
-                0x20 => {
-                    self.push_stack_u16(self.program_counter + 2 - 1);
-                    let target_address = self.read_mem_u16(self.program_counter);
-                    self.program_counter = target_address;
-                }
+    /// Copies out the registers, this is what gets attached to an EmuError
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            accumulator: self.accumulator,
+            index_register_x: self.index_register_x,
+            index_register_y: self.index_register_y,
+            processor_status: self.processor_status.0,
+            current_cycle: self.current_cycle,
+            trace: trace::trace(self),
+        }
+    }
 
-                // return from subroutine
-                0x60 => {
-                    self.program_counter = self.read_stack_u16() + 1;
-                }
+    /// Fires a non maskable interrupt. It is serviced before the next instruction
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
 
-                /* ------ STATUS OPERATIONS ------ */
-                // clear flags
-                0x18 => self
-                    .processor_status
-                    .set_flag_false(ProcessorStatusFlags::CarryFlag),
-                0xD8 => self
-                    .processor_status
-                    .set_flag_false(ProcessorStatusFlags::DecimalMode),
-                0x58 => self
-                    .processor_status
-                    .set_flag_false(ProcessorStatusFlags::InterruptDisable),
-                0xB8 => self
-                    .processor_status
-                    .set_flag_false(ProcessorStatusFlags::Overflow),
-
-                // set flags
-                0x38 => self
-                    .processor_status
-                    .set_flag_true(ProcessorStatusFlags::CarryFlag),
-                0xF8 => self
-                    .processor_status
-                    .set_flag_true(ProcessorStatusFlags::DecimalMode),
-                0x78 => self
-                    .processor_status
-                    .set_flag_true(ProcessorStatusFlags::InterruptDisable),
-
-                /* ------ COMPARING INSTRUCTIONS ------ */
-                // bit
-                0x24 | 0x2C => self.bit(&opcode.addressing_mode),
-
-                // cmp
-                0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
-                    self.cmp(&opcode.addressing_mode)
-                }
+    /// Pulls the IRQ line low for the source, it stays asserted until released
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.irq_lines |= source as u8;
+    }
 
-                // cmx
-                0xE0 | 0xE4 | 0xEC => self.cpx(&opcode.addressing_mode),
+    /// Lets go of the IRQ line for the source
+    pub fn release_irq(&mut self, source: IrqSource) {
+        self.irq_lines &= !(source as u8);
+    }
 
-                // cmy
-                0xC0 | 0xC4 | 0xCC => self.cpy(&opcode.addressing_mode),
+    /// True while any source is holding the IRQ line
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_lines != 0
+    }
 
-                /* ------ TRANSFER INSTRUCTIONS ------ */
-                // transfer operations
-                0xAA => self.set_register_x(self.accumulator), // { self.index_register_x = self.accumulator; self.processor_status.update_zero_and_negative_flags(self.index_register_x); },
-                0xA8 => self.set_register_y(self.accumulator), //{ self.index_register_y = self.accumulator; self.processor_status.update_zero_and_negative_flags(self.index_register_y); },
-                0xBA => self.set_register_x(
-                    self.read_mem_u8(self.stack_pointer - self.stack_base.wrapping_sub(1) as u16),
-                ),
-                0x8A => self.set_register_a(self.index_register_x), //{ self.accumulator = self.index_register_x; self.processor_status.update_zero_and_negative_flags(self.accumulator); } ,
-                0x9A => self.set_register_y(
-                    self.read_mem_u8(self.stack_pointer - self.stack_base.wrapping_sub(1) as u16),
-                ), // self.index_register_y = self.read_mem_u8(self.stack_pointer - self.stack_base.wrapping_sub(1) as u16),
-                0x98 => self.set_register_a(self.index_register_y), //{ self.accumulator = self.index_register_y; self.processor_status.update_zero_and_negative_flags(self.accumulator); },
+    /// Services a pending NMI or IRQ at the instruction boundary.
+    /// NMI wins when both are waiting
+    fn poll_interrupts(&mut self) -> Option<InterruptType> {
+        let interrupt = if self.nmi_pending {
+            self.nmi_pending = false;
+            interrupt::NMI
+        } else if self.irq_pending {
+            self.irq_pending = false;
+            interrupt::IRQ
+        } else {
+            return None;
+        };
 
-                /* ------ INCREMENT AND DECREMENT INSTRUCTIONS ------ */
-                // INC
-                0xE6 | 0xF6 | 0xEE | 0xFE => self.inc(&opcode.addressing_mode),
-                0xE8 => self.set_register_x(self.index_register_x.wrapping_add(1)),
-                0xC8 => self.set_register_y(self.index_register_y.wrapping_add(1)),
+        // the CPU reads the next instruction twice and throws it away before pushing
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+        self.interrupt(interrupt, false);
 
-                // dec
-                0xC6 | 0xD6 | 0xCE | 0xDE => self.dec(&opcode.addressing_mode), // from point in memory
-                0xCA => self.set_register_x(self.index_register_x.wrapping_sub(1)),
-                0x88 => self.set_register_y(self.index_register_y.wrapping_sub(1)),
+        Some(interrupt.interrupt_type)
+    }
 
-                /* ------ STACK OPERATIONS ------ */
-                // push the accumulator onto the stack
-                0x48 => self.push_stack(self.accumulator),
+    /// Fetches, decodes and executes the instruction under the program counter
+    fn execute_instruction(&mut self) -> Result<&'static OpCode, EmuError> {
+        // CLI, SEI and PLP change the flag after the IRQ line is polled
+        // so the old value decides if the IRQ gets in after them
+        let interrupts_disabled = self
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::InterruptDisable);
 
-                0x08 => self.accumulator = self.pop_stack(),
+        let kind = match std::mem::take(&mut self.jumped_indirect) {
+            true => ReadKind::IndirectOpcode,
+            false => ReadKind::Opcode,
+        };
+        let code = self.read_as(self.program_counter, kind);
+        let opcode = match opcodes::lookup(code) {
+            Some(opcode) => opcode,
+            None => {
+                return Err(EmuError::UnknownOpcode {
+                    code,
+                    state: self.state(),
+                })
+            }
+        };
 
-                0x28 => self.processor_status.0 = self.pop_stack(),
+        if opcode.illegal && self.strict_opcodes {
+            return Err(EmuError::UnofficialOpcode {
+                code,
+                mnemonic: opcode.mnemonic,
+                state: self.state(),
+            });
+        }
 
-                /* ------ ROTATE OPERATIONS ------ */
-                0x2A => self.rol_acc(),
-                0x26 | 0x36 | 0x2E | 0x3E => self.rol(&opcode.addressing_mode),
+        // consume the read instruction and point to the next
+        self.program_counter = self.program_counter.wrapping_add(1);
 
-                0x6A => self.ror_acc(),
-                0x66 | 0x76 | 0x6E | 0x7E => self.ror(&opcode.addressing_mode),
+        // instructions without an operand still read the byte after the opcode.
+        // The operands of everything else are fetched as the instruction runs
+        if let AddressingMode::NoneAddressing | AddressingMode::Accumulator = opcode.addressing_mode
+        {
+            self.dummy_read(self.program_counter);
+        }
 
-                /* ------ STORE OPERATIONS ------ */
-                // sta
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => self.sta(&opcode.addressing_mode),
+        (Self::DISPATCH[code as usize])(self, &opcode.addressing_mode);
+        self.jumped_indirect = opcode.instruction == Instruction::JMP
+            && opcode.addressing_mode == AddressingMode::Indirect;
 
-                0x84 | 0x94 | 0x8C => self.sty(&opcode.addressing_mode),
+        self.poll_irq(opcode.instruction, interrupts_disabled);
 
-                0x86 | 0x96 | 0x8E => self.stx(&opcode.addressing_mode),
+        if let Some(mode) = self.invalid_mode.take() {
+            return Err(EmuError::InvalidAddressingMode {
+                mode,
+                state: self.state(),
+            });
+        }
 
-                /* ------ NO OPERATION ------ */
-                0xEA => continue,
+        if let Some(BusFault::RomWrite { addr, data }) = self.bus.take_fault() {
+            return Err(EmuError::RomWrite {
+                addr,
+                data,
+                state: self.state(),
+            });
+        }
 
-                /* ------ BREAK AND INTERRUPT OPERATIONS ------ */
-                0x40 => self.rti(),
+        Ok(opcode)
+    }
 
-                0x00 => {
-                    warn!("instruction brk is not implemented because we don't have interrupts");
-                    break;
-                }
-                _ => todo!(),
-            }
+    /// Samples the IRQ line at the end of an instruction
+    fn poll_irq(&mut self, instruction: Instruction, interrupts_disabled_before: bool) {
+        let interrupts_disabled = match instruction {
+            Instruction::CLI | Instruction::SEI | Instruction::PLP => interrupts_disabled_before,
+            _ => self
+                .processor_status
+                .has_flag_set(ProcessorStatusFlags::InterruptDisable),
+        };
 
-            // increment the program counter
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.bytes - 1) as u16;
-            }
-        }
+        self.irq_pending = self.irq_asserted() && !interrupts_disabled;
     }
 }
 
-impl CPU {
+impl<M: Mem> CPU<M> {
     fn push_stack(&mut self, data: u8) {
-        self.write_mem_u8(self.stack_pointer - self.stack_base as u16, data);
-        self.stack_base = self.stack_base.wrapping_add(1);
+        self.write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
     fn pop_stack(&mut self) -> u8 {
-        self.stack_base = self.stack_base.wrapping_sub(1);
-        let val = self.read_mem_u8(self.stack_pointer - self.stack_base as u16);
-        return val;
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.read(STACK + self.stack_pointer as u16)
+    }
+
+    /// The pulling instructions spend a cycle reading the top of the stack
+    /// before they move the stack pointer
+    fn dummy_read_stack(&mut self) {
+        self.dummy_read(STACK + self.stack_pointer as u16);
     }
 
     fn push_stack_u16(&mut self, data: u16) {
@@ -540,6 +752,16 @@ impl CPU {
         hi << 8 | lo
     }
 
+    /// Status pulled from the stack (PLP and RTI) ignores the break bit
+    /// and always has bit 5 set because it doesn't exist in the register
+    fn pull_processor_status(&mut self) {
+        self.processor_status.0 = self.pop_stack();
+        self.processor_status
+            .set_flag_false(ProcessorStatusFlags::BreakCommand);
+        self.processor_status
+            .set_flag_true(ProcessorStatusFlags::BreakCommand2);
+    }
+
     /// Helper function that loads field data into register A
     fn add_to_register_a(&mut self, data: u8) {
         let sum = self.accumulator as u16
@@ -599,60 +821,140 @@ impl CPU {
     /// Adds the contents of a memory location to the accumulator with the carry bit
     /// if it overflows then we set the carry bit
     fn adc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.read_mem_u8(addr);
+        let value = self.read_operand(mode);
 
-        self.add_to_register_a(value);
+        self.add_with_carry(value);
     }
 
     /// Subtract with carry
     /// subtract the contents of memory location to the accumulator together with
     /// the not of the carry bit
     fn sbc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.read_mem_u8(addr);
+        let value = self.read_operand(mode);
+
+        self.subtract_with_carry(value);
+    }
+
+    /// True when ADC and SBC should work on BCD digits
+    fn decimal_enabled(&self) -> bool {
+        self.decimal_mode
+            && self
+                .processor_status
+                .has_flag_set(ProcessorStatusFlags::DecimalMode)
+    }
+
+    fn add_with_carry(&mut self, data: u8) {
+        if self.decimal_enabled() {
+            self.add_decimal(data);
+        } else {
+            self.add_to_register_a(data);
+        }
+    }
+
+    fn subtract_with_carry(&mut self, data: u8) {
+        if self.decimal_enabled() {
+            self.subtract_decimal(data);
+        } else {
+            self.add_to_register_a(!data);
+        }
+    }
+
+    /// BCD addition the way the NMOS 6502 does it, following Bruce Clark's
+    /// "Decimal Mode" tutorial on 6502.org. Invalid BCD digits give the same garbage the chip does
+    fn add_decimal(&mut self, data: u8) {
+        let carry = self
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag) as i16;
+
+        let mut lo = (self.accumulator & 0x0F) as i16 + (data & 0x0F) as i16 + carry;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+
+        // N and V are taken before the high digit is adjusted, from a signed sum
+        let signed = (self.accumulator & 0xF0) as i8 as i16 + (data & 0xF0) as i8 as i16 + lo;
+
+        let mut sum = (self.accumulator & 0xF0) as i16 + (data & 0xF0) as i16 + lo;
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+
+        // and Z is taken from the plain binary sum
+        let binary = self
+            .accumulator
+            .wrapping_add(data)
+            .wrapping_add(carry as u8);
+
+        self.accumulator = sum as u8;
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::CarryFlag, sum >= 0x100);
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::ZeroFlag, binary == 0);
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::Negative, signed & 0x80 != 0);
+        self.processor_status.set_flag(
+            ProcessorStatusFlags::Overflow,
+            !(-128..=127).contains(&signed),
+        );
+    }
+
+    /// BCD subtraction on the NMOS 6502, the flags come out the same as in binary mode
+    fn subtract_decimal(&mut self, data: u8) {
+        let borrow = !self
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag) as i16;
+
+        let mut lo = (self.accumulator & 0x0F) as i16 - (data & 0x0F) as i16 - borrow;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0F) - 0x10;
+        }
+
+        let mut difference = (self.accumulator & 0xF0) as i16 - (data & 0xF0) as i16 + lo;
+        if difference < 0 {
+            difference -= 0x60;
+        }
 
-        self.add_to_register_a(((value as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        self.add_to_register_a(!data);
+        self.accumulator = difference as u8;
     }
 
     /// Loads a value into the a register
     fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.read_mem_u8(addr);
+        let value = self.read_operand(mode);
 
         self.set_register_a(value);
     }
 
     fn ldx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.read_mem_u8(addr);
+        let value = self.read_operand(mode);
 
         self.set_register_x(value);
     }
 
     fn ldy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.read_mem_u8(addr);
+        let value = self.read_operand(mode);
 
         self.set_register_y(value);
     }
 
     /// Logical and
     fn and(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
+        let val = self.read_operand(mode);
         self.set_register_a(self.accumulator & val);
     }
 
     /// Arithmetic shift left. the 7 bit is placed in the carry flag
-    fn asl(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
+    /// returns the value written back to memory
+    fn asl(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, val) = self.read_for_modify(mode);
         let res: u16 = (val as u16) << 1;
         self.processor_status
             .set_flag(ProcessorStatusFlags::CarryFlag, res > 0xFF);
+        self.processor_status
+            .update_zero_and_negative_flags(res as u8);
 
-        self.write_mem_u8(addr, res as u8);
+        self.write(addr, res as u8);
+        res as u8
     }
 
     /// Arithmetic shift on the accumulate register
@@ -664,13 +966,23 @@ impl CPU {
         self.set_register_a(res as u8);
     }
 
-    /// Helper function for the branch functions
-    fn branch(&mut self) {
-        let jump: i8 = self.read_mem_u8(self.program_counter) as i8;
-        let jump_addr = self
-            .program_counter
-            .wrapping_add(1)
-            .wrapping_add(jump as u16);
+    /// Helper function for the branch functions, the offset is always fetched.
+    /// A taken branch costs one more cycle, and another if it lands on a different page.
+    /// Both go on reading the instruction after the branch, the second one with the
+    /// high byte of the target not fixed up yet
+    fn branch(&mut self, taken: bool) {
+        let jump = self.fetch() as i8;
+        if !taken {
+            return;
+        }
+
+        let next_instruction = self.program_counter;
+        let jump_addr = next_instruction.wrapping_add(jump as u16);
+
+        self.dummy_read(next_instruction);
+        if next_instruction & 0xFF00 != jump_addr & 0xFF00 {
+            self.dummy_read((next_instruction & 0xFF00) | (jump_addr & 0x00FF));
+        }
 
         self.program_counter = jump_addr;
     }
@@ -678,230 +990,182 @@ impl CPU {
     /// Branch carry if clear - if the carry flag is clear add the displacement
     /// to the program counter to branch the program to a new location
     fn bcc(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::CarryFlag)
-            == false
-        {
-            self.branch();
-        }
+        self.branch(
+            !self
+                .processor_status
+                .has_flag_set(ProcessorStatusFlags::CarryFlag),
+        );
     }
 
     /// Branch carry if set - if the carry flag is set add the displacement
     /// to the program counter to branch the program to a new location
     fn bcs(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::CarryFlag)
-            == true
-        {
-            self.branch();
-        }
+        self.branch(
+            self.processor_status
+                .has_flag_set(ProcessorStatusFlags::CarryFlag),
+        );
     }
 
     /// Branch if zero flag is set
     fn beq(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::ZeroFlag)
-            == true
-        {
-            self.branch();
-        }
+        self.branch(
+            self.processor_status
+                .has_flag_set(ProcessorStatusFlags::ZeroFlag),
+        );
     }
 
     /// Branch if zero flag is not set
     fn bne(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::ZeroFlag)
-            == false
-        {
-            self.branch();
-        }
+        self.branch(
+            !self
+                .processor_status
+                .has_flag_set(ProcessorStatusFlags::ZeroFlag),
+        );
     }
 
     /// Branch if positive
     fn bpl(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::Negative)
-            == false
-        {
-            self.branch();
-        }
+        self.branch(
+            !self
+                .processor_status
+                .has_flag_set(ProcessorStatusFlags::Negative),
+        );
     }
 
     /// Branch if zero flag is not set
     fn bmi(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::Negative)
-            == true
-        {
-            self.branch();
-        }
+        self.branch(
+            self.processor_status
+                .has_flag_set(ProcessorStatusFlags::Negative),
+        );
     }
 
     /// Preforms the and operation with the accumulator register
-    /// and the data at the address and updates the cpu flags accordingly
+    /// and the data at the address and updates the cpu flags accordingly.
+    /// N and V are copied straight from bits 7 and 6 of the memory value
     fn bit(&mut self, mode: &AddressingMode) {
-        let addr: u16 = self.get_operand_address(mode);
-        let mem: u8 = self.read_mem_u8(addr);
+        let mem: u8 = self.read_operand(mode);
         let res: u8 = self.accumulator & mem;
 
-        if res == 0 {
-            self.processor_status
-                .set_flag_true(ProcessorStatusFlags::ZeroFlag);
-        }
-
         self.processor_status
-            .set_flag(ProcessorStatusFlags::Negative, (res >> 6 & 1) != 0); // bit 6
+            .set_flag(ProcessorStatusFlags::ZeroFlag, res == 0);
         self.processor_status
-            .set_flag(ProcessorStatusFlags::Overflow, (res >> 7 & 1) != 0); // bit 6
+            .set_flag(ProcessorStatusFlags::Negative, (mem >> 7 & 1) != 0); // bit 7
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::Overflow, (mem >> 6 & 1) != 0); // bit 6
     }
 
     /// Branch if overflow clear
     fn bvc(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::Overflow)
-            == false
-        {
-            let addr: u8 = self.read_mem_u8(self.program_counter); // gets the program counter
-            self.program_counter += addr as u16;
-        }
+        self.branch(
+            !self
+                .processor_status
+                .has_flag_set(ProcessorStatusFlags::Overflow),
+        );
     }
 
     /// Branch if overflow set
     fn bvs(&mut self) {
-        if self
-            .processor_status
-            .has_flag_set(ProcessorStatusFlags::Overflow)
-            == true
-        {
-            let addr: u8 = self.read_mem_u8(self.program_counter); // gets the program counter
-            self.program_counter += addr as u16;
-        }
+        self.branch(
+            self.processor_status
+                .has_flag_set(ProcessorStatusFlags::Overflow),
+        );
     }
 
     /// This is a helper function that sets the flags for a comparison operation between u8 a and u8 b
     /// field a is the source and field b is the integer you are comparing to
     fn set_compare_flags(&mut self, a: u8, b: u8) {
-        if a < b {
-            self.processor_status
-                .set_flag_false(ProcessorStatusFlags::ZeroFlag);
-            self.processor_status
-                .set_flag_false(ProcessorStatusFlags::CarryFlag);
-            self.processor_status
-                .set_flag(ProcessorStatusFlags::Negative, (a >> 7 & 1) != 0);
-        } else if a == b {
-            self.processor_status
-                .set_flag_true(ProcessorStatusFlags::ZeroFlag);
-            self.processor_status
-                .set_flag_true(ProcessorStatusFlags::CarryFlag);
-            self.processor_status
-                .set_flag_false(ProcessorStatusFlags::Negative);
-        } else {
-            self.processor_status
-                .set_flag_false(ProcessorStatusFlags::ZeroFlag);
-            self.processor_status
-                .set_flag_true(ProcessorStatusFlags::CarryFlag);
-            self.processor_status
-                .set_flag(ProcessorStatusFlags::Negative, (a >> 7 & 1) != 0);
-        }
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::CarryFlag, a >= b);
+        self.processor_status
+            .update_zero_and_negative_flags(a.wrapping_sub(b));
     }
 
     /// Compares the value stored in memory with the value in the accumulator
-    /// if A < mem it sets Z and C flags are zero, and N is the 7th bit of A
-    /// if A = mem then Z and C are ones
-    /// if A > mem then Z is zero and C is one, and N is the 7th bit of A
+    /// if A < mem then C is zero, if A >= mem then C is one
+    /// Z is set when they are equal, and N is the 7th bit of A - mem
     fn cmp(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
+        let val = self.read_operand(mode);
 
         self.set_compare_flags(self.accumulator, val);
     }
 
     /// same as cmp but compares the address to the x register
     fn cpx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
+        let val = self.read_operand(mode);
 
         self.set_compare_flags(self.index_register_x, val);
     }
 
     /// same as cmp but compares the address to the y register
     fn cpy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
+        let val = self.read_operand(mode);
 
         self.set_compare_flags(self.index_register_y, val);
     }
 
-    fn dec(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr).wrapping_sub(1);
+    fn dec(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, val) = self.read_for_modify(mode);
+        let val = val.wrapping_sub(1);
 
-        self.write_mem_u8(addr, val);
+        self.write(addr, val);
         self.processor_status.update_zero_and_negative_flags(val);
+        val
     }
 
-    fn inc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr).wrapping_add(1);
+    fn inc(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, val) = self.read_for_modify(mode);
+        let val = val.wrapping_add(1);
 
-        self.write_mem_u8(addr, val);
+        self.write(addr, val);
         self.processor_status.update_zero_and_negative_flags(val);
-    }
-
-    fn eor(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
-
-        let xor_res = self.accumulator ^ val;
-        self.accumulator = xor_res;
-        self.processor_status
-            .update_zero_and_negative_flags(xor_res);
-    }
-
-    /*
-    fn jmp(&mut self, mode: &AddressingMode) {
-      let addr = self.get_operand_address(mode);
-      self.program_counter = self.read_mem_u16(addr);
+        val
     }
 
     /// Pushes the address (minus one) of the return point on to the
-    /// stack and then sets the program counter to the target memory address
-    fn jsr(&mut self, mode: &AddressingMode) {
-      let addr = self.get_operand_address(mode);
+    /// stack and then sets the program counter to the target memory address.
+    /// The high byte of the target is only fetched after the pushes
+    fn jsr(&mut self) {
+        let lo = self.fetch() as u16;
+        self.dummy_read_stack();
 
-      let bytes = (self.program_counter + 2).to_le_bytes();
+        // the program counter is sitting on the high byte, the last byte of the instruction
+        self.push_stack_u16(self.program_counter);
 
-      self.push_stack(bytes[1]);
-      self.push_stack(bytes[0]);
-
-      self.program_counter = addr;
+        let hi = self.read_as(self.program_counter, ReadKind::Operand) as u16;
+        self.program_counter = hi << 8 | lo;
     }
 
     /// used at the end of a subroutine to return from the subroutine
     /// gets the return value from the stack
     fn rts(&mut self) {
-      self.program_counter = u16::from_le_bytes([self.pop_stack(), self.pop_stack()]);
+        self.dummy_read_stack();
+        let addr = self.read_stack_u16();
+
+        // the return address points at the last byte of the JSR, it is read and skipped
+        self.dummy_read(addr);
+        self.program_counter = addr.wrapping_add(1);
     }
-    */
 
-    /// preforms the logical shift right to the defined memory address
-    fn lsr(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
-        let res: u16 = (val as u16) >> 1;
-        self.write_mem_u8(addr, res as u8);
+    fn eor(&mut self, mode: &AddressingMode) {
+        let val = self.read_operand(mode);
 
+        let xor_res = self.accumulator ^ val;
+        self.accumulator = xor_res;
         self.processor_status
-            .set_flag(ProcessorStatusFlags::CarryFlag, res & 1 == 1);
+            .update_zero_and_negative_flags(xor_res);
+    }
+
+    /// preforms the logical shift right to the defined memory address
+    fn lsr(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, val) = self.read_for_modify(mode);
+        let res = val >> 1;
+        self.write(addr, res);
+
         self.processor_status
-            .update_zero_and_negative_flags(res as u8);
+            .set_flag(ProcessorStatusFlags::CarryFlag, val & 1 == 1);
+        self.processor_status.update_zero_and_negative_flags(res);
+        res
     }
 
     /// preforms the logical shift right to the accumulator register
@@ -914,14 +1178,13 @@ impl CPU {
             self.processor_status
                 .set_flag_false(ProcessorStatusFlags::CarryFlag);
         }
-        data = data >> 1;
+        data >>= 1;
         self.set_register_a(data)
     }
 
     /// Logical Inclusive OR preformed on the accumulator using the contents of the address
     fn ora(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let val = self.read_mem_u8(addr);
+        let val = self.read_operand(mode);
 
         self.accumulator |= val;
         self.processor_status
@@ -939,26 +1202,26 @@ impl CPU {
             val |= 1;
         }
         self.processor_status
-            .set_flag(ProcessorStatusFlags::CarryFlag, val >> 7 == 1);
-        self.accumulator = val;
+            .set_flag(ProcessorStatusFlags::CarryFlag, self.accumulator >> 7 == 1);
+        self.set_register_a(val);
     }
 
     /// shifts the bits at the memory location one place to the left
     /// bit 0 is filled with the value of the carry flag, the old bit 7 becomes the carry flag
-    fn rol(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mem_val = self.read_mem_u8(addr);
+    fn rol(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, mem_val) = self.read_for_modify(mode);
         let mut val = mem_val << 1;
         if self
             .processor_status
             .has_flag_set(ProcessorStatusFlags::CarryFlag)
-            == true
         {
-            val = val | 1;
+            val |= 1;
         }
         self.processor_status
             .set_flag(ProcessorStatusFlags::CarryFlag, mem_val >> 7 == 1);
-        self.write_mem_u8(addr, val);
+        self.processor_status.update_zero_and_negative_flags(val);
+        self.write(addr, val);
+        val
     }
 
     /// shifts the bits in the accumulator register one place to the right
@@ -969,66 +1232,81 @@ impl CPU {
         if self
             .processor_status
             .has_flag_set(ProcessorStatusFlags::CarryFlag)
-            == true
         {
             val |= 0b1000_0000;
         }
-        self.processor_status.set_flag(
-            ProcessorStatusFlags::CarryFlag,
-            (self.accumulator >> 0 & 1) != 0,
-        );
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::CarryFlag, self.accumulator & 1 != 0);
 
-        self.accumulator = val;
+        self.set_register_a(val);
     }
 
     /// shifts the bits at the memory location one place to the right
     /// bit 0 is filled with the value of the carry flag, the old bit 7 becomes the carry flag
-    fn ror(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mem_val = self.read_mem_u8(addr);
+    fn ror(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, mem_val) = self.read_for_modify(mode);
         let mut val = mem_val >> 1;
 
         if self
             .processor_status
             .has_flag_set(ProcessorStatusFlags::CarryFlag)
-            == true
         {
             val |= 0b1000_0000;
         }
-        self.processor_status.set_flag(
-            ProcessorStatusFlags::CarryFlag,
-            (self.accumulator >> 0 & 1) != 0,
-        );
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::CarryFlag, mem_val & 1 != 0);
+        self.processor_status.update_zero_and_negative_flags(val);
 
-        self.write_mem_u8(addr, val);
+        self.write(addr, val);
+        val
     }
 
     /// return from interrupt; this instruction is called at the end of an interrupt
     /// loop and pulls the processor flags from the stack and the program counter from the stack
     fn rti(&mut self) {
-        let cpu_flags = self.pop_stack();
-        let program_counter = u16::from_le_bytes([self.pop_stack(), self.pop_stack()]);
+        self.dummy_read_stack();
+        self.pull_processor_status();
+        self.program_counter = self.read_stack_u16();
+    }
+
+    /// Pushes a copy of the status flags on to the stack.
+    /// The pushed copy always has both break bits set
+    fn php(&mut self) {
+        let flags = self.processor_status.0
+            | ProcessorStatusFlags::BreakCommand as u8
+            | ProcessorStatusFlags::BreakCommand2 as u8;
+        self.push_stack(flags);
+    }
+
+    /// Pulls a byte from the stack into the accumulator
+    fn pla(&mut self) {
+        self.dummy_read_stack();
+        let data = self.pop_stack();
+        self.set_register_a(data);
+    }
 
-        self.processor_status.0 = cpu_flags;
-        self.program_counter = program_counter;
+    /// Pulls the status flags from the stack
+    fn plp(&mut self) {
+        self.dummy_read_stack();
+        self.pull_processor_status();
     }
 
     /// Loads memory into the a register
     fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        self.write_mem_u8(addr, self.accumulator);
+        let addr = self.operand_address(mode);
+        self.write(addr, self.accumulator);
     }
 
     /// Loads memory into the x register
     fn stx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        self.write_mem_u8(addr, self.index_register_x);
+        let addr = self.operand_address(mode);
+        self.write(addr, self.index_register_x);
     }
 
     /// Loads memory into the y register
     fn sty(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        self.write_mem_u8(addr, self.index_register_y);
+        let addr = self.operand_address(mode);
+        self.write(addr, self.index_register_y);
     }
 
     /*
@@ -1047,110 +1325,445 @@ impl CPU {
     /// Forces the generation of an interrupt and pushes the flags and current
     /// instruction to the stack. It sets program counter to u16 value in 0xFFFE
     fn brk(&mut self) {
-        /*
-        let bytes = self.program_counter.to_le_bytes();
+        // BRK has a padding byte after it, the return address skips over it
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        // an NMI that shows up during the BRK hijacks it and uses the NMI vector
+        let interrupt = match std::mem::take(&mut self.nmi_pending) {
+            true => interrupt::NMI,
+            false => interrupt::BRK,
+        };
+        self.interrupt(interrupt, true);
+    }
+
+    /// Pushes the program counter and the flags and jumps through the interrupt vector.
+    /// The pushed flags have the break bit set when a BRK started it, bit 5 is always set
+    fn interrupt(&mut self, interrupt: Interrupt, break_flag: bool) {
+        self.jumped_indirect = false;
+        self.push_stack_u16(self.program_counter);
+
+        let mut flags = self.processor_status.0 | ProcessorStatusFlags::BreakCommand2 as u8;
+        if break_flag {
+            flags |= ProcessorStatusFlags::BreakCommand as u8;
+        } else {
+            flags &= !(ProcessorStatusFlags::BreakCommand as u8);
+        }
+        self.push_stack(flags);
+
+        self.processor_status
+            .set_flag_true(ProcessorStatusFlags::InterruptDisable);
+
+        let lo = self.read(interrupt.vector_addr) as u16;
+        let hi = self.read(interrupt.vector_addr.wrapping_add(1)) as u16;
+        self.program_counter = hi << 8 | lo;
+    }
+}
+
+/// The unofficial opcodes, most of them are two official instructions glued together
+impl<M: Mem> CPU<M> {
+    /// The multi byte nops still read their operand, it just goes nowhere
+    fn nop_read(&mut self, mode: &AddressingMode) {
+        self.read_operand(mode);
+    }
+
+    /// Loads the same value into a and x
+    fn lax(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+
+        self.set_register_a(value);
+        self.index_register_x = value;
+    }
+
+    /// Stores a & x without touching the flags
+    fn sax(&mut self, mode: &AddressingMode) {
+        let addr = self.operand_address(mode);
+        self.write(addr, self.accumulator & self.index_register_x);
+    }
+
+    /// dec followed by cmp
+    fn dcp(&mut self, mode: &AddressingMode) {
+        let val = self.dec(mode);
+        self.set_compare_flags(self.accumulator, val);
+    }
+
+    /// inc followed by sbc
+    fn isb(&mut self, mode: &AddressingMode) {
+        let val = self.inc(mode);
+        self.subtract_with_carry(val);
+    }
+
+    /// asl followed by ora
+    fn slo(&mut self, mode: &AddressingMode) {
+        let val = self.asl(mode);
+        self.set_register_a(self.accumulator | val);
+    }
+
+    /// rol followed by and
+    fn rla(&mut self, mode: &AddressingMode) {
+        let val = self.rol(mode);
+        self.set_register_a(self.accumulator & val);
+    }
+
+    /// lsr followed by eor
+    fn sre(&mut self, mode: &AddressingMode) {
+        let val = self.lsr(mode);
+        self.set_register_a(self.accumulator ^ val);
+    }
+
+    /// ror followed by adc, the carry out of the rotate goes into the add
+    fn rra(&mut self, mode: &AddressingMode) {
+        let val = self.ror(mode);
+        self.add_with_carry(val);
+    }
+
+    /// and with the immediate, then bit 7 of the result is copied into the carry
+    fn anc(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.processor_status.set_flag(
+            ProcessorStatusFlags::CarryFlag,
+            self.processor_status
+                .has_flag_set(ProcessorStatusFlags::Negative),
+        );
+    }
+
+    /// and with the immediate followed by lsr on the accumulator
+    fn alr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.lsr_acc();
+    }
+
+    /// and with the immediate followed by ror on the accumulator.
+    /// The carry comes from bit 6 of the result and the overflow is bit 6 xor bit 5
+    fn arr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.ror_acc();
+
+        let result = self.accumulator;
+        self.processor_status
+            .set_flag(ProcessorStatusFlags::CarryFlag, result >> 6 & 1 != 0);
+        self.processor_status.set_flag(
+            ProcessorStatusFlags::Overflow,
+            (result >> 6 ^ result >> 5) & 1 != 0,
+        );
+    }
 
-        self.push_stack(bytes[1]);
-        self.push_stack(bytes[0]);
-        self.push_stack(self.processor_status.0);
+    /// x = (a & x) - immediate, the flags are set like cpx and the carry is not used
+    fn axs(&mut self, mode: &AddressingMode) {
+        let val = self.read_operand(mode);
+        let and = self.accumulator & self.index_register_x;
 
-        self.processor_status.set_flag_true(ProcessorStatusFlags::BreakCommand);
+        self.set_compare_flags(and, val);
+        self.index_register_x = and.wrapping_sub(val);
+    }
 
-        let interrupt_handler = self.read_mem_u16(0xFFFE);
+    /// JAM (KIL) locks up the CPU. The program counter stays on the opcode
+    /// and nothing but a reset gets it going again
+    fn jam(&mut self) {
+        self.jammed = true;
+        self.program_counter = self.program_counter.wrapping_sub(1);
+    }
+}
+
+/// Runs one instruction once its opcode has been fetched
+type Handler<M> = fn(&mut CPU<M>, &AddressingMode);
+
+impl<M: Mem> CPU<M> {
+    /// The handler for every opcode byte, built from the OPCODES table when this compiles.
+    /// Bytes that aren't in the table never get here, execute_instruction stops on them first
+    const DISPATCH: [Handler<M>; 256] = {
+        let mut table: [Handler<M>; 256] = [|_, _| unreachable!(); 256];
+        let mut code = 0;
+        while code < OPCODES.len() {
+            if let Some(opcode) = &OPCODES[code] {
+                table[code] = handler(opcode.instruction);
+            }
+            code += 1;
+        }
+        table
+    };
+}
+
+/// Which method carries out an instruction.
+/// The instructions with an accumulator form and the unofficial multi byte nops
+/// pick what to do from the addressing mode
+const fn handler<M: Mem>(instruction: Instruction) -> Handler<M> {
+    match instruction {
+        /* ------ LOAD INSTRUCTIONS ------ */
+        Instruction::LDA => |cpu, mode| cpu.lda(mode),
+        Instruction::LDX => |cpu, mode| cpu.ldx(mode),
+        Instruction::LDY => |cpu, mode| cpu.ldy(mode),
+
+        /* ------ ADDING + SUBTRACTING (WITH CARRY) INSTRUCTIONS ------ */
+        Instruction::ADC => |cpu, mode| cpu.adc(mode),
+        Instruction::SBC => |cpu, mode| cpu.sbc(mode),
+
+        /* ------ LOGICAL BIT OPERATIONS ------ */
+        Instruction::AND => |cpu, mode| cpu.and(mode),
+        Instruction::ORA => |cpu, mode| cpu.ora(mode),
+        Instruction::EOR => |cpu, mode| cpu.eor(mode),
+        Instruction::LSR => |cpu, mode| match mode {
+            AddressingMode::Accumulator => cpu.lsr_acc(),
+            _ => {
+                cpu.lsr(mode);
+            }
+        },
+        Instruction::ASL => |cpu, mode| match mode {
+            AddressingMode::Accumulator => cpu.asl_acc(),
+            _ => {
+                cpu.asl(mode);
+            }
+        },
+
+        /* ------ BRANCH OPERATIONS ------ */
+        Instruction::BCS => |cpu, _| cpu.bcs(),
+        Instruction::BEQ => |cpu, _| cpu.beq(),
+        Instruction::BNE => |cpu, _| cpu.bne(),
+        Instruction::BPL => |cpu, _| cpu.bpl(),
+        Instruction::BMI => |cpu, _| cpu.bmi(),
+        Instruction::BVC => |cpu, _| cpu.bvc(),
+        Instruction::BVS => |cpu, _| cpu.bvs(),
+        Instruction::BCC => |cpu, _| cpu.bcc(),
+
+        // operand_address does the page wrap bug for the indirect JMP
+        Instruction::JMP => |cpu, mode| cpu.program_counter = cpu.operand_address(mode),
+
+        // jump and return from subroutine
+        Instruction::JSR => |cpu, _| cpu.jsr(),
+        Instruction::RTS => |cpu, _| cpu.rts(),
+
+        /* ------ STATUS OPERATIONS ------ */
+        // clear flags
+        Instruction::CLC => |cpu, _| {
+            cpu.processor_status
+                .set_flag_false(ProcessorStatusFlags::CarryFlag)
+        },
+        Instruction::CLD => |cpu, _| {
+            cpu.processor_status
+                .set_flag_false(ProcessorStatusFlags::DecimalMode)
+        },
+        Instruction::CLI => |cpu, _| {
+            cpu.processor_status
+                .set_flag_false(ProcessorStatusFlags::InterruptDisable)
+        },
+        Instruction::CLV => |cpu, _| {
+            cpu.processor_status
+                .set_flag_false(ProcessorStatusFlags::Overflow)
+        },
 
-        self.program_counter = interrupt_handler;
-        */
+        // set flags
+        Instruction::SEC => |cpu, _| {
+            cpu.processor_status
+                .set_flag_true(ProcessorStatusFlags::CarryFlag)
+        },
+        Instruction::SED => |cpu, _| {
+            cpu.processor_status
+                .set_flag_true(ProcessorStatusFlags::DecimalMode)
+        },
+        Instruction::SEI => |cpu, _| {
+            cpu.processor_status
+                .set_flag_true(ProcessorStatusFlags::InterruptDisable)
+        },
+
+        /* ------ COMPARING INSTRUCTIONS ------ */
+        Instruction::BIT => |cpu, mode| cpu.bit(mode),
+        Instruction::CMP => |cpu, mode| cpu.cmp(mode),
+        Instruction::CPX => |cpu, mode| cpu.cpx(mode),
+        Instruction::CPY => |cpu, mode| cpu.cpy(mode),
+
+        /* ------ TRANSFER INSTRUCTIONS ------ */
+        Instruction::TAX => |cpu, _| cpu.set_register_x(cpu.accumulator),
+        Instruction::TAY => |cpu, _| cpu.set_register_y(cpu.accumulator),
+        Instruction::TSX => |cpu, _| cpu.set_register_x(cpu.stack_pointer),
+        Instruction::TXA => |cpu, _| cpu.set_register_a(cpu.index_register_x),
+        Instruction::TXS => |cpu, _| cpu.stack_pointer = cpu.index_register_x, // TXS does not touch the flags
+        Instruction::TYA => |cpu, _| cpu.set_register_a(cpu.index_register_y),
+
+        /* ------ INCREMENT AND DECREMENT INSTRUCTIONS ------ */
+        Instruction::INC => |cpu, mode| {
+            cpu.inc(mode);
+        },
+        Instruction::INX => |cpu, _| cpu.set_register_x(cpu.index_register_x.wrapping_add(1)),
+        Instruction::INY => |cpu, _| cpu.set_register_y(cpu.index_register_y.wrapping_add(1)),
+        Instruction::DEC => |cpu, mode| {
+            cpu.dec(mode);
+        },
+        Instruction::DEX => |cpu, _| cpu.set_register_x(cpu.index_register_x.wrapping_sub(1)),
+        Instruction::DEY => |cpu, _| cpu.set_register_y(cpu.index_register_y.wrapping_sub(1)),
+
+        /* ------ STACK OPERATIONS ------ */
+        Instruction::PHA => |cpu, _| cpu.push_stack(cpu.accumulator),
+        Instruction::PHP => |cpu, _| cpu.php(),
+        Instruction::PLA => |cpu, _| cpu.pla(),
+        Instruction::PLP => |cpu, _| cpu.plp(),
+
+        /* ------ ROTATE OPERATIONS ------ */
+        Instruction::ROL => |cpu, mode| match mode {
+            AddressingMode::Accumulator => cpu.rol_acc(),
+            _ => {
+                cpu.rol(mode);
+            }
+        },
+        Instruction::ROR => |cpu, mode| match mode {
+            AddressingMode::Accumulator => cpu.ror_acc(),
+            _ => {
+                cpu.ror(mode);
+            }
+        },
+
+        /* ------ STORE OPERATIONS ------ */
+        Instruction::STA => |cpu, mode| cpu.sta(mode),
+        Instruction::STX => |cpu, mode| cpu.stx(mode),
+        Instruction::STY => |cpu, mode| cpu.sty(mode),
+
+        /* ------ NO OPERATION ------ */
+        // the unofficial multi byte nops still read their operand
+        Instruction::NOP => |cpu, mode| match mode {
+            AddressingMode::NoneAddressing => {}
+            _ => cpu.nop_read(mode),
+        },
+
+        /* ------ BREAK AND INTERRUPT OPERATIONS ------ */
+        Instruction::RTI => |cpu, _| cpu.rti(),
+        Instruction::BRK => |cpu, _| cpu.brk(),
+
+        /* ------ UNOFFICIAL OPERATIONS ------ */
+        Instruction::LAX => |cpu, mode| cpu.lax(mode),
+        Instruction::SAX => |cpu, mode| cpu.sax(mode),
+        Instruction::DCP => |cpu, mode| cpu.dcp(mode),
+        Instruction::ISB => |cpu, mode| cpu.isb(mode),
+        Instruction::SLO => |cpu, mode| cpu.slo(mode),
+        Instruction::RLA => |cpu, mode| cpu.rla(mode),
+        Instruction::SRE => |cpu, mode| cpu.sre(mode),
+        Instruction::RRA => |cpu, mode| cpu.rra(mode),
+        Instruction::ANC => |cpu, mode| cpu.anc(mode),
+        Instruction::ALR => |cpu, mode| cpu.alr(mode),
+        Instruction::ARR => |cpu, mode| cpu.arr(mode),
+        Instruction::AXS => |cpu, mode| cpu.axs(mode),
+        Instruction::JAM => |cpu, _| cpu.jam(),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::cpu::asm;
+    use crate::cpu::memory::MemoryMap;
     use crate::cpu::rom::test::test_rom;
 
     use super::*;
 
+    /// Assembles a test program at 0x0600, where load_program puts it
+    fn assemble(source: &str) -> Vec<u8> {
+        asm::assemble(source, 0x0600).unwrap()
+    }
+
     #[test]
     fn cpu_new() {
-        let bus = Bus::new(test_rom());
-        let cpu = CPU::new(bus);
+        let cpu = CPU::new(MemoryMap::new());
         assert_eq!(cpu.index_register_x, 0);
     }
 
     #[test]
     fn cpu_lda_from_memory() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x10, 0xF1); // this should set off the negative flag
         cpu.write_mem_u8(0x11, 0x00); // this should set off the zero flag
 
         // test the negative flag
-        cpu.load_and_run_program(vec![0xa5, 0x10, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            LDA $10
+            BRK
+            ",
+        ))
+        .unwrap();
         assert_eq!(cpu.accumulator, 0xF1);
-        assert_eq!(
-            cpu.processor_status
-                .has_flag_set(ProcessorStatusFlags::Negative),
-            true
-        );
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::Negative));
 
         // test the positive flag
-        cpu.load_and_run_program(vec![0xa5, 0x11, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            LDA $11
+            BRK
+            ",
+        ))
+        .unwrap();
         assert_eq!(cpu.accumulator, 0x00);
-        assert_eq!(
-            cpu.processor_status
-                .has_flag_set(ProcessorStatusFlags::ZeroFlag),
-            true
-        );
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::ZeroFlag));
     }
 
     #[test]
     fn cpu_adc_from_memory() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x10, 0x05); // this should set off the zero flag
 
-        cpu.load_and_run_program(vec![0x65, 0x10, 0x65, 0x10]);
+        cpu.load_and_run_program(assemble(
+            "
+            ADC $10
+            ADC $10
+            ",
+        ))
+        .unwrap();
         assert_eq!(cpu.accumulator, 0x0A);
     }
 
     #[test]
     fn cpu_bit() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
-        cpu.write_mem_u8(0x11, 0b1011_1111); // this should set off the overflow flag
-        cpu.load_and_run_program(vec![0xA9, 0xFF, 0x24, 0x11]);
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.write_mem_u8(0x11, 0b0111_1111); // bit 6 should set off the overflow flag
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$FF
+            BIT $11
+            ",
+        ))
+        .unwrap();
 
-        assert_eq!(
-            cpu.processor_status
-                .has_flag_set(ProcessorStatusFlags::Overflow),
-            true
-        );
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::Overflow));
+        assert!(!cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::Negative));
     }
 
     #[test]
     fn cpu_clear_set_flag_instructions() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x11, 0x00);
-        cpu.load_and_run_program(vec![0x38, 0xF8, 0x18, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            SEC
+            SED
+            CLC
+            BRK
+            ",
+        ))
+        .unwrap();
 
-        assert_eq!(
-            cpu.processor_status
-                .has_flag_set(ProcessorStatusFlags::DecimalMode),
-            true
-        );
-        assert_eq!(
-            cpu.processor_status
-                .has_flag_set(ProcessorStatusFlags::CarryFlag),
-            false
-        );
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::DecimalMode));
+        assert!(!cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag));
     }
 
     #[test]
     fn cpu_compare_instructions() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x11, 0x00);
-        cpu.load_and_run_program(vec![0xA9, 0x81, 0xC9, 0x02, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$81
+            CMP #$01  ; $81 - $01 has bit 7 set
+            BRK
+            ",
+        ))
+        .unwrap();
 
         assert!(cpu
             .processor_status
@@ -1162,10 +1775,17 @@ mod tests {
 
     #[test]
     fn cpu_increment_decrement() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x11, 0x05);
-        cpu.load_and_run_program(vec![0xC6, 0x11, 0xC6, 0x11, 0xE6, 0x11, 0xE8]);
+        cpu.load_and_run_program(assemble(
+            "
+            DEC $11
+            DEC $11
+            INC $11
+            INX
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.read_mem_u8(0x11), 4);
         assert_eq!(cpu.index_register_x, 1);
@@ -1173,72 +1793,134 @@ mod tests {
 
     #[test]
     fn cpu_eor() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x11, 0x05);
-        cpu.load_and_run_program(vec![0xA9, 0b1111_1100, 0x49, 0b1111_1110, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #%11111100
+            EOR #%11111110
+            BRK
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.accumulator, 2);
     }
 
-    /*
     #[test]
     fn cpu_jmp() {
-      let mut cpu = CPU::new();
-      cpu.write_mem_u8(0x11, 0x05);
-      cpu.load_and_run_program(vec![0x4C, 0x00, 0x60, 0x00, 0x00, 0x00, 0xA9, 0x05, 0x00]);
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            JMP load
+            LDA #$01
+            BRK
+            load:
+            LDA #$05
+            BRK
+            ",
+        ))
+        .unwrap();
 
-      assert_eq!(cpu.accumulator, 5);
+        assert_eq!(cpu.accumulator, 5);
     }
-    */
 
     #[test]
     fn cpu_ora() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
-        cpu.load_and_run_program(vec![0xA9, 0b0000_0010, 0x4A]);
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #%00000010
+            LSR A
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.accumulator, 1);
     }
 
     #[test]
     fn cpu_acc_stack() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
-        cpu.load_and_run_program(vec![0xA9, 0x01, 0x48, 0xA9, 0x02, 0x48, 0x28]);
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$01
+            PHA
+            LDA #$02
+            PHA
+            PLP
+            ",
+        ))
+        .unwrap();
 
-        assert_eq!(
-            cpu.processor_status
-                .has_flag_set(ProcessorStatusFlags::ZeroFlag),
-            true
-        );
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::ZeroFlag));
     }
 
     #[test]
     fn cpu_rotate_instructions() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x02, 0xFF);
-        cpu.load_and_run_program(vec![0x26, 0x02, 0x66, 0x02]);
+        cpu.load_and_run_program(assemble(
+            "
+            ROL $02
+            ROR $02
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(0xFF, cpu.read_mem_u8(0x02));
     }
 
     #[test]
     fn cpu_transfer_operations() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
-        cpu.load_and_run_program(vec![0xA9, 0x05, 0x48, 0xBA, 0xAA]);
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$05
+            PHA
+            TSX
+            TAX
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.accumulator, 5);
         assert_eq!(cpu.index_register_x, 5);
     }
 
+    #[test]
+    fn cpu_stack_pointer_wraps_in_page_one() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.reset();
+        assert_eq!(cpu.stack_pointer, 0xFD);
+
+        cpu.load_and_run_program(assemble(
+            "
+            LDX #$00
+            TXS
+            LDA #$42
+            PHA
+            TSX
+            ",
+        ))
+        .unwrap();
+
+        assert_eq!(cpu.read_mem_u8(0x0100), 0x42);
+        assert_eq!(cpu.index_register_x, 0xFF);
+    }
+
     #[test]
     fn test_0xa9_lda_immediate_load_data() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
-        cpu.load_and_run_program(vec![0xa9, 0x05, 0x00]);
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$05
+            BRK
+            ",
+        ))
+        .unwrap();
         assert_eq!(cpu.accumulator, 5);
         assert!(cpu.processor_status.0 & 0b0000_0010 == 0b00);
         assert!(cpu.processor_status.0 & 0b1000_0000 == 0);
@@ -1246,41 +1928,1002 @@ mod tests {
 
     #[test]
     fn test_0xaa_tax_move_a_to_x() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.accumulator = 10;
-        cpu.load_and_run_program(vec![0xaa, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            TAX
+            BRK
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.index_register_x, 10)
     }
 
     #[test]
     fn test_5_ops_working_together() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
-        cpu.load_and_run_program(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$C0
+            TAX
+            INX
+            BRK
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.index_register_x, 0xc1)
     }
 
     #[test]
     fn test_inx_overflow() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.index_register_x = 0xFF;
-        cpu.load_and_run_program(vec![0xe8, 0xe8, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            INX
+            INX
+            BRK
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.index_register_x, 2)
     }
 
     #[test]
     fn test_lda_from_memory() {
-        let bus = Bus::new(test_rom());
-        let mut cpu = CPU::new(bus);
+        let mut cpu = CPU::new(MemoryMap::new());
         cpu.write_mem_u8(0x10, 0x55);
 
-        cpu.load_and_run_program(vec![0xa5, 0x10, 0x00]);
+        cpu.load_and_run_program(assemble(
+            "
+            LDA $10
+            BRK
+            ",
+        ))
+        .unwrap();
 
         assert_eq!(cpu.accumulator, 0x55);
     }
+
+    /// Every official 6502 opcode and the mnemonic it decodes to
+    #[rustfmt::skip]
+    const OFFICIAL_OPCODES: [(u8, &str); 151] = [
+        (0x69, "ADC"), (0x65, "ADC"), (0x75, "ADC"), (0x6D, "ADC"), (0x7D, "ADC"),
+        (0x79, "ADC"), (0x61, "ADC"), (0x71, "ADC"),
+        (0x29, "AND"), (0x25, "AND"), (0x35, "AND"), (0x2D, "AND"), (0x3D, "AND"),
+        (0x39, "AND"), (0x21, "AND"), (0x31, "AND"),
+        (0x0A, "ASL"), (0x06, "ASL"), (0x16, "ASL"), (0x0E, "ASL"), (0x1E, "ASL"),
+        (0x90, "BCC"),
+        (0xB0, "BCS"),
+        (0xF0, "BEQ"),
+        (0x24, "BIT"), (0x2C, "BIT"),
+        (0x30, "BMI"),
+        (0xD0, "BNE"),
+        (0x10, "BPL"),
+        (0x00, "BRK"),
+        (0x50, "BVC"),
+        (0x70, "BVS"),
+        (0x18, "CLC"),
+        (0xD8, "CLD"),
+        (0x58, "CLI"),
+        (0xB8, "CLV"),
+        (0xC9, "CMP"), (0xC5, "CMP"), (0xD5, "CMP"), (0xCD, "CMP"), (0xDD, "CMP"),
+        (0xD9, "CMP"), (0xC1, "CMP"), (0xD1, "CMP"),
+        (0xE0, "CPX"), (0xE4, "CPX"), (0xEC, "CPX"),
+        (0xC0, "CPY"), (0xC4, "CPY"), (0xCC, "CPY"),
+        (0xC6, "DEC"), (0xD6, "DEC"), (0xCE, "DEC"), (0xDE, "DEC"),
+        (0xCA, "DEX"),
+        (0x88, "DEY"),
+        (0x49, "EOR"), (0x45, "EOR"), (0x55, "EOR"), (0x4D, "EOR"), (0x5D, "EOR"),
+        (0x59, "EOR"), (0x41, "EOR"), (0x51, "EOR"),
+        (0xE6, "INC"), (0xF6, "INC"), (0xEE, "INC"), (0xFE, "INC"),
+        (0xE8, "INX"),
+        (0xC8, "INY"),
+        (0x4C, "JMP"), (0x6C, "JMP"),
+        (0x20, "JSR"),
+        (0xA9, "LDA"), (0xA5, "LDA"), (0xB5, "LDA"), (0xAD, "LDA"), (0xBD, "LDA"),
+        (0xB9, "LDA"), (0xA1, "LDA"), (0xB1, "LDA"),
+        (0xA2, "LDX"), (0xA6, "LDX"), (0xB6, "LDX"), (0xAE, "LDX"), (0xBE, "LDX"),
+        (0xA0, "LDY"), (0xA4, "LDY"), (0xB4, "LDY"), (0xAC, "LDY"), (0xBC, "LDY"),
+        (0x4A, "LSR"), (0x46, "LSR"), (0x56, "LSR"), (0x4E, "LSR"), (0x5E, "LSR"),
+        (0xEA, "NOP"),
+        (0x09, "ORA"), (0x05, "ORA"), (0x15, "ORA"), (0x0D, "ORA"), (0x1D, "ORA"),
+        (0x19, "ORA"), (0x01, "ORA"), (0x11, "ORA"),
+        (0x48, "PHA"),
+        (0x08, "PHP"),
+        (0x68, "PLA"),
+        (0x28, "PLP"),
+        (0x2A, "ROL"), (0x26, "ROL"), (0x36, "ROL"), (0x2E, "ROL"), (0x3E, "ROL"),
+        (0x6A, "ROR"), (0x66, "ROR"), (0x76, "ROR"), (0x6E, "ROR"), (0x7E, "ROR"),
+        (0x40, "RTI"),
+        (0x60, "RTS"),
+        (0xE9, "SBC"), (0xE5, "SBC"), (0xF5, "SBC"), (0xED, "SBC"), (0xFD, "SBC"),
+        (0xF9, "SBC"), (0xE1, "SBC"), (0xF1, "SBC"),
+        (0x38, "SEC"),
+        (0xF8, "SED"),
+        (0x78, "SEI"),
+        (0x85, "STA"), (0x95, "STA"), (0x8D, "STA"), (0x9D, "STA"), (0x99, "STA"),
+        (0x81, "STA"), (0x91, "STA"),
+        (0x86, "STX"), (0x96, "STX"), (0x8E, "STX"),
+        (0x84, "STY"), (0x94, "STY"), (0x8C, "STY"),
+        (0xAA, "TAX"),
+        (0xA8, "TAY"),
+        (0xBA, "TSX"),
+        (0x8A, "TXA"),
+        (0x9A, "TXS"),
+        (0x98, "TYA"),
+    ];
+
+    #[test]
+    fn official_opcodes_are_all_in_the_map() {
+        for (code, mnemonic) in OFFICIAL_OPCODES {
+            let opcode = opcodes::lookup(code).unwrap_or_else(|| {
+                panic!(
+                    "OpCode {:#04x} ({}) is missing from the OPCODES table",
+                    code, mnemonic
+                )
+            });
+            assert_eq!(
+                opcode.mnemonic, mnemonic,
+                "OpCode {:#04x} has the wrong mnemonic",
+                code
+            );
+        }
+
+        for opcode in OPCODES.iter().flatten() {
+            assert!(
+                opcode.illegal
+                    || OFFICIAL_OPCODES
+                        .iter()
+                        .any(|(official, _)| *official == opcode.code),
+                "OpCode {:#04x} is in the OPCODES table but is not official or marked illegal",
+                opcode.code
+            );
+        }
+    }
+
+    #[test]
+    fn every_opcode_in_the_map_is_dispatched() {
+        let mut missing: Vec<u8> = Vec::new();
+
+        for opcode in OPCODES.iter().flatten() {
+            // by its byte to cover every one in the table, the operand points into ram
+            // so every addressing mode lands somewhere writable
+            let mut cpu = CPU::new(MemoryMap::new());
+            cpu.load_program(vec![opcode.code, 0x10, 0x02]);
+            cpu.program_counter = 0x0600;
+
+            if cpu.step().is_err() {
+                missing.push(opcode.code);
+            }
+        }
+
+        missing.sort();
+        assert!(
+            missing.is_empty(),
+            "OpCodes not dispatched: {:02x?}",
+            missing
+        );
+    }
+
+    #[test]
+    fn official_opcodes_are_not_marked_illegal() {
+        for (code, _) in OFFICIAL_OPCODES {
+            assert!(
+                !opcodes::lookup(code).unwrap().illegal,
+                "OpCode {:#04x} is official",
+                code
+            );
+        }
+    }
+
+    /// Runs one opcode at `at` with its operand pointing at 0x0210 and the pointer at 0x10
+    /// holding 0x0210, returns the cycles it took and where the program counter ended up
+    fn run_opcode(opcode: &OpCode, at: u16, index: u8, status: u8) -> (u64, u16) {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.write_mem_u8(at, opcode.code);
+        cpu.write_mem_u16(at.wrapping_add(1), 0x0210);
+        cpu.write_mem_u16(0x10, 0x0210);
+        cpu.program_counter = at;
+        cpu.stack_pointer = 0xFD;
+        cpu.index_register_x = index;
+        cpu.index_register_y = index;
+        cpu.processor_status.0 = status;
+
+        let cycles = cpu.step().unwrap().cycles;
+        (cycles, cpu.program_counter)
+    }
+
+    #[test]
+    fn opcode_timing_matches_the_table() {
+        for opcode in OPCODES.iter().flatten() {
+            if opcode.addressing_mode == AddressingMode::Relative {
+                for status in [0x00, 0xFF] {
+                    let (cycles, pc) = run_opcode(opcode, 0x0600, 0, status);
+                    if pc == 0x0602 {
+                        assert_eq!(
+                            cycles, opcode.cycles as u64,
+                            "{:#04x} not taken",
+                            opcode.code
+                        );
+                        continue;
+                    }
+                    assert_eq!(
+                        cycles,
+                        (opcode.cycles + opcode.branch_penalty) as u64,
+                        "{:#04x} taken",
+                        opcode.code
+                    );
+
+                    // the offset is 0x10 so this lands on the next page
+                    let (cycles, _) = run_opcode(opcode, 0x06F0, 0, status);
+                    assert_eq!(
+                        cycles,
+                        (opcode.cycles + opcode.branch_penalty + opcode.page_cross_penalty) as u64,
+                        "{:#04x} taken to a new page",
+                        opcode.code
+                    );
+                }
+                continue;
+            }
+
+            let (cycles, _) = run_opcode(opcode, 0x0600, 0, 0x24);
+            assert_eq!(cycles, opcode.cycles as u64, "{:#04x}", opcode.code);
+
+            // 0x0210 + 0xFF crosses into the next page
+            let (cycles, _) = run_opcode(opcode, 0x0600, 0xFF, 0x24);
+            assert_eq!(
+                cycles,
+                (opcode.cycles + opcode.page_cross_penalty) as u64,
+                "{:#04x} crossing a page",
+                opcode.code
+            );
+        }
+    }
+
+    #[test]
+    fn opcodes_only_change_the_flags_they_write() {
+        for opcode in OPCODES.iter().flatten() {
+            for status in [0x00, 0x24, 0xFF] {
+                for index in [0x00, 0x80, 0xFF] {
+                    let mut cpu = CPU::new(MemoryMap::new());
+                    cpu.write_mem_u8(0x0600, opcode.code);
+                    cpu.write_mem_u16(0x0601, 0x0210);
+                    cpu.write_mem_u16(0x10, 0x0210);
+                    cpu.write_mem_u8(0x0210, index);
+                    cpu.program_counter = 0x0600;
+                    cpu.stack_pointer = 0xFD;
+                    cpu.accumulator = index ^ 0x5A;
+                    cpu.index_register_x = index;
+                    cpu.index_register_y = index;
+                    cpu.processor_status.0 = status;
+
+                    cpu.step().unwrap();
+                    let changed = (status ^ cpu.processor_status.0) & opcodes::ALL_FLAGS;
+                    assert_eq!(
+                        changed & !opcode.flags_written,
+                        0,
+                        "{:#04x} ({}) changed {:#010b}",
+                        opcode.code,
+                        opcode.mnemonic,
+                        changed
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn to_string_with_memory_renders_the_operand() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_program(assemble(
+            "
+            LDA $0200,X
+            ",
+        ));
+        cpu.program_counter = 0x0600;
+        cpu.index_register_x = 1;
+        cpu.write_mem_u8(0x0201, 0x05);
+
+        let opcode = opcodes::lookup(0xBD).unwrap();
+        assert_eq!(opcode.access, opcodes::Access::Read);
+        assert_eq!(
+            opcode.to_string_with_memory(&cpu),
+            "LDA $0200,X @ 0201 = 05"
+        );
+
+        cpu.load_program(assemble(
+            "
+            JSR $0200
+            ",
+        ));
+        let opcode = opcodes::lookup(0x20).unwrap();
+        assert_eq!(opcode.access, opcodes::Access::Jump);
+        assert_eq!(opcode.to_string_with_memory(&cpu), "JSR $0200");
+    }
+
+    #[test]
+    fn cpu_lax_sax() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.write_mem_u8(0x10, 0x8F);
+        cpu.load_and_run_program(assemble(
+            "
+            LAX $10
+            LDA #$F0
+            SAX $11
+            BRK
+            ",
+        ))
+        .unwrap();
+
+        assert_eq!(cpu.index_register_x, 0x8F);
+        assert_eq!(cpu.read_mem_u8(0x11), 0x80);
+    }
+
+    #[test]
+    fn cpu_dcp_isb() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.write_mem_u8(0x10, 0x06);
+        cpu.write_mem_u8(0x11, 0x01);
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$05
+            DCP $10   ; mem becomes 5, equal to a
+            SEC
+            ISB $11   ; a = 5 - 2
+            BRK
+            ",
+        ))
+        .unwrap();
+
+        assert_eq!(cpu.read_mem_u8(0x10), 0x05);
+        assert_eq!(cpu.read_mem_u8(0x11), 0x02);
+        assert_eq!(cpu.accumulator, 0x03);
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag));
+    }
+
+    #[test]
+    fn cpu_slo_sre() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.write_mem_u8(0x10, 0x81);
+        cpu.write_mem_u8(0x11, 0x03);
+        cpu.load_and_run_program(assemble(
+            "
+            LDA #$01
+            SLO $10   ; mem = $02, a = $03
+            SRE $11   ; mem = $01, a = $02
+            BRK
+            ",
+        ))
+        .unwrap();
+
+        assert_eq!(cpu.read_mem_u8(0x10), 0x02);
+        assert_eq!(cpu.read_mem_u8(0x11), 0x01);
+        assert_eq!(cpu.accumulator, 0x02);
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag));
+    }
+
+    #[test]
+    fn cpu_arr() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            SEC
+            LDA #$FF
+            ARR #$C0  ; a = $E0, bit 6 set and bit 5 set
+            BRK
+            ",
+        ))
+        .unwrap();
+
+        assert_eq!(cpu.accumulator, 0xE0);
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag));
+        assert!(!cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::Overflow));
+    }
+
+    #[test]
+    fn strict_mode_rejects_unofficial_opcodes() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.strict_opcodes = true;
+        cpu.load_program(assemble("LAX $10"));
+        cpu.program_counter = 0x0600;
+
+        assert!(matches!(
+            cpu.step(),
+            Err(EmuError::UnofficialOpcode { code: 0xA7, .. })
+        ));
+    }
+
+    #[test]
+    fn cpu_brk_pushes_state_and_jumps_through_vector() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        // CLC so the pushed flags are just the break bits and the reset flags
+        cpu.load_and_run_program(assemble(
+            "
+            CLC
+            BRK
+            ",
+        ))
+        .unwrap();
+
+        assert_eq!(cpu.program_counter, cpu.read_mem_u16(0xFFFE));
+        // return address skips the padding byte after the BRK at 0x0601
+        assert_eq!(cpu.read_mem_u8(0x01FD), 0x06);
+        assert_eq!(cpu.read_mem_u8(0x01FC), 0x03);
+        assert_eq!(cpu.read_mem_u8(0x01FB), 0b0011_0100);
+        assert_eq!(cpu.stack_pointer, 0xFA);
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::InterruptDisable));
+    }
+
+    #[test]
+    fn cpu_nmi_ignores_interrupt_disable() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        cpu.trigger_nmi();
+        cpu.poll_interrupts();
+
+        assert_eq!(cpu.program_counter, cpu.read_mem_u16(0xFFFA));
+        assert_eq!(cpu.read_mem_u8(0x01FD), 0x06);
+        assert_eq!(cpu.read_mem_u8(0x01FC), 0x00);
+        // break bit clear, bit 5 set
+        assert_eq!(cpu.read_mem_u8(0x01FB), 0b0010_0100);
+        assert_eq!(cpu.current_cycle, 7 + 7);
+    }
+
+    #[test]
+    fn cpu_nmi_hijacks_a_brk() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.write_mem_u16(0xFFFA, 0x9000);
+        cpu.reset();
+        // just past the BRK opcode, with the NMI coming in while it runs
+        cpu.program_counter = 0x0601;
+        cpu.nmi_pending = true;
+
+        cpu.brk();
+
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert!(!cpu.nmi_pending);
+        assert_eq!(cpu.read_mem_u8(0x01FC), 0x02);
+        // the pushed flags still say it was a BRK
+        assert_eq!(cpu.read_mem_u8(0x01FB), 0b0011_0100);
+    }
+
+    #[test]
+    fn cpu_irq_waits_one_instruction_after_cli() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_program(assemble(
+            "
+            CLI
+            NOP
+            NOP
+            ",
+        ));
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu.assert_irq(IrqSource::External);
+
+        cpu.step().unwrap(); // CLI
+        assert!(!cpu.irq_pending);
+
+        cpu.step().unwrap(); // NOP
+        assert!(cpu.irq_pending);
+
+        cpu.poll_interrupts();
+        assert_eq!(cpu.program_counter, cpu.read_mem_u16(0xFFFE));
+        assert_eq!(cpu.read_mem_u8(0x01FC), 0x02);
+        assert!(cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::InterruptDisable));
+    }
+
+    #[test]
+    fn cpu_irq_line_is_shared() {
+        let mut cpu = CPU::new(MemoryMap::new());
+
+        cpu.assert_irq(IrqSource::Mapper);
+        cpu.assert_irq(IrqSource::FrameCounter);
+        cpu.release_irq(IrqSource::Mapper);
+        assert!(cpu.irq_asserted());
+
+        cpu.release_irq(IrqSource::FrameCounter);
+        assert!(!cpu.irq_asserted());
+    }
+
+    /// Runs the program one instruction at a time and returns how many cycles each one took
+    fn instruction_cycles(
+        cpu: &mut CPU<MemoryMap>,
+        program: Vec<u8>,
+        instructions: usize,
+    ) -> Vec<u64> {
+        cpu.load_program(program);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        (0..instructions)
+            .map(|_| cpu.step().unwrap().cycles)
+            .collect()
+    }
+
+    #[test]
+    fn cpu_step_describes_the_instruction() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_program(assemble(
+            "
+            LDA #$01
+            NOP
+            ",
+        ));
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        let step = cpu.step().unwrap();
+
+        assert_eq!(step.address, 0x0600);
+        assert_eq!(step.opcode.mnemonic, "LDA");
+        assert_eq!(step.interrupt, None);
+        assert_eq!(step.cycles, 2);
+        assert_eq!(cpu.program_counter, 0x0602);
+    }
+
+    /// Starts the program at 0x0600 with nothing but NOPs behind it
+    fn cpu_with_nops() -> CPU<MemoryMap> {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_program(vec![0xEA; 16]);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu
+    }
+
+    #[test]
+    fn cpu_run_for_cycles_stops_on_budget() {
+        let mut cpu = cpu_with_nops();
+
+        assert_eq!(cpu.run_for_cycles(5), Ok(StopReason::CycleBudget));
+        assert_eq!(cpu.current_cycle, 7 + 6);
+        assert_eq!(cpu.program_counter, 0x0603);
+    }
+
+    #[test]
+    fn cpu_run_stops_at_breakpoint_and_resumes() {
+        let mut cpu = cpu_with_nops();
+        cpu.breakpoints.insert(0x0603);
+
+        assert_eq!(cpu.run_for_cycles(100), Ok(StopReason::Breakpoint(0x0603)));
+        assert_eq!(cpu.program_counter, 0x0603);
+
+        let reason = cpu.run_until(|cpu| cpu.program_counter == 0x0605);
+        assert_eq!(reason, Ok(StopReason::Condition));
+        assert_eq!(cpu.program_counter, 0x0605);
+    }
+
+    #[test]
+    fn cpu_run_stops_on_brk() {
+        let mut cpu = cpu_with_nops();
+        cpu.write_mem_u8(0x0602, 0x00);
+
+        assert_eq!(cpu.run_for_cycles(100), Ok(StopReason::Brk));
+        assert_eq!(cpu.program_counter, cpu.read_mem_u16(0xFFFE));
+    }
+
+    #[test]
+    fn cpu_run_frame() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_program(assemble(
+            "
+            JMP $0600
+            ",
+        ));
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        assert_eq!(cpu.run_frame(), Ok(StopReason::FrameComplete));
+        // stops on the JMP that crossed into the next frame
+        assert_eq!(trace::ppu_frame(cpu.current_cycle), 1);
+        assert_eq!(trace::ppu_frame(cpu.current_cycle - 3), 0);
+    }
+
+    #[test]
+    fn cpu_unknown_opcode_is_an_error() {
+        let mut cpu = cpu_with_nops();
+        // ANE is too unstable to emulate
+        cpu.write_mem_u8(0x0601, 0x8B);
+
+        cpu.step().unwrap();
+        match cpu.step() {
+            Err(EmuError::UnknownOpcode { code, state }) => {
+                assert_eq!(code, 0x8B);
+                assert_eq!(state.program_counter, 0x0601);
+                assert!(state.trace.starts_with("0601  8B"));
+            }
+            result => panic!("expected an unknown opcode, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn cpu_decimal_mode() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.decimal_mode = true;
+        cpu.load_and_run_program(assemble(
+            "
+            SED
+            CLC
+            LDA #$15
+            ADC #$27
+            ",
+        ))
+        .unwrap();
+        assert_eq!(cpu.accumulator, 0x42);
+        assert!(!cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag));
+
+        cpu.load_and_run_program(assemble(
+            "
+            SED
+            SEC
+            LDA #$00
+            SBC #$01
+            ",
+        ))
+        .unwrap();
+        assert_eq!(cpu.accumulator, 0x99);
+        assert!(!cpu
+            .processor_status
+            .has_flag_set(ProcessorStatusFlags::CarryFlag));
+    }
+
+    #[test]
+    fn cpu_ignores_decimal_flag_like_the_2a03() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_and_run_program(assemble(
+            "
+            SED
+            CLC
+            LDA #$15
+            ADC #$27
+            ",
+        ))
+        .unwrap();
+        assert_eq!(cpu.accumulator, 0x3C);
+    }
+
+    #[test]
+    fn cpu_runs_on_flat_memory() {
+        let mut memory = MemoryMap::new();
+        let program = asm::assemble(
+            "
+            LDA #$42
+            STA $C000
+            BRK
+            ",
+            0x8000,
+        )
+        .unwrap();
+        memory.memory[0x8000..0x8000 + program.len()].copy_from_slice(&program);
+        memory.write_mem_u16(0xFFFC, 0x8000);
+        memory.write_mem_u16(0xFFFE, 0x9000);
+
+        let mut cpu = CPU::new(memory);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8000);
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.read_mem_u8(0xC000), 0x42);
+        assert_eq!(cpu.program_counter, 0x9000);
+    }
+
+    #[test]
+    fn cpu_rom_write_is_an_error() {
+        let mut cpu = CPU::new(Bus::new(test_rom()));
+        cpu.load_program(assemble(
+            "
+            STA $8000
+            ",
+        ));
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        match cpu.step() {
+            Err(EmuError::RomWrite { addr, state, .. }) => {
+                assert_eq!(addr, 0x8000);
+                assert_eq!(state.program_counter, 0x0603);
+            }
+            result => panic!("expected a rom write, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn cpu_jam_halts_until_reset() {
+        let mut cpu = cpu_with_nops();
+        cpu.write_mem_u8(0x0602, 0x02);
+
+        assert_eq!(cpu.run_for_cycles(100), Ok(StopReason::Jam(0x0602)));
+        assert!(cpu.jammed());
+        assert_eq!(cpu.program_counter, 0x0602);
+        assert!(matches!(cpu.step(), Err(EmuError::Jammed { .. })));
+
+        cpu.reset();
+        assert!(!cpu.jammed());
+    }
+
+    #[test]
+    fn cpu_reset_takes_seven_cycles() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.reset();
+
+        assert_eq!(cpu.current_cycle, 7);
+    }
+
+    #[test]
+    fn cpu_page_cross_penalty_only_on_reads() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.write_mem_u8(0x10, 0xFF);
+        cpu.write_mem_u8(0x11, 0x02);
+
+        let cycles = instruction_cycles(
+            &mut cpu,
+            assemble(
+                "
+                LDX #$01
+                LDA $02FF,X  ; crosses
+                LDA $0200,X  ; doesn't
+                STA $02FF,X
+                LDY #$01
+                LDA ($10),Y  ; crosses
+                INC $02FF,X
+                ",
+            ),
+            7,
+        );
+
+        assert_eq!(cycles, vec![2, 5, 4, 5, 2, 6, 7]);
+    }
+
+    #[test]
+    fn cpu_branch_penalties() {
+        let mut cpu = CPU::new(MemoryMap::new());
+
+        let cycles = instruction_cycles(
+            &mut cpu,
+            assemble(
+                "
+                SEC
+                BCC $0613  ; not taken
+                BCS $0605  ; taken to the next instruction
+                BCS $05F7  ; taken back into page 0x05
+                ",
+            ),
+            4,
+        );
+
+        assert_eq!(cycles, vec![2, 2, 3, 4]);
+        assert_eq!(cpu.program_counter, 0x05F7);
+    }
+
+    /// Flat memory that keeps a log of every access and counts the ticks
+    struct RecordingMemory {
+        memory: MemoryMap,
+        accesses: std::cell::RefCell<Vec<(u16, u8, bool)>>,
+        ticks: u64,
+    }
+
+    impl Mem for RecordingMemory {
+        fn read_mem_u8(&self, addr: u16) -> u8 {
+            let data = self.memory.read_mem_u8(addr);
+            self.accesses.borrow_mut().push((addr, data, false));
+            data
+        }
+
+        fn write_mem_u8(&mut self, addr: u16, data: u8) {
+            self.accesses.borrow_mut().push((addr, data, true));
+            self.memory.write_mem_u8(addr, data);
+        }
+
+        fn tick(&mut self) {
+            self.ticks += 1;
+        }
+    }
+
+    /// Runs one instruction at 0x0600 and returns the bus accesses it made.
+    /// Checks the bus got ticked once for every cycle on the way
+    fn bus_accesses(
+        program: Vec<u8>,
+        setup: impl FnOnce(&mut CPU<RecordingMemory>),
+    ) -> Vec<(u16, u8, bool)> {
+        step_bus(program, setup).0
+    }
+
+    fn step_bus(
+        program: Vec<u8>,
+        setup: impl FnOnce(&mut CPU<RecordingMemory>),
+    ) -> (Vec<(u16, u8, bool)>, u64) {
+        let mut cpu = CPU::new(RecordingMemory {
+            memory: MemoryMap::new(),
+            accesses: std::cell::RefCell::new(vec![]),
+            ticks: 0,
+        });
+        cpu.load_program(program);
+        cpu.program_counter = 0x0600;
+        cpu.stack_pointer = 0xFD;
+        setup(&mut cpu);
+        cpu.bus.accesses.borrow_mut().clear();
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.cycles, cpu.bus.ticks);
+
+        let accesses = cpu.bus.accesses.borrow().clone();
+        (accesses, step.cycles)
+    }
+
+    #[test]
+    #[cfg(not(feature = "fast-cpu"))]
+    fn cpu_read_modify_write_writes_twice() {
+        let accesses = bus_accesses(assemble("INC $02FF,X"), |cpu| {
+            cpu.index_register_x = 1;
+            cpu.write_mem_u8(0x0300, 0x41);
+        });
+
+        assert_eq!(
+            accesses,
+            vec![
+                (0x0600, 0xFE, false),
+                (0x0601, 0xFF, false),
+                (0x0602, 0x02, false),
+                // the high byte isn't fixed up yet
+                (0x0200, 0x00, false),
+                (0x0300, 0x41, false),
+                (0x0300, 0x41, true),
+                (0x0300, 0x42, true),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "fast-cpu"))]
+    fn cpu_taken_branch_reads_across_the_page() {
+        // from near the end of the page as far forward as a branch goes
+        let program = assemble(
+            "
+            .org $06F0
+            BNE $0771
+            ",
+        );
+        let accesses = bus_accesses(program, |cpu| cpu.program_counter = 0x06F0);
+
+        assert_eq!(
+            accesses,
+            vec![
+                (0x06F0, 0xD0, false),
+                (0x06F1, 0x7F, false),
+                (0x06F2, 0x00, false),
+                (0x0671, 0x00, false),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "fast-cpu"))]
+    fn cpu_jsr_and_rts_bus_cycles() {
+        let accesses = bus_accesses(assemble("JSR $0700"), |_| {});
+        assert_eq!(
+            accesses,
+            vec![
+                (0x0600, 0x20, false),
+                (0x0601, 0x00, false),
+                (0x01FD, 0x00, false),
+                (0x01FD, 0x06, true),
+                (0x01FC, 0x02, true),
+                (0x0602, 0x07, false),
+            ]
+        );
+
+        // and back to 0x0603
+        let accesses = bus_accesses(assemble("RTS"), |cpu| {
+            cpu.stack_pointer = 0xFB;
+            cpu.write_mem_u8(0x01FC, 0x02);
+            cpu.write_mem_u8(0x01FD, 0x06);
+        });
+        assert_eq!(
+            accesses,
+            vec![
+                (0x0600, 0x60, false),
+                (0x0601, 0x00, false),
+                (0x01FB, 0x00, false),
+                (0x01FC, 0x02, false),
+                (0x01FD, 0x06, false),
+                (0x0602, 0x00, false),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "fast-cpu"))]
+    fn cpu_reset_reads_the_stack_instead_of_pushing() {
+        let mut cpu = CPU::new(RecordingMemory {
+            memory: MemoryMap::new(),
+            accesses: std::cell::RefCell::new(vec![]),
+            ticks: 0,
+        });
+        cpu.write_mem_u8(0xFFFC, 0x00);
+        cpu.write_mem_u8(0xFFFD, 0x80);
+        cpu.program_counter = 0x0600;
+        cpu.bus.accesses.borrow_mut().clear();
+
+        cpu.reset();
+
+        assert_eq!(
+            *cpu.bus.accesses.borrow(),
+            vec![
+                (0x0600, 0x00, false),
+                (0x0600, 0x00, false),
+                (0x0100, 0x00, false),
+                (0x01FF, 0x00, false),
+                (0x01FE, 0x00, false),
+                (0xFFFC, 0x00, false),
+                (0xFFFD, 0x80, false),
+            ]
+        );
+        assert_eq!(cpu.bus.ticks, 7);
+        assert_eq!(cpu.current_cycle, 7);
+        assert_eq!(cpu.stack_pointer, 0xFD);
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    #[test]
+    fn cpu_ticks_the_bus_every_cycle() {
+        for source in ["PHA", "PLA", "ASL A", "NOP", "LDA ($10,X)"] {
+            let program = assemble(source);
+            let expected = opcodes::lookup(program[0]).unwrap().cycles as u64;
+            let (accesses, cycles) = step_bus(program, |_| {});
+            assert_eq!(cycles, expected);
+            if cfg!(not(feature = "fast-cpu")) {
+                assert_eq!(accesses.len() as u64, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn cpu_snapshot_rewinds_the_machine() {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_program(assemble("loop: INC $10\n LDX $10\n JMP loop"));
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu.step().unwrap();
+        cpu.trigger_nmi();
+
+        let saved = cpu.save_state();
+        let trace: Vec<String> = (0..4)
+            .map(|_| {
+                cpu.step().unwrap();
+                trace::trace(&cpu)
+            })
+            .collect();
+
+        // the pending NMI and the memory come back with the registers
+        cpu.load_state(&saved);
+        assert_eq!(cpu.read_mem_u8(0x10), 1);
+        let again: Vec<String> = (0..4)
+            .map(|_| {
+                cpu.step().unwrap();
+                trace::trace(&cpu)
+            })
+            .collect();
+        assert_eq!(trace, again);
+    }
 }
@@ -0,0 +1,1247 @@
+//! The command line debugger behind `--debug`.
+//!
+//! The CPU runs on a `WatchedBus` wrapped around the real one, so watchpoints see every
+//! read and write the CPU puts on the bus, dummy accesses included, and not just the
+//! program counter. Memory the debugger looks at itself for a hexdump or a trace is not
+//! reported, those reads don't come with a bus cycle.
+//!
+//! Both kinds can carry a condition in the language from `expr`, and can log a line
+//! instead of stopping, e.g. `b c123 if a == 0 && [$0300] > 5 log X is {x}`
+//!
+//! The program can also be run backwards, `history` keeps the checkpoints for that.
+//! Going back doesn't count hits or log anything, the instructions already did that
+//! the first time round
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::cpu::cpu::CPU;
+use crate::cpu::error::EmuError;
+use crate::cpu::expr::{Expr, Template};
+use crate::cpu::history::History;
+use crate::cpu::memory::{BusFault, Mem, ReadKind, Snapshot};
+use crate::cpu::opcodes::{self, Instruction};
+use crate::cpu::step::Step;
+use crate::cpu::symbols;
+use crate::cpu::trace;
+
+/// Bytes shown by `x` when no length is given
+const DEFAULT_DUMP: u16 = 0x40;
+
+/// Instructions between the checkpoints stepping back starts from
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// How many checkpoints are kept, about a hundred frames of a NES game
+const CHECKPOINTS: usize = 1000;
+
+const HELP: &str = "\
+s, step [n]            run one instruction, or n of them
+n, next                like step but runs a JSR through to its return
+o, out                 run until the current subroutine returns
+c, continue            run until a breakpoint or a watchpoint
+rs, rstep [n]          step back one instruction, or n of them
+rc, rcontinue          run backwards to the last breakpoint or watchpoint
+b, break <addr> [if <expr>] [log <text>]
+                       stop before the instruction at addr
+d, delete <addr>       remove the breakpoints at addr
+w, watch <r|w|x> <addr>[-<end>] [if <expr>] [log <text>]
+                       stop after a read or write of the range, or before running code in it
+unwatch <n>            remove watchpoint n
+set <a|x|y|sp|p|pc> <value>
+                       change a register
+x <addr> [len]         hexdump memory
+r, regs                show the registers and the next instruction
+i, info                list the breakpoints and watchpoints
+q, quit
+Addresses are hex, with or without a $ or 0x in front, or a label from the symbol files.
+An empty line repeats the last command.
+Going backwards stops after the last write or read a watchpoint saw, so a step back from there
+gets to the instruction that made it.
+A breakpoint with a condition only stops when it is true, see expr.rs for what can go in one,
+e.g. `a == 0 && [$0300] > 5`. With log it prints the text instead of stopping,
+expressions in braces are filled in: `log A is {a}`";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Execute,
+}
+
+/// What breakpoints and watchpoints have in common:
+/// when they go off, what they do then and how many times they have
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trigger {
+    /// Only goes off while this is true
+    pub condition: Option<Expr>,
+
+    /// Logs this instead of stopping
+    pub log: Option<Template>,
+
+    /// How many times the condition held
+    pub hits: Cell<u64>,
+}
+
+impl Trigger {
+    /// Parses what comes after the address, `[if <expr>] [log <text>]`
+    fn parse(text: &str) -> Result<Trigger, String> {
+        // the keyword is a word of its own, any whitespace around it
+        let keyword = text.match_indices("log").map(|(at, _)| at).find(|&at| {
+            text[..at]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+                && text[at + 3..]
+                    .chars()
+                    .next()
+                    .is_none_or(char::is_whitespace)
+        });
+        let (condition, log) = match keyword {
+            Some(at) => match text[at + 3..].trim() {
+                "" => return Err("log needs the text to print".to_string()),
+                log => (text[..at].trim(), Some(Template::parse(log)?)),
+            },
+            None => (text.trim(), None),
+        };
+
+        let condition = match condition {
+            "" => None,
+            _ => match condition.strip_prefix("if ") {
+                Some(expr) => Some(Expr::parse(expr)?),
+                None => return Err(format!("expected if or log, not {}", condition)),
+            },
+        };
+
+        Ok(Trigger {
+            condition,
+            log,
+            hits: Cell::new(0),
+        })
+    }
+
+    /// True when the trigger would stop the program, without counting a hit
+    fn holds<M: Mem>(&self, cpu: &CPU<M>, access: (u16, u8)) -> bool {
+        self.log.is_none()
+            && self
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.is_true(cpu, access))
+    }
+
+    /// Counts a hit if the condition holds and logs it if that is what the trigger does.
+    /// Returns true when the program should stop
+    fn fire<M: Mem>(&self, cpu: &CPU<M>, access: (u16, u8), log: &mut Vec<String>) -> bool {
+        if let Some(condition) = &self.condition {
+            if !condition.is_true(cpu, access) {
+                return false;
+            }
+        }
+        self.hits.set(self.hits.get() + 1);
+
+        match &self.log {
+            Some(template) => {
+                log.push(template.render(cpu, access));
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        if let Some(log) = &self.log {
+            write!(f, " log {}", log)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stops the program before it runs the instruction at the address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub trigger: Trigger,
+}
+
+/// Stops the program when it touches any address in the range
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    pub trigger: Trigger,
+}
+
+/// An access that landed on a watchpoint.
+/// The data is the byte read or written, or the opcode for an execute watchpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub kind: WatchKind,
+    pub addr: u16,
+    pub data: u8,
+}
+
+/// Sits between the CPU and the bus and notes the accesses that hit a watchpoint.
+/// An access only counts when a tick came before it, that is how the CPU's own
+/// bus cycles are told apart from the debugger peeking at memory
+pub struct WatchedBus<M: Mem> {
+    pub inner: M,
+    pub watchpoints: Vec<Watchpoint>,
+    hits: RefCell<Vec<WatchHit>>,
+    in_cycle: Cell<bool>,
+}
+
+impl<M: Mem> WatchedBus<M> {
+    pub fn new(inner: M) -> Self {
+        WatchedBus {
+            inner,
+            watchpoints: Vec::new(),
+            hits: RefCell::new(Vec::new()),
+            in_cycle: Cell::new(false),
+        }
+    }
+
+    /// Takes the hits noted since the last call
+    pub fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(self.hits.get_mut())
+    }
+
+    /// The watchpoints of the kind that cover the address
+    fn watching(&self, kind: WatchKind, addr: u16) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints
+            .iter()
+            .filter(move |watch| watch.kind == kind && watch.range.contains(&addr))
+    }
+
+    fn note(&self, kind: WatchKind, addr: u16, data: u8) {
+        if self.in_cycle.replace(false) && self.watching(kind, addr).next().is_some() {
+            self.hits.borrow_mut().push(WatchHit { kind, addr, data });
+        }
+    }
+}
+
+impl<M: Mem> Mem for WatchedBus<M> {
+    fn read_mem_u8(&self, addr: u16) -> u8 {
+        let data = self.inner.read_mem_u8(addr);
+        self.note(WatchKind::Read, addr, data);
+        data
+    }
+
+    fn read_for(&mut self, addr: u16, kind: ReadKind) -> u8 {
+        let data = self.inner.read_for(addr, kind);
+        self.note(WatchKind::Read, addr, data);
+        data
+    }
+
+    fn write_mem_u8(&mut self, addr: u16, data: u8) {
+        self.note(WatchKind::Write, addr, data);
+        self.inner.write_mem_u8(addr, data);
+    }
+
+    fn tick(&mut self) {
+        self.in_cycle.set(true);
+        self.inner.tick();
+    }
+
+    fn take_fault(&mut self) -> Option<BusFault> {
+        self.inner.take_fault()
+    }
+}
+
+/// The watchpoints belong to the debugger, only the memory behind them goes back in time
+impl<M: Mem + Snapshot> Snapshot for WatchedBus<M> {
+    type State = M::State;
+
+    fn save_state(&self) -> Self::State {
+        self.inner.save_state()
+    }
+
+    fn load_state(&mut self, state: &Self::State) {
+        self.inner.load_state(state);
+        self.hits.get_mut().clear();
+        self.in_cycle.set(false);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    StackPointer,
+    Status,
+    ProgramCounter,
+}
+
+/// One line typed at the debugger prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Step(u32),
+    StepOver,
+    StepOut,
+    Continue,
+    StepBack(u32),
+    ReverseContinue,
+    Break(Breakpoint),
+    Delete(u16),
+    Watch(Watchpoint),
+    Unwatch(usize),
+    Set(Register, u16),
+    Hexdump { addr: u16, len: u16 },
+    Registers,
+    Info,
+    Help,
+    Quit,
+}
+
+/// The text after the first `n` words of the line
+fn after_words(line: &str, n: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        rest = rest
+            .find(char::is_whitespace)
+            .map_or("", |end| rest[end..].trim_start());
+    }
+    rest
+}
+
+/// Parses a label or a hex number, the `$` or `0x` in front is optional.
+/// A label wins over a number spelled the same, like `add`
+fn number(text: &str, labels: &BTreeMap<u16, String>) -> Result<u16, String> {
+    if let Some(address) = symbols::address_of(labels, text) {
+        return Ok(address);
+    }
+
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("{} is not a hex number or a label", text))
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        Command::parse(line, &BTreeMap::new())
+    }
+}
+
+impl Command {
+    /// Parses a line typed at the prompt, the addresses in it can be names from `labels`
+    pub fn parse(line: &str, labels: &BTreeMap<u16, String>) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |text: &str| number(text, labels);
+        let arg = |i: usize| -> Result<u16, String> {
+            let word = words
+                .get(i)
+                .ok_or_else(|| format!("{} needs more arguments, see help", words[0]))?;
+            number(word)
+        };
+
+        let count = || match words.get(1) {
+            Some(count) => count
+                .parse()
+                .map_err(|_| format!("{} is not a count", count)),
+            None => Ok(1),
+        };
+
+        let command = match words.first().copied().unwrap_or("") {
+            "s" | "step" => Command::Step(count()?),
+            "n" | "next" => Command::StepOver,
+            "o" | "out" => Command::StepOut,
+            "c" | "continue" => Command::Continue,
+            "rs" | "rstep" => Command::StepBack(count()?),
+            "rc" | "rcontinue" => Command::ReverseContinue,
+            "b" | "break" => Command::Break(Breakpoint {
+                address: arg(1)?,
+                trigger: Trigger::parse(after_words(line, 2))?,
+            }),
+            "d" | "delete" => Command::Delete(arg(1)?),
+            "w" | "watch" => {
+                let kind = match words.get(1).copied() {
+                    Some("r") => WatchKind::Read,
+                    Some("w") => WatchKind::Write,
+                    Some("x") => WatchKind::Execute,
+                    _ => return Err("watch needs r, w or x".to_string()),
+                };
+                let range = words
+                    .get(2)
+                    .ok_or_else(|| "watch needs an address".to_string())?;
+                let range = match range.split_once('-') {
+                    Some((start, end)) => number(start)?..=number(end)?,
+                    None => number(range)?..=number(range)?,
+                };
+                Command::Watch(Watchpoint {
+                    range,
+                    kind,
+                    trigger: Trigger::parse(after_words(line, 3))?,
+                })
+            }
+            "unwatch" => Command::Unwatch(
+                words
+                    .get(1)
+                    .and_then(|number| number.parse().ok())
+                    .ok_or_else(|| "unwatch needs the number info shows".to_string())?,
+            ),
+            "set" => {
+                let register = match words.get(1).copied() {
+                    Some("a") => Register::A,
+                    Some("x") => Register::X,
+                    Some("y") => Register::Y,
+                    Some("sp") => Register::StackPointer,
+                    Some("p") => Register::Status,
+                    Some("pc") => Register::ProgramCounter,
+                    _ => return Err("set needs one of a, x, y, sp, p or pc".to_string()),
+                };
+                Command::Set(register, arg(2)?)
+            }
+            "x" => Command::Hexdump {
+                addr: arg(1)?,
+                len: if words.len() > 2 {
+                    arg(2)?
+                } else {
+                    DEFAULT_DUMP
+                },
+            },
+            "r" | "regs" => Command::Registers,
+            "i" | "info" => Command::Info,
+            "h" | "help" | "?" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            other => return Err(format!("unknown command {}, try help", other)),
+        };
+
+        Ok(command)
+    }
+}
+
+/// Why the debugger gave control back to the prompt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The command ran to the end, the step count ran out or the subroutine returned
+    Done,
+
+    /// The program counter reached a breakpoint, the instruction there has not run yet
+    Breakpoint(u16),
+
+    /// The last instruction touched a watchpoint, or the next one is in an execute watchpoint
+    Watch(WatchHit),
+
+    /// A JAM opcode at this address locked the CPU up
+    Jam(u16),
+
+    /// Going backwards got to the oldest checkpoint, there is no more history before it
+    HistoryStart,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Done => Ok(()),
+            Stop::Breakpoint(addr) => write!(f, "breakpoint at ${:04X}", addr),
+            Stop::Watch(hit) => match hit.kind {
+                WatchKind::Read => write!(f, "read ${:02X} from ${:04X}", hit.data, hit.addr),
+                WatchKind::Write => write!(f, "wrote ${:02X} to ${:04X}", hit.data, hit.addr),
+                WatchKind::Execute => write!(f, "executing ${:04X}", hit.addr),
+            },
+            Stop::Jam(addr) => write!(f, "the CPU jammed at ${:04X}", addr),
+            Stop::HistoryStart => write!(f, "reached the start of the history"),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Execute => "execute",
+        };
+        if self.range.start() == self.range.end() {
+            write!(f, "{} ${:04X}", kind, self.range.start())?;
+        } else {
+            write!(
+                f,
+                "{} ${:04X}-${:04X}",
+                kind,
+                self.range.start(),
+                self.range.end()
+            )?;
+        }
+        write!(f, "{}", self.trigger)
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${:04X}{}", self.address, self.trigger)
+    }
+}
+
+pub struct Debugger<M: Mem + Snapshot> {
+    pub cpu: CPU<WatchedBus<M>>,
+    pub breakpoints: Vec<Breakpoint>,
+
+    /// Checkpoints of the CPU for running backwards
+    pub history: History<<CPU<WatchedBus<M>> as Snapshot>::State>,
+
+    /// Names for addresses, shown in the traces and usable in place of an address
+    pub labels: BTreeMap<u16, String>,
+
+    /// Lines from the logging breakpoints and watchpoints that haven't been shown yet
+    pub log: Vec<String>,
+}
+
+impl<M: Mem + Snapshot> Debugger<M> {
+    /// The CPU is not reset, that is up to the caller once the memory is set up
+    pub fn new(bus: M) -> Self {
+        Debugger {
+            cpu: CPU::new(WatchedBus::new(bus)),
+            breakpoints: Vec::new(),
+            history: History::new(CHECKPOINT_INTERVAL, CHECKPOINTS),
+            labels: BTreeMap::new(),
+            log: Vec::new(),
+        }
+    }
+
+    /// Runs `count` instructions
+    pub fn step(&mut self, count: u32) -> Result<Stop, EmuError> {
+        let mut left = count;
+        self.run(false, |_, _| {
+            left = left.saturating_sub(1);
+            left == 0
+        })
+    }
+
+    /// Steps, but a JSR is run until it comes back to the instruction after it
+    pub fn step_over(&mut self) -> Result<Stop, EmuError> {
+        match self.over_call() {
+            Some(done) => self.run(false, done),
+            None => self.step(1),
+        }
+    }
+
+    /// Runs until an RTS or RTI takes the stack above where it is now,
+    /// that is the return from the subroutine or interrupt handler the CPU is in
+    pub fn step_out(&mut self) -> Result<Stop, EmuError> {
+        let done = self.out_of_call();
+        self.run(false, done)
+    }
+
+    /// When the CPU is on a JSR, the condition for being back after it.
+    /// None for any other instruction, stepping over those is a single step
+    pub fn over_call(&self) -> Option<impl FnMut(&CPU<WatchedBus<M>>, &Step) -> bool> {
+        let pc = self.cpu.program_counter;
+        let opcode = opcodes::lookup(self.cpu.read_mem_u8(pc));
+        if opcode.map(|opcode| opcode.instruction) != Some(Instruction::JSR) {
+            return None;
+        }
+
+        // a recursive call comes back to the same address deeper in the stack
+        let return_address = pc.wrapping_add(3);
+        let stack_pointer = self.cpu.stack_pointer;
+        Some(move |cpu: &CPU<WatchedBus<M>>, _: &Step| {
+            cpu.program_counter == return_address && cpu.stack_pointer == stack_pointer
+        })
+    }
+
+    /// The condition for having returned from the subroutine or interrupt handler the CPU is in
+    pub fn out_of_call(&self) -> impl FnMut(&CPU<WatchedBus<M>>, &Step) -> bool {
+        let stack_pointer = self.cpu.stack_pointer;
+        move |cpu, step| {
+            matches!(step.opcode.instruction, Instruction::RTS | Instruction::RTI)
+                && cpu.stack_pointer > stack_pointer
+        }
+    }
+
+    /// Runs until a breakpoint or a watchpoint
+    pub fn resume(&mut self) -> Result<Stop, EmuError> {
+        self.run(false, |_, _| false)
+    }
+
+    /// Resumes for at most `count` instructions and then comes back with Stop::Done,
+    /// so a frontend can run the program in slices and still listen to its user.
+    /// Only the first slice after a stop should pass `first_slice`, the later ones
+    /// have to check for a breakpoint on the instruction they start on
+    pub fn resume_for(&mut self, count: u32, first_slice: bool) -> Result<Stop, EmuError> {
+        Ok(self
+            .resume_until(count, first_slice, |_, _| false)?
+            .unwrap_or(Stop::Done))
+    }
+
+    /// Like `resume_for`, but also stops with Stop::Done once `done` returns true after an
+    /// instruction. That is how a step over or step out runs in slices.
+    /// None when the slice ran out before anything stopped it
+    pub fn resume_until<F>(
+        &mut self,
+        count: u32,
+        first_slice: bool,
+        mut done: F,
+    ) -> Result<Option<Stop>, EmuError>
+    where
+        F: FnMut(&CPU<WatchedBus<M>>, &Step) -> bool,
+    {
+        let mut left = count;
+        let mut reached = false;
+        let stop = self.run(!first_slice, |cpu, step| {
+            reached = done(cpu, step);
+            left = left.saturating_sub(1);
+            reached || left == 0
+        })?;
+
+        Ok(match stop {
+            Stop::Done if !reached => None,
+            stop => Some(stop),
+        })
+    }
+
+    /// Runs instructions until `done` returns true after one of them.
+    /// Breakpoints and execute watchpoints are checked before every instruction,
+    /// the first one only with `check_first` so running again from where one stopped gets past it
+    fn run<F>(&mut self, check_first: bool, mut done: F) -> Result<Stop, EmuError>
+    where
+        F: FnMut(&CPU<WatchedBus<M>>, &Step) -> bool,
+    {
+        self.cpu.bus.take_hits();
+        let mut check = check_first;
+
+        loop {
+            self.record();
+            if check {
+                if let Some(stop) = self.check_pc() {
+                    return Ok(stop);
+                }
+            }
+            check = true;
+
+            let step = self.cpu.step();
+            self.history.advance();
+            let step = step?;
+            // the fast-cpu dummy accesses tick without touching the bus,
+            // don't let one of those make the next peek look like a CPU access
+            self.cpu.bus.in_cycle.set(false);
+
+            if self.cpu.jammed() {
+                return Ok(Stop::Jam(step.address));
+            }
+            if let Some(stop) = self.check_hits() {
+                return Ok(stop);
+            }
+            if done(&self.cpu, &step) {
+                return Ok(Stop::Done);
+            }
+        }
+    }
+
+    /// Goes back `count` instructions, or as far as the history goes
+    pub fn step_back(&mut self, count: u32) -> Result<Stop, EmuError> {
+        let now = self.history.now();
+        let oldest = match self.history.oldest() {
+            Some(oldest) if oldest < now => oldest,
+            _ => return Ok(Stop::HistoryStart),
+        };
+
+        if now - oldest < count as u64 {
+            self.travel(oldest);
+            return Ok(Stop::HistoryStart);
+        }
+        self.travel(now - count as u64);
+        Ok(Stop::Done)
+    }
+
+    /// Runs backwards to the last time a breakpoint or a watchpoint would have stopped the program.
+    /// The history is replayed one checkpoint at a time, newest first, until one has a stop in it
+    pub fn reverse_continue(&mut self) -> Result<Stop, EmuError> {
+        let now = self.history.now();
+        let mut end = now;
+
+        while end > 0 {
+            let state = match self.history.rewind(end - 1) {
+                Some(state) => state,
+                None => break,
+            };
+            self.cpu.load_state(&state);
+            let start = self.history.now();
+
+            let mut last = None;
+            while self.history.now() < end {
+                let time = self.history.now();
+                if let Some(stop) = self.stop_here() {
+                    last = Some((time, stop));
+                }
+                for hit in self.replay_step() {
+                    let stops = self
+                        .cpu
+                        .bus
+                        .watching(hit.kind, hit.addr)
+                        .any(|watch| watch.trigger.holds(&self.cpu, (hit.addr, hit.data)));
+                    // the watchpoint it is stopped on now doesn't count
+                    if stops && time + 1 < now {
+                        last = Some((time + 1, Stop::Watch(hit)));
+                    }
+                }
+            }
+
+            if let Some((time, stop)) = last {
+                self.travel(time);
+                return Ok(stop);
+            }
+            end = start;
+        }
+
+        if let Some(oldest) = self.history.oldest() {
+            self.travel(oldest);
+        }
+        Ok(Stop::HistoryStart)
+    }
+
+    /// Takes a checkpoint when one is due, or loads the one kept for this point in time
+    /// so a change made here by hand is made again
+    fn record(&mut self) {
+        if let Some(state) = self.history.record(|| self.cpu.save_state()) {
+            self.cpu.load_state(&state);
+        }
+    }
+
+    /// Puts the machine back the way it was at `time` by running forward from the checkpoint before it
+    fn travel(&mut self, time: u64) {
+        if let Some(state) = self.history.rewind(time) {
+            self.cpu.load_state(&state);
+        }
+        while self.history.now() < time {
+            self.replay_step();
+        }
+    }
+
+    /// Runs one instruction again and returns what it did to the watchpoints, without firing them.
+    /// An error comes back the same as it did the first time, it is left for the clock to move on
+    fn replay_step(&mut self) -> Vec<WatchHit> {
+        self.record();
+        let _ = self.cpu.step();
+        self.cpu.bus.in_cycle.set(false);
+        self.history.advance();
+        self.cpu.bus.take_hits()
+    }
+
+    /// The breakpoint or execute watchpoint that would stop the program where it is, going backwards
+    fn stop_here(&self) -> Option<Stop> {
+        let pc = self.cpu.program_counter;
+        let access = (pc, self.cpu.read_mem_u8(pc));
+
+        if self
+            .breakpoints
+            .iter()
+            .any(|b| b.address == pc && b.trigger.holds(&self.cpu, access))
+        {
+            return Some(Stop::Breakpoint(pc));
+        }
+        self.cpu
+            .bus
+            .watching(WatchKind::Execute, pc)
+            .any(|watch| watch.trigger.holds(&self.cpu, access))
+            .then_some(Stop::Watch(WatchHit {
+                kind: WatchKind::Execute,
+                addr: pc,
+                data: access.1,
+            }))
+    }
+
+    /// Fires the breakpoints and execute watchpoints on the instruction under the program counter.
+    /// All of them count the hit even when the first one already stops
+    fn check_pc(&mut self) -> Option<Stop> {
+        let pc = self.cpu.program_counter;
+        let access = (pc, self.cpu.read_mem_u8(pc));
+        let mut stop = None;
+
+        for breakpoint in self.breakpoints.iter().filter(|b| b.address == pc) {
+            if breakpoint.trigger.fire(&self.cpu, access, &mut self.log) {
+                stop = stop.or(Some(Stop::Breakpoint(pc)));
+            }
+        }
+        for watch in self.cpu.bus.watching(WatchKind::Execute, pc) {
+            if watch.trigger.fire(&self.cpu, access, &mut self.log) {
+                stop = stop.or(Some(Stop::Watch(WatchHit {
+                    kind: WatchKind::Execute,
+                    addr: pc,
+                    data: access.1,
+                })));
+            }
+        }
+
+        stop
+    }
+
+    /// Fires the read and write watchpoints for the accesses the last instruction made
+    fn check_hits(&mut self) -> Option<Stop> {
+        let mut stop = None;
+
+        for hit in self.cpu.bus.take_hits() {
+            for watch in self.cpu.bus.watching(hit.kind, hit.addr) {
+                if watch
+                    .trigger
+                    .fire(&self.cpu, (hit.addr, hit.data), &mut self.log)
+                {
+                    stop = stop.or(Some(Stop::Watch(hit)));
+                }
+            }
+        }
+
+        stop
+    }
+
+    /// Dumps `len` bytes starting at `start`, 16 to a line with the printable ones on the right
+    pub fn hexdump(&self, start: u16, len: u16) -> String {
+        let bytes: Vec<u8> = (0..len)
+            .map(|i| self.cpu.read_mem_u8(start.wrapping_add(i)))
+            .collect();
+
+        let lines: Vec<String> = bytes
+            .chunks(16)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+                let text: String = chunk
+                    .iter()
+                    .map(|&byte| match byte {
+                        0x20..=0x7E => byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                format!(
+                    "{:04X}  {:47}  {}",
+                    start.wrapping_add(row as u16 * 16),
+                    hex.join(" "),
+                    text
+                )
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Carries out a command and returns what to print. Quit is left to the caller
+    pub fn run_command(&mut self, command: Command) -> String {
+        let stop = match command {
+            Command::Step(count) => self.step(count.max(1)),
+            Command::StepOver => self.step_over(),
+            Command::StepOut => self.step_out(),
+            Command::Continue => self.resume(),
+            Command::StepBack(count) => self.step_back(count.max(1)),
+            Command::ReverseContinue => self.reverse_continue(),
+            Command::Break(breakpoint) => {
+                let message = format!("breakpoint {}", breakpoint);
+                self.breakpoints.push(breakpoint);
+                return message;
+            }
+            Command::Delete(addr) => {
+                let before = self.breakpoints.len();
+                self.breakpoints
+                    .retain(|breakpoint| breakpoint.address != addr);
+                return if self.breakpoints.len() < before {
+                    format!("deleted the breakpoints at ${:04X}", addr)
+                } else {
+                    format!("there is no breakpoint at ${:04X}", addr)
+                };
+            }
+            Command::Watch(watch) => {
+                self.cpu.bus.watchpoints.push(watch);
+                let number = self.cpu.bus.watchpoints.len();
+                return format!(
+                    "watchpoint {}: {}",
+                    number,
+                    self.cpu.bus.watchpoints[number - 1]
+                );
+            }
+            Command::Unwatch(number) => {
+                let watchpoints = &mut self.cpu.bus.watchpoints;
+                if number == 0 || number > watchpoints.len() {
+                    return format!("there is no watchpoint {}", number);
+                }
+                return format!("deleted watchpoint {}", watchpoints.remove(number - 1));
+            }
+            Command::Set(register, value) => {
+                if let Err(message) = self.set_register(register, value) {
+                    return message;
+                }
+                return trace::trace_with_labels(&self.cpu, &self.labels);
+            }
+            Command::Hexdump { addr, len } => return self.hexdump(addr, len),
+            Command::Registers => return trace::trace_with_labels(&self.cpu, &self.labels),
+            Command::Info => return self.info(),
+            Command::Help => return HELP.to_string(),
+            Command::Quit => return String::new(),
+        };
+
+        let mut lines = std::mem::take(&mut self.log);
+        match stop {
+            Ok(Stop::Done) => {}
+            Ok(stop) => lines.push(stop.to_string()),
+            Err(error) => {
+                lines.push(error.to_string());
+                return lines.join("\n");
+            }
+        }
+        lines.push(trace::trace_with_labels(&self.cpu, &self.labels));
+        lines.join("\n")
+    }
+
+    /// Changes a register, the history keeps the change so going back and forward again doesn't lose it
+    pub fn set_register(&mut self, register: Register, value: u16) -> Result<(), String> {
+        if register == Register::ProgramCounter {
+            self.cpu.program_counter = value;
+        } else {
+            let byte =
+                u8::try_from(value).map_err(|_| format!("${:X} doesn't fit in 8 bits", value))?;
+            match register {
+                Register::A => self.cpu.accumulator = byte,
+                Register::X => self.cpu.index_register_x = byte,
+                Register::Y => self.cpu.index_register_y = byte,
+                Register::StackPointer => self.cpu.stack_pointer = byte,
+                Register::Status => self.cpu.processor_status.0 = byte,
+                Register::ProgramCounter => unreachable!(),
+            }
+        }
+
+        self.history.checkpoint(self.cpu.save_state());
+        Ok(())
+    }
+
+    fn info(&self) -> String {
+        let hits = |trigger: &Trigger| match trigger.hits.get() {
+            1 => "1 hit".to_string(),
+            hits => format!("{} hits", hits),
+        };
+
+        let mut lines: Vec<String> = self
+            .breakpoints
+            .iter()
+            .map(|breakpoint| format!("breakpoint {}, {}", breakpoint, hits(&breakpoint.trigger)))
+            .collect();
+        lines.extend(
+            self.cpu
+                .bus
+                .watchpoints
+                .iter()
+                .enumerate()
+                .map(|(i, watch)| {
+                    format!("watchpoint {}: {}, {}", i + 1, watch, hits(&watch.trigger))
+                }),
+        );
+
+        if lines.is_empty() {
+            "no breakpoints or watchpoints".to_string()
+        } else {
+            lines.join("\n")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::asm;
+    use crate::cpu::memory::MemoryMap;
+
+    const PROGRAM: &str = "
+        start:  LDX #$00
+        loop:   JSR sub
+                INX
+                STA $0200,X
+                JMP loop
+        sub:    LDA #$05
+                JSR inner
+                RTS
+        inner:  NOP
+                RTS
+    ";
+
+    const SUB: u16 = 0x060C;
+    const INNER: u16 = 0x0612;
+
+    fn debugger() -> Debugger<MemoryMap> {
+        let program = asm::assemble(PROGRAM, 0x0600).unwrap();
+        let mut memory = MemoryMap::new();
+        memory.memory[0x0600..0x0600 + program.len()].copy_from_slice(&program);
+        memory.write_mem_u16(0xFFFC, 0x0600);
+
+        let mut debugger = Debugger::new(memory);
+        debugger.cpu.reset();
+        debugger
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoints() {
+        let mut debugger = debugger();
+        debugger.run_command("b 060c".parse().unwrap());
+
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(SUB));
+        assert_eq!(debugger.cpu.index_register_x, 0);
+
+        // going again gets past the breakpoint and round the loop to it
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(SUB));
+        assert_eq!(debugger.cpu.index_register_x, 1);
+    }
+
+    #[test]
+    fn step_over_and_out_of_subroutines() {
+        let mut debugger = debugger();
+        debugger.step(1).unwrap();
+        assert_eq!(debugger.cpu.program_counter, 0x0602);
+
+        // JSR sub runs sub and inner and comes back to the INX
+        assert_eq!(debugger.step_over().unwrap(), Stop::Done);
+        assert_eq!(debugger.cpu.program_counter, 0x0605);
+        assert_eq!(debugger.cpu.accumulator, 0x05);
+
+        // into sub and on into inner, then out of inner back to the RTS in sub
+        debugger.step(3).unwrap();
+        debugger.step(3).unwrap();
+        assert_eq!(debugger.cpu.program_counter, INNER);
+        assert_eq!(debugger.step_out().unwrap(), Stop::Done);
+        assert_eq!(debugger.cpu.program_counter, INNER - 1);
+
+        // the out from sub lands after the JSR in the loop
+        assert_eq!(debugger.step_out().unwrap(), Stop::Done);
+        assert_eq!(debugger.cpu.program_counter, 0x0605);
+    }
+
+    #[test]
+    fn watchpoints_see_the_bus() {
+        let mut debugger = debugger();
+        debugger.run_command("w w 0202".parse().unwrap());
+
+        // the STA that wrote it has run, the JMP after it has not
+        let stop = debugger.resume().unwrap();
+        assert_eq!(
+            stop,
+            Stop::Watch(WatchHit {
+                kind: WatchKind::Write,
+                addr: 0x0202,
+                data: 0x05
+            })
+        );
+        assert_eq!(debugger.cpu.program_counter, 0x0609);
+
+        // stack pushes go through the bus too
+        debugger.run_command("w r 01fd-01ff".parse().unwrap());
+        debugger.run_command("unwatch 1".parse().unwrap());
+        let stop = debugger.resume().unwrap();
+        assert!(matches!(stop, Stop::Watch(hit) if hit.kind == WatchKind::Read));
+
+        debugger.run_command("w x 0612".parse().unwrap());
+        debugger.run_command("unwatch 1".parse().unwrap());
+        let stop = debugger.resume().unwrap();
+        assert_eq!(
+            stop,
+            Stop::Watch(WatchHit {
+                kind: WatchKind::Execute,
+                addr: INNER,
+                data: 0xEA
+            })
+        );
+        assert_eq!(debugger.cpu.program_counter, INNER);
+    }
+
+    #[test]
+    fn looking_at_memory_does_not_trip_watchpoints() {
+        let mut debugger = debugger();
+        debugger.run_command("w r 0600-06ff".parse().unwrap());
+
+        let dump = debugger.hexdump(0x0600, 0x12);
+        assert_eq!(
+            dump,
+            "0600  A2 00 20 0C 06 E8 9D 00 02 4C 02 06 A9 05 20 12  .. ......L.... .\n\
+             0610  06 60                                            .`"
+        );
+        debugger.run_command(Command::Registers);
+
+        assert!(debugger.cpu.bus.take_hits().is_empty());
+    }
+
+    #[test]
+    fn conditions_and_logging() {
+        let mut debugger = debugger();
+        debugger.run_command("b 0605 if x == 2 && a == 5".parse().unwrap());
+        debugger.run_command("b 060c log sub with X={x} at {cycle}".parse().unwrap());
+        debugger.run_command(
+            "w w 0200-02ff if value == 5 && addr > $0202"
+                .parse()
+                .unwrap(),
+        );
+
+        // the logging breakpoint goes off on every call without stopping
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(0x0605));
+        assert_eq!(debugger.cpu.index_register_x, 2);
+        assert_eq!(debugger.breakpoints[1].trigger.hits.get(), 3);
+        assert_eq!(
+            debugger.log,
+            [
+                "sub with X=00 at 0F",
+                "sub with X=01 at 35",
+                "sub with X=02 at 5B"
+            ]
+        );
+        debugger.log.clear();
+
+        // the write to $0202 didn't count, the one to $0203 does.
+        // The lines logged on the way come out in front of the stop
+        debugger.run_command("b 0606 log X={x}".parse().unwrap());
+        let output = debugger.run_command(Command::Continue);
+        assert_eq!(debugger.cpu.read_mem_u8(0x0203), 5);
+        assert!(output.starts_with("X=03\nwrote $05 to $0203\n0609  4C 02 06  JMP $0602"));
+        assert!(debugger.log.is_empty());
+
+        assert_eq!(
+            debugger.run_command(Command::Info),
+            "breakpoint $0605 if x == 2 && a == 5, 1 hit\n\
+             breakpoint $060C log sub with X={x} at {cycle}, 3 hits\n\
+             breakpoint $0606 log X={x}, 1 hit\n\
+             watchpoint 1: write $0200-$02FF if value == 5 && addr > $0202, 1 hit"
+        );
+
+        assert_eq!(
+            "b 0605 when x".parse::<Command>(),
+            Err("expected if or log, not when x".to_string())
+        );
+        assert!("w r 10 if [10".parse::<Command>().is_err());
+
+        // log is only the keyword on its own, wherever the whitespace is
+        let command = "b 0605 if x == 2\tlog\tcatalog {x}".parse::<Command>();
+        assert!(command.is_ok(), "{:?}", command);
+        assert_eq!(
+            "b 0605 log".parse::<Command>(),
+            Err("log needs the text to print".to_string())
+        );
+        assert!("b 0605 if x == 2blog x".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            "b $C000".parse(),
+            Ok(Command::Break(Breakpoint {
+                address: 0xC000,
+                trigger: Trigger::default()
+            }))
+        );
+        assert_eq!("step 10".parse(), Ok(Command::Step(10)));
+        assert_eq!("rs 3".parse(), Ok(Command::StepBack(3)));
+        assert_eq!("rc".parse(), Ok(Command::ReverseContinue));
+        assert_eq!(
+            "x 0x200 10".parse(),
+            Ok(Command::Hexdump {
+                addr: 0x0200,
+                len: 0x10
+            })
+        );
+        assert!("b".parse::<Command>().is_err());
+        assert!("jump 1".parse::<Command>().is_err());
+
+        let mut debugger = debugger();
+        debugger.run_command("set a 7f".parse().unwrap());
+        debugger.run_command("set pc $0605".parse().unwrap());
+        assert_eq!(debugger.cpu.accumulator, 0x7F);
+        assert_eq!(debugger.cpu.program_counter, 0x0605);
+        assert_eq!(
+            debugger.run_command("set x 100".parse().unwrap()),
+            "$100 doesn't fit in 8 bits"
+        );
+    }
+
+    #[test]
+    fn labels_stand_in_for_addresses() {
+        let mut debugger = debugger();
+        // `add` would be a hex number without the label
+        debugger.labels = BTreeMap::from([(SUB, "sub".to_string()), (INNER, "add".to_string())]);
+
+        let command = Command::parse("b add", &debugger.labels).unwrap();
+        assert_eq!(debugger.run_command(command), "breakpoint $0612");
+        assert!(Command::parse("b nowhere", &debugger.labels).is_err());
+
+        let output = debugger.run_command(Command::Continue);
+        assert!(output.starts_with("breakpoint at $0612\n0612  EA        add: NOP"));
+
+        let command = Command::parse("set pc sub", &debugger.labels).unwrap();
+        assert!(debugger
+            .run_command(command)
+            .starts_with("060C  A9 05     sub: LDA #$05"));
+        assert!(debugger.run_command(Command::Step(1)).contains("JSR add"));
+    }
+
+    #[test]
+    fn stepping_backwards() {
+        let mut debugger = debugger();
+        // small enough that going back crosses a few checkpoints
+        debugger.history = History::new(4, 100);
+
+        debugger.step(20).unwrap();
+        let then = trace::trace(&debugger.cpu);
+        let memory = debugger.hexdump(0x0200, 4);
+        debugger.step(11).unwrap();
+
+        assert_eq!(debugger.step_back(11).unwrap(), Stop::Done);
+        assert_eq!(trace::trace(&debugger.cpu), then);
+        assert_eq!(debugger.hexdump(0x0200, 4), memory);
+
+        // a register set by hand stays set when the instructions after it are run again
+        debugger.run_command("set x 40".parse().unwrap());
+        debugger.step(6).unwrap();
+        let then = trace::trace(&debugger.cpu);
+        debugger.step_back(2).unwrap();
+        debugger.step(2).unwrap();
+        assert_eq!(trace::trace(&debugger.cpu), then);
+        debugger.step_back(6).unwrap();
+        assert_eq!(debugger.cpu.index_register_x, 0x40);
+
+        assert_eq!(debugger.step_back(100).unwrap(), Stop::HistoryStart);
+        assert_eq!(debugger.cpu.program_counter, 0x0600);
+        assert_eq!(debugger.cpu.current_cycle, 7);
+    }
+
+    #[test]
+    fn reverse_continue_to_the_last_write() {
+        let mut debugger = debugger();
+        debugger.history = History::new(4, 100);
+        debugger.run_command("w w 0200-02ff".parse().unwrap());
+        for _ in 0..3 {
+            debugger.resume().unwrap();
+        }
+        assert_eq!(debugger.cpu.read_mem_u8(0x0203), 0x05);
+
+        // back to just after the write before, and one more step to the STA that made it
+        let stop = debugger.reverse_continue().unwrap();
+        assert_eq!(
+            stop,
+            Stop::Watch(WatchHit {
+                kind: WatchKind::Write,
+                addr: 0x0202,
+                data: 0x05
+            })
+        );
+        assert_eq!(debugger.cpu.read_mem_u8(0x0203), 0x00);
+        debugger.step_back(1).unwrap();
+        assert_eq!(debugger.cpu.program_counter, 0x0606);
+        assert_eq!(debugger.cpu.read_mem_u8(0x0202), 0x00);
+
+        // breakpoints count going backwards too, but no hits are added
+        debugger.run_command("b 0612 if x == 1".parse().unwrap());
+        let output = debugger.run_command(Command::ReverseContinue);
+        assert!(output.starts_with("breakpoint at $0612\n0612  EA"));
+        assert_eq!(debugger.cpu.index_register_x, 1);
+
+        let stop = debugger.reverse_continue().unwrap();
+        assert!(matches!(stop, Stop::Watch(hit) if hit.addr == 0x0201));
+        assert_eq!(debugger.reverse_continue().unwrap(), Stop::HistoryStart);
+        assert_eq!(debugger.cpu.program_counter, 0x0600);
+        assert_eq!(
+            debugger.run_command(Command::Info),
+            "breakpoint $0612 if x == 1, 0 hits\n\
+             watchpoint 1: write $0200-$02FF, 3 hits"
+        );
+    }
+}
@@ -0,0 +1,640 @@
+//! Turns machine code back into 6502 assembly, `LDA ($20),Y`, `BNE $C5F9` and so on.
+//! Everything is decoded through the OPCODES table, the same one the CPU runs from
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::cpu::cpu::AddressingMode;
+use crate::cpu::memory::Mem;
+use crate::cpu::opcodes::{self, Access, Instruction, OpCode};
+use crate::cpu::rom::Rom;
+use crate::cpu::symbols::Symbols;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// A single 16K bank shows up at $8000 and again at $C000
+const MIRROR: u16 = 0x4000;
+
+/// The interrupt vectors at the top of memory and the label given to their handlers
+const VECTORS: [(u16, &str); 3] = [(0xFFFA, "nmi"), (0xFFFC, "reset"), (0xFFFE, "irq")];
+
+/// How many bytes of data go on one `.byte` line
+const BYTES_PER_LINE: usize = 8;
+
+/// One decoded instruction
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+
+    /// None when the bytes don't decode to anything or the instruction runs off the end
+    /// of what can be read, the line is data then
+    pub opcode: Option<&'static OpCode>,
+}
+
+impl Line {
+    /// How far it is to the next instruction
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The operand as a number, a zero page address or immediate value only uses the low byte
+    pub fn operand(&self) -> u16 {
+        match self.bytes.len() {
+            2 => self.bytes[1] as u16,
+            3 => (self.bytes[2] as u16) << 8 | self.bytes[1] as u16,
+            _ => 0,
+        }
+    }
+
+    /// Where a branch, JSR or absolute JMP goes
+    pub fn target(&self) -> Option<u16> {
+        let opcode = self.opcode?;
+        match opcode.addressing_mode {
+            AddressingMode::Relative => Some(branch_target(self.address, self.bytes[1])),
+            AddressingMode::Absolute if opcode.access == Access::Jump => Some(self.operand()),
+            _ => None,
+        }
+    }
+
+    /// True for the instructions that never carry on to the next one
+    pub fn ends_flow(&self) -> bool {
+        match self.opcode {
+            Some(opcode) => matches!(
+                opcode.instruction,
+                Instruction::JMP
+                    | Instruction::RTS
+                    | Instruction::RTI
+                    | Instruction::BRK
+                    | Instruction::JAM
+            ),
+            None => true,
+        }
+    }
+
+    /// Formats the instruction and puts the label in place of the address it jumps to,
+    /// or the address it reads or writes
+    pub fn to_string_with_labels(&self, labels: &BTreeMap<u16, String>) -> String {
+        let opcode = match self.opcode {
+            Some(opcode) => opcode,
+            None => return format_bytes(&self.bytes),
+        };
+
+        let operand = self.operand();
+        let byte = || match labels.get(&operand) {
+            Some(label) => label.clone(),
+            None => format!("${:02X}", operand),
+        };
+        let word = |addr: u16| match labels.get(&addr) {
+            Some(label) => label.clone(),
+            None => format!("${:04X}", addr),
+        };
+
+        let formatted = match opcode.addressing_mode {
+            AddressingMode::NoneAddressing => return opcode.mnemonic.to_string(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", operand),
+            AddressingMode::ZeroPage => byte(),
+            AddressingMode::ZeroPageX => format!("{},X", byte()),
+            AddressingMode::ZeroPageY => format!("{},Y", byte()),
+            AddressingMode::Absolute => word(operand),
+            AddressingMode::AbsoluteX => format!("{},X", word(operand)),
+            AddressingMode::AbsoluteY => format!("{},Y", word(operand)),
+            AddressingMode::Indirect => format!("({})", word(operand)),
+            AddressingMode::IndirectX => format!("({},X)", byte()),
+            AddressingMode::IndirectY => format!("({}),Y", byte()),
+            AddressingMode::Relative => word(self.target().unwrap_or_default()),
+        };
+
+        format!("{} {}", opcode.mnemonic, formatted)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with_labels(&BTreeMap::new()))
+    }
+}
+
+/// Where a branch at `address` with the offset `offset` lands
+pub fn branch_target(address: u16, offset: u8) -> u16 {
+    address.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+    format!(".byte {}", bytes.join(", "))
+}
+
+/// Decodes the instruction at `address`, `read` gives None for anything that can't be read
+pub fn decode(address: u16, read: impl Fn(u16) -> Option<u8>) -> Line {
+    let code = read(address).unwrap_or(0);
+    let data = |bytes| Line {
+        address,
+        bytes,
+        opcode: None,
+    };
+
+    let opcode = match opcodes::lookup(code) {
+        Some(opcode) => opcode,
+        None => return data(vec![code]),
+    };
+
+    let bytes: Option<Vec<u8>> = (0..opcode.bytes as u16)
+        .map(|i| read(address.wrapping_add(i)))
+        .collect();
+
+    match bytes {
+        Some(bytes) => Line {
+            address,
+            bytes,
+            opcode: Some(opcode),
+        },
+        None => data(vec![code]),
+    }
+}
+
+/// Disassembles `start..=end` one instruction after the other.
+/// The last one can run past `end`
+pub fn disassemble<M: Mem>(mem: &M, start: u16, end: u16) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = start as u32;
+
+    while address <= end as u32 {
+        let line = decode(address as u16, |addr| Some(mem.read_mem_u8(addr)));
+        address += line.len() as u32;
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Follows every path the code can take from the entry points, through branches,
+/// jumps and subroutine calls. Returns the address of every instruction it reached.
+/// Indirect jumps can't be followed, whatever they go to has to be reached some other way
+pub fn follow_code(entries: &[u16], read: impl Fn(u16) -> Option<u8>) -> BTreeSet<u16> {
+    let mut code = BTreeSet::new();
+    let mut pending: Vec<u16> = entries.to_vec();
+
+    while let Some(mut address) = pending.pop() {
+        loop {
+            if code.contains(&address) || read(address).is_none() {
+                break;
+            }
+
+            let line = decode(address, &read);
+            if line.opcode.is_none() {
+                break;
+            }
+            code.insert(address);
+
+            if let Some(target) = line.target() {
+                pending.push(target);
+            }
+            if line.ends_flow() {
+                break;
+            }
+            address = address.wrapping_add(line.len());
+        }
+    }
+
+    code
+}
+
+/// Names the places the code jumps to, subroutines get `sub_` and everything else `L_`.
+/// Labels that are already there are kept
+pub fn add_labels(labels: &mut BTreeMap<u16, String>, lines: &[Line], suffix: &str) {
+    for line in lines {
+        let (Some(target), Some(opcode)) = (line.target(), line.opcode) else {
+            continue;
+        };
+
+        let prefix = if opcode.instruction == Instruction::JSR {
+            "sub"
+        } else {
+            "L"
+        };
+        labels
+            .entry(target)
+            .or_insert_with(|| format!("{}_{:04X}{}", prefix, target, suffix));
+    }
+}
+
+/// Names the vector handlers and every place the code reachable from them jumps to,
+/// reading through a live memory map with whatever banks are switched in.
+/// The debuggers know the program by these
+pub fn labels<M: Mem>(mem: &M) -> BTreeMap<u16, String> {
+    let read = |addr: u16| Some(mem.read_mem_u8(addr));
+
+    let mut labels = BTreeMap::new();
+    let mut entries = Vec::new();
+    for (vector, name) in VECTORS {
+        let handler = mem.read_mem_u16(vector);
+        labels.entry(handler).or_insert_with(|| name.to_string());
+        entries.push(handler);
+    }
+
+    let lines: Vec<Line> = follow_code(&entries, read)
+        .into_iter()
+        .map(|addr| decode(addr, read))
+        .collect();
+    add_labels(&mut labels, &lines, "");
+    labels
+}
+
+/// 16K of PRG ROM and where the CPU sees it
+pub struct Bank<'a> {
+    pub number: usize,
+    pub base: u16,
+    pub data: &'a [u8],
+
+    /// Fixed banks are always mapped in, the others can be swapped out by the mapper
+    pub fixed: bool,
+}
+
+impl<'a> Bank<'a> {
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        self.data.get(offset).copied()
+    }
+
+    pub fn end(&self) -> u16 {
+        self.base + (self.data.len() - 1) as u16
+    }
+}
+
+/// Splits the PRG ROM into banks. NROM has everything mapped all the time, a single bank
+/// is mirrored so it sits at $C000 with the vectors. Bigger ROMs are treated like UxROM,
+/// the last bank is fixed at $C000 and the others take turns at $8000
+pub fn banks(rom: &Rom) -> Vec<Bank<'_>> {
+    let count = rom.prg_rom.len() / PRG_BANK_SIZE;
+
+    rom.prg_rom
+        .chunks(PRG_BANK_SIZE)
+        .enumerate()
+        .map(|(number, data)| {
+            let last = number == count - 1;
+            Bank {
+                number,
+                base: if last { 0xC000 } else { 0x8000 },
+                data,
+                fixed: count <= 2 || last,
+            }
+        })
+        .collect()
+}
+
+/// Disassembles a whole ROM into a `.s` listing.
+/// The fixed banks are followed from the reset, NMI and IRQ vectors so only code ends up
+/// as instructions, anything never reached is left as data. Nothing says which bank is
+/// switched in when, so the switchable banks are disassembled from the top to the bottom.
+/// The symbols name what they can, labels outside the ROM become `name = $0300` at the top
+pub fn disassemble_rom(rom: &Rom, symbols: &Symbols) -> String {
+    let banks = banks(rom);
+    let mirrored = banks.len() == 1;
+
+    // code in the mirror is listed where the bank really is
+    let canonical = |addr: u16| {
+        if mirrored && (0x8000..0xC000).contains(&addr) {
+            addr + MIRROR
+        } else {
+            addr
+        }
+    };
+    let read_fixed = |addr: u16| {
+        banks
+            .iter()
+            .filter(|bank| bank.fixed)
+            .find_map(|bank| bank.read(canonical(addr)))
+    };
+
+    let fixed: Vec<usize> = banks
+        .iter()
+        .filter(|bank| bank.fixed)
+        .map(|bank| bank.number)
+        .collect();
+    let mut labels = symbols.bank_labels(&fixed);
+
+    let mut entries = Vec::new();
+    for (vector, name) in VECTORS {
+        if let (Some(lo), Some(hi)) = (read_fixed(vector), read_fixed(vector + 1)) {
+            let handler = (hi as u16) << 8 | lo as u16;
+            labels.entry(handler).or_insert_with(|| name.to_string());
+            entries.push(handler);
+        }
+    }
+
+    let code: BTreeSet<u16> = follow_code(&entries, read_fixed)
+        .into_iter()
+        .map(canonical)
+        .collect();
+    let lines: Vec<Line> = code.iter().map(|addr| decode(*addr, read_fixed)).collect();
+    add_labels(&mut labels, &lines, "");
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "; {} PRG banks, mapper {}\n",
+        banks.len(),
+        rom.mapper
+    ));
+    for (addr, name) in labels.range(..0x8000) {
+        out.push_str(&format!("{} = ${:04X}\n", name, addr));
+    }
+
+    for bank in &banks {
+        let read = |addr: u16| bank.read(addr);
+        let (code, labels) = if bank.fixed {
+            (code.clone(), labels.clone())
+        } else {
+            let lines = disassemble_bank(bank);
+            let mut bank_labels = labels.clone();
+            for (addr, name) in symbols.bank_labels(&[bank.number]) {
+                bank_labels.entry(addr).or_insert(name);
+            }
+            add_labels(&mut bank_labels, &lines, &format!("_b{}", bank.number));
+            let code = lines
+                .iter()
+                .filter(|line| line.opcode.is_some())
+                .map(|line| line.address)
+                .collect();
+            (code, bank_labels)
+        };
+
+        out.push_str(&format!(
+            "\n; bank {} at ${:04X}-${:04X}, {}\n.org ${:04X}\n",
+            bank.number,
+            bank.base,
+            bank.end(),
+            if bank.fixed {
+                "fixed"
+            } else {
+                "switchable, swept from the top"
+            },
+            bank.base
+        ));
+        write_bank(&mut out, bank, &code, &labels, mirrored, read);
+    }
+
+    out
+}
+
+fn disassemble_bank(bank: &Bank) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = bank.base as u32;
+
+    while address <= bank.end() as u32 {
+        let line = decode(address as u16, |addr| bank.read(addr));
+        address += line.len() as u32;
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Writes out one bank, instructions where there is code and `.byte` everywhere else.
+/// Labels jumped to through the mirror of a single bank are defined relative to where it is
+fn write_bank(
+    out: &mut String,
+    bank: &Bank,
+    code: &BTreeSet<u16>,
+    labels: &BTreeMap<u16, String>,
+    mirrored: bool,
+    read: impl Fn(u16) -> Option<u8>,
+) {
+    let end = bank.end() as u32;
+    let mut address = bank.base as u32;
+    let mirror_label = |addr: u16| {
+        mirrored
+            .then(|| labels.get(&addr.wrapping_sub(MIRROR)))
+            .flatten()
+    };
+
+    while address <= end {
+        let addr = address as u16;
+        if let Some(label) = labels.get(&addr) {
+            out.push_str(&format!("{}:\n", label));
+        }
+        if let Some(label) = mirror_label(addr) {
+            out.push_str(&format!("{} = * - ${:04X}\n", label, MIRROR));
+        }
+
+        let line = if code.contains(&addr) {
+            decode(addr, &read)
+        } else if bank.fixed && addr == VECTORS[0].0 && end == 0xFFFF {
+            write_vectors(out, labels, &read);
+            break;
+        } else {
+            // data runs up to the next bit of code or label
+            let mut bytes = vec![bank.data[(address - bank.base as u32) as usize]];
+            let mut next = address + 1;
+            while next <= end
+                && bytes.len() < BYTES_PER_LINE
+                && !code.contains(&(next as u16))
+                && !labels.contains_key(&(next as u16))
+                && mirror_label(next as u16).is_none()
+                && !(bank.fixed && next as u16 == VECTORS[0].0)
+            {
+                bytes.push(bank.data[(next - bank.base as u32) as usize]);
+                next += 1;
+            }
+            Line {
+                address: addr,
+                bytes,
+                opcode: None,
+            }
+        };
+
+        let hex: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        out.push_str(&format!(
+            "    {:<40}; {:04X}  {}\n",
+            line.to_string_with_labels(labels),
+            addr,
+            hex.join(" ")
+        ));
+        address += line.len() as u32;
+    }
+}
+
+fn write_vectors(
+    out: &mut String,
+    labels: &BTreeMap<u16, String>,
+    read: impl Fn(u16) -> Option<u8>,
+) {
+    for (vector, name) in VECTORS {
+        let handler =
+            read(vector).unwrap_or(0) as u16 | (read(vector + 1).unwrap_or(0) as u16) << 8;
+        let target = match labels.get(&handler) {
+            Some(label) => label.clone(),
+            None => format!("${:04X}", handler),
+        };
+        out.push_str(&format!(
+            "    {:<40}; {:04X}  {} vector\n",
+            format!(".word {}", target),
+            vector,
+            name
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::asm;
+    use crate::cpu::memory::MemoryMap;
+    use crate::cpu::rom::Mirroring;
+
+    fn memory_with(at: u16, program: &[u8]) -> MemoryMap {
+        let mut memory = MemoryMap::new();
+        memory.memory[at as usize..at as usize + program.len()].copy_from_slice(program);
+        memory
+    }
+
+    #[test]
+    fn disassemble_uses_standard_syntax() {
+        // as bytes, the assembler works from the same opcode table and would agree with anything
+        // LDA ($20),Y; BNE back to the start; JMP ($0200); ASL A; STA $0300,X; LDX $10,Y
+        let memory = memory_with(
+            0xC5F9,
+            &[
+                0xB1, 0x20, 0xD0, 0xFC, 0x6C, 0x00, 0x02, 0x0A, 0x9D, 0x00, 0x03, 0xB6, 0x10,
+            ],
+        );
+
+        let lines: Vec<String> = disassemble(&memory, 0xC5F9, 0xC604)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "LDA ($20),Y",
+                "BNE $C5F9",
+                "JMP ($0200)",
+                "ASL A",
+                "STA $0300,X",
+                "LDX $10,Y"
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_opcodes_are_data() {
+        let memory = memory_with(0x0600, &[0x8B, 0xEA]);
+        let lines = disassemble(&memory, 0x0600, 0x0601);
+
+        assert!(lines[0].opcode.is_none());
+        assert_eq!(lines[0].to_string(), ".byte $8B");
+        assert_eq!(lines[1].to_string(), "NOP");
+    }
+
+    #[test]
+    fn follow_code_skips_data() {
+        let program = asm::assemble(
+            "
+            start:  JSR sub
+                    BEQ skip
+                    RTS
+            skip:   JMP start
+            sub:    LDA #$01
+                    RTS
+                    .byte $FF, $FF
+            ",
+            0x0600,
+        )
+        .unwrap();
+        let memory = memory_with(0x0600, &program);
+        let read = |addr: u16| {
+            (0x0600..0x060E)
+                .contains(&addr)
+                .then(|| memory.memory[addr as usize])
+        };
+
+        let code = follow_code(&[0x0600], read);
+        assert_eq!(
+            code.into_iter().collect::<Vec<u16>>(),
+            vec![0x0600, 0x0603, 0x0605, 0x0606, 0x0609, 0x060B]
+        );
+    }
+
+    #[test]
+    fn rom_listing_labels_the_vectors_and_targets() {
+        let mut prg_rom = vec![0xFF; PRG_BANK_SIZE];
+        prg_rom[..7].copy_from_slice(&[
+            0x78, //             C000 SEI
+            0x20, 0x06, 0xC0, // C001 JSR $C006
+            0xD0, 0xFE, //       C004 BNE $C004
+            0x40, //             C006 RTI
+        ]);
+        prg_rom[0x3FFA..].copy_from_slice(&[0x06, 0xC0, 0x00, 0xC0, 0x06, 0xC0]);
+
+        let rom = Rom {
+            prg_rom,
+            chr_rom: vec![],
+            mapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+        };
+        let listing = disassemble_rom(&rom, &Symbols::default());
+
+        assert!(listing.contains(".org $C000\nreset:\n    SEI"));
+        assert!(listing.contains("    JSR nmi "));
+        assert!(listing.contains("L_C004:\n    BNE L_C004 "));
+        assert!(listing.contains("    .byte $FF, $FF, $FF, $FF, $FF, $FF, $FF, $FF"));
+        assert!(
+            listing.contains("    .word reset                             ; FFFC  reset vector")
+        );
+    }
+
+    #[test]
+    fn rom_listing_uses_symbols() {
+        let mut prg_rom = vec![0xFF; PRG_BANK_SIZE];
+        prg_rom[..8].copy_from_slice(&[
+            0xA5, 0x10, //       C000 LDA $10
+            0x8D, 0x00, 0x20, // C002 STA $2000
+            0x4C, 0x00, 0xC0, // C005 JMP $C000
+        ]);
+        prg_rom[0x3FFA..].copy_from_slice(&[0x00, 0xC0, 0x00, 0xC0, 0x00, 0xC0]);
+
+        let rom = Rom {
+            prg_rom,
+            chr_rom: vec![],
+            mapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+        };
+        let symbols =
+            Symbols::parse_mlb("P:0000:main\nR:0010:player_x\nG:2000:PPUCTRL\n", 1).unwrap();
+        let listing = disassemble_rom(&rom, &symbols);
+
+        assert!(listing.contains("player_x = $0010\nPPUCTRL = $2000\n"));
+        assert!(listing.contains("main:\n    LDA player_x "));
+        assert!(listing.contains("    STA PPUCTRL "));
+        assert!(listing.contains("    JMP main "));
+        assert!(listing.contains("    .word main "));
+    }
+
+    #[test]
+    fn big_roms_have_one_fixed_bank() {
+        let rom = Rom {
+            prg_rom: vec![0xEA; 4 * PRG_BANK_SIZE],
+            chr_rom: vec![],
+            mapper: 2,
+            screen_mirroring: Mirroring::VERTICAL,
+        };
+
+        let banks = banks(&rom);
+        let layout: Vec<(u16, bool)> = banks.iter().map(|bank| (bank.base, bank.fixed)).collect();
+        assert_eq!(
+            layout,
+            vec![
+                (0x8000, false),
+                (0x8000, false),
+                (0x8000, false),
+                (0xC000, true)
+            ]
+        );
+    }
+}
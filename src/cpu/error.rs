@@ -0,0 +1,93 @@
+use std::fmt;
+
+use crate::cpu::cpu::AddressingMode;
+
+/// The registers at the moment something went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuState {
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+    pub accumulator: u8,
+    pub index_register_x: u8,
+    pub index_register_y: u8,
+    pub processor_status: u8,
+    pub current_cycle: u64,
+
+    /// The instruction under the program counter in the nestest trace format
+    pub trace: String,
+}
+
+/// Everything that stops the CPU from carrying on with the program.
+/// The CPU is left as it was so the frontend can show it and offer a reset
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmuError {
+    /// The opcode is not in the OPCODES table, these are the unstable unofficial ones.
+    /// The program counter is left on the opcode
+    UnknownOpcode { code: u8, state: CpuState },
+
+    /// An unofficial opcode came up while the CPU is in strict mode.
+    /// The program counter is left on the opcode
+    UnofficialOpcode {
+        code: u8,
+        mnemonic: &'static str,
+        state: CpuState,
+    },
+
+    /// The program wrote to cartridge ROM, the write was dropped.
+    /// The state is from after the instruction that did it
+    RomWrite {
+        addr: u16,
+        data: u8,
+        state: CpuState,
+    },
+
+    /// An instruction asked for the memory address of a mode that doesn't have one,
+    /// the opcode table and the dispatch disagree
+    InvalidAddressingMode {
+        mode: AddressingMode,
+        state: CpuState,
+    },
+
+    /// The CPU ran a JAM opcode and is locked up until it is reset
+    Jammed { state: CpuState },
+}
+
+impl EmuError {
+    pub fn state(&self) -> &CpuState {
+        match self {
+            EmuError::UnknownOpcode { state, .. }
+            | EmuError::UnofficialOpcode { state, .. }
+            | EmuError::RomWrite { state, .. }
+            | EmuError::InvalidAddressingMode { state, .. }
+            | EmuError::Jammed { state } => state,
+        }
+    }
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::UnknownOpcode { code, .. } => {
+                write!(f, "OpCode {:#04x} is not in the OPCODES table", code)?
+            }
+            EmuError::UnofficialOpcode { code, mnemonic, .. } => write!(
+                f,
+                "OpCode {:#04x} ({}) is an unofficial opcode and the CPU is in strict mode",
+                code, mnemonic
+            )?,
+            EmuError::RomWrite { addr, data, .. } => write!(
+                f,
+                "Attempt to write {:#04x} to Cartridge ROM space at {:#06x}",
+                data, addr
+            )?,
+            EmuError::InvalidAddressingMode { mode, .. } => {
+                write!(f, "mode {:?} does not address memory", mode)?
+            }
+            EmuError::Jammed { .. } => write!(f, "the CPU is jammed and needs a reset")?,
+        }
+
+        write!(f, ". dumping CPU\n {}", self.state().trace)
+    }
+}
+
+impl std::error::Error for EmuError {}
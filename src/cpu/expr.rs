@@ -0,0 +1,453 @@
+//! The expressions breakpoint and watchpoint conditions are written in.
+//!
+//! ```text
+//! a == 0 && [$0300] > 5       registers, and a byte of memory in square brackets
+//! scanline == 241 && x & 1    the PPU position, worked out from the cycle count for now
+//! {$FFFA} != $C000 || !i      a little endian word in braces, single letter flags
+//! ```
+//!
+//! The names are `a x y sp p pc`, the flags `c z i d v n`, `cycle`, `scanline`, `dot`
+//! and `frame`, and `addr` and `value` for the access that set a watchpoint off
+//! (the program counter and the opcode for a breakpoint). Numbers are decimal unless they
+//! start with `$` or `0x`. The operators and their precedence are the ones from C,
+//! comparisons give 1 or 0 and anything that isn't 0 is true
+
+use std::fmt;
+
+use crate::cpu::cpu::CPU;
+use crate::cpu::memory::Mem;
+use crate::cpu::processor_status::ProcessorStatusFlags;
+use crate::cpu::trace;
+
+/// A parsed expression, it keeps the source around to show it back
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(i64),
+    Name(Name),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Name {
+    A,
+    X,
+    Y,
+    StackPointer,
+    Status,
+    ProgramCounter,
+    /// The mask of one of the ProcessorStatusFlags
+    Flag(u8),
+    Cycle,
+    Scanline,
+    Dot,
+    Frame,
+    Addr,
+    Value,
+}
+
+impl Name {
+    fn parse(name: &str) -> Option<Name> {
+        let flag = |flag: ProcessorStatusFlags| Some(Name::Flag(flag as u8));
+        match name {
+            "a" => Some(Name::A),
+            "x" => Some(Name::X),
+            "y" => Some(Name::Y),
+            "sp" => Some(Name::StackPointer),
+            "p" => Some(Name::Status),
+            "pc" => Some(Name::ProgramCounter),
+            "c" => flag(ProcessorStatusFlags::CarryFlag),
+            "z" => flag(ProcessorStatusFlags::ZeroFlag),
+            "i" => flag(ProcessorStatusFlags::InterruptDisable),
+            "d" => flag(ProcessorStatusFlags::DecimalMode),
+            "v" => flag(ProcessorStatusFlags::Overflow),
+            "n" => flag(ProcessorStatusFlags::Negative),
+            "cycle" => Some(Name::Cycle),
+            "scanline" => Some(Name::Scanline),
+            "dot" => Some(Name::Dot),
+            "frame" => Some(Name::Frame),
+            "addr" => Some(Name::Addr),
+            "value" => Some(Name::Value),
+            _ => None,
+        }
+    }
+}
+
+/// Longest first so `<=` isn't read as `<` and `=`
+const OPERATORS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "|", "^", "&", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]", "{", "}",
+];
+
+/// How tightly each binary operator binds, higher goes first
+fn precedence(operator: &str) -> Option<u8> {
+    match operator {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "|" => Some(3),
+        "^" => Some(4),
+        "&" => Some(5),
+        "==" | "!=" => Some(6),
+        "<" | "<=" | ">" | ">=" => Some(7),
+        "<<" | ">>" => Some(8),
+        "+" | "-" => Some(9),
+        "*" | "/" | "%" => Some(10),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let length = if c == '$' || c.is_ascii_digit() {
+            let (digits, radix, skip) = if let Some(hex) = rest.strip_prefix('$') {
+                (hex, 16, 1)
+            } else if let Some(hex) = rest.strip_prefix("0x") {
+                (hex, 16, 2)
+            } else {
+                (rest, 10, 0)
+            };
+            let length = digits
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(digits.len());
+            let number = i64::from_str_radix(&digits[..length], radix)
+                .map_err(|_| format!("{} is not a number", &rest[..skip + length]))?;
+            tokens.push(Token::Number(number));
+            skip + length
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..length].to_lowercase()));
+            length
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        } else {
+            return Err(format!("unexpected {}", c));
+        };
+
+        rest = rest[length..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, closing: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Operator(operator)) if operator == closing => Ok(()),
+            _ => Err(format!("missing {}", closing)),
+        }
+    }
+
+    /// Parses binary operators that bind at least as tightly as `min`
+    fn binary(&mut self, min: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+
+        while let Some(Token::Operator(operator)) = self.peek() {
+            let operator = *operator;
+            let precedence = match precedence(operator) {
+                Some(precedence) if precedence >= min => precedence,
+                _ => break,
+            };
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Name(name)) => Name::parse(&name)
+                .map(Node::Name)
+                .ok_or_else(|| format!("don't know what {} is", name)),
+            Some(Token::Operator(operator @ ("!" | "-" | "~"))) => {
+                Ok(Node::Unary(operator, Box::new(self.unary()?)))
+            }
+            Some(Token::Operator("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Operator("[")) => {
+                let node = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Byte(Box::new(node)))
+            }
+            Some(Token::Operator("{")) => {
+                let node = self.binary(0)?;
+                self.expect("}")?;
+                Ok(Node::Word(Box::new(node)))
+            }
+            Some(Token::Operator(operator)) => Err(format!("unexpected {}", operator)),
+            None => Err("the expression ends too soon".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let node = parser.binary(0)?;
+
+        match parser.peek() {
+            None => Ok(Expr {
+                source: source.trim().to_string(),
+                node,
+            }),
+            Some(Token::Number(number)) => Err(format!("unexpected {}", number)),
+            Some(Token::Name(name)) => Err(format!("unexpected {}", name)),
+            Some(Token::Operator(operator)) => Err(format!("unexpected {}", operator)),
+        }
+    }
+
+    /// Works the expression out against the CPU as it is now.
+    /// `access` is the address and byte `addr` and `value` stand for
+    pub fn eval<M: Mem>(&self, cpu: &CPU<M>, access: (u16, u8)) -> i64 {
+        eval(&self.node, cpu, access)
+    }
+
+    pub fn is_true<M: Mem>(&self, cpu: &CPU<M>, access: (u16, u8)) -> bool {
+        self.eval(cpu, access) != 0
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn eval<M: Mem>(node: &Node, cpu: &CPU<M>, access: (u16, u8)) -> i64 {
+    match node {
+        Node::Number(number) => *number,
+        Node::Name(name) => match name {
+            Name::A => cpu.accumulator as i64,
+            Name::X => cpu.index_register_x as i64,
+            Name::Y => cpu.index_register_y as i64,
+            Name::StackPointer => cpu.stack_pointer as i64,
+            Name::Status => cpu.processor_status.0 as i64,
+            Name::ProgramCounter => cpu.program_counter as i64,
+            Name::Flag(mask) => (cpu.processor_status.0 & mask != 0) as i64,
+            Name::Cycle => cpu.current_cycle as i64,
+            Name::Scanline => trace::ppu_position(cpu.current_cycle).0 as i64,
+            Name::Dot => trace::ppu_position(cpu.current_cycle).1 as i64,
+            Name::Frame => trace::ppu_frame(cpu.current_cycle) as i64,
+            Name::Addr => access.0 as i64,
+            Name::Value => access.1 as i64,
+        },
+        Node::Byte(addr) => cpu.read_mem_u8(eval(addr, cpu, access) as u16) as i64,
+        Node::Word(addr) => cpu.read_mem_u16(eval(addr, cpu, access) as u16) as i64,
+        Node::Unary(operator, operand) => {
+            let operand = eval(operand, cpu, access);
+            match *operator {
+                "!" => (operand == 0) as i64,
+                "-" => operand.wrapping_neg(),
+                _ => !operand,
+            }
+        }
+        Node::Binary(operator, left, right) => {
+            let left_value = eval(left, cpu, access);
+            // the logical operators only look at the right side when they have to
+            match *operator {
+                "&&" => return (left_value != 0 && eval(right, cpu, access) != 0) as i64,
+                "||" => return (left_value != 0 || eval(right, cpu, access) != 0) as i64,
+                _ => {}
+            }
+
+            let right_value = eval(right, cpu, access);
+            match *operator {
+                "==" => (left_value == right_value) as i64,
+                "!=" => (left_value != right_value) as i64,
+                "<" => (left_value < right_value) as i64,
+                "<=" => (left_value <= right_value) as i64,
+                ">" => (left_value > right_value) as i64,
+                ">=" => (left_value >= right_value) as i64,
+                "|" => left_value | right_value,
+                "^" => left_value ^ right_value,
+                "&" => left_value & right_value,
+                "<<" => left_value.wrapping_shl(right_value as u32),
+                ">>" => left_value.wrapping_shr(right_value as u32),
+                "+" => left_value.wrapping_add(right_value),
+                "-" => left_value.wrapping_sub(right_value),
+                "*" => left_value.wrapping_mul(right_value),
+                // a condition can't stop the run to complain, dividing by zero gives 0
+                "/" => left_value.checked_div(right_value).unwrap_or(0),
+                _ => left_value.checked_rem(right_value).unwrap_or(0),
+            }
+        }
+    }
+}
+
+/// Log text with expressions in braces that get filled in with their values in hex,
+/// e.g. `A is {a} at {pc}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Value(Expr),
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+
+            // braces nest, a word read can go inside a hole
+            let mut depth = 0;
+            let close = rest[open..]
+                .find(|c| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .ok_or_else(|| "a { in the log text is never closed".to_string())?;
+
+            parts.push(Part::Value(Expr::parse(&rest[open + 1..open + close])?));
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        Ok(Template {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    pub fn render<M: Mem>(&self, cpu: &CPU<M>, access: (u16, u8)) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value(expr) => match expr.eval(cpu, access) {
+                    value @ 0..=0xFF => format!("{:02X}", value),
+                    value => format!("{:04X}", value),
+                },
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::memory::MemoryMap;
+
+    fn cpu() -> CPU<MemoryMap> {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.accumulator = 0;
+        cpu.index_register_x = 3;
+        cpu.program_counter = 0xC123;
+        cpu.processor_status.0 = 0b0010_0101;
+        cpu.current_cycle = 27_394;
+        cpu.write_mem_u8(0x0300, 6);
+        cpu.write_mem_u16(0xFFFA, 0xC000);
+        cpu
+    }
+
+    fn eval(source: &str) -> i64 {
+        Expr::parse(source).unwrap().eval(&cpu(), (0x0200, 0x42))
+    }
+
+    #[test]
+    fn names_and_memory() {
+        assert_eq!(eval("pc"), 0xC123);
+        assert_eq!(eval("a == 0 && [$0300] > 5"), 1);
+        assert_eq!(eval("{$FFFA}"), 0xC000);
+        assert_eq!(eval("c + i * 2 + z * 4"), 3);
+        assert_eq!(eval("scanline == 241 && x & 1"), 1);
+        assert_eq!(eval("dot"), 1);
+        assert_eq!(eval("addr + value"), 0x0242);
+        assert_eq!(eval("[{$FFFA} - $BD00]"), 6);
+    }
+
+    #[test]
+    fn precedence_follows_c() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 | 2 == 2"), 1);
+        assert_eq!(eval("8 >> 1 + 1"), 2);
+        assert_eq!(eval("-1 < 0 || 1 / 0"), 1);
+        assert_eq!(eval("!0x10 + ~0"), -1);
+        assert_eq!(eval("7 % 0"), 0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Expr::parse("a ==").unwrap_err(),
+            "the expression ends too soon"
+        );
+        assert_eq!(Expr::parse("[$10").unwrap_err(), "missing ]");
+        assert_eq!(Expr::parse("q > 1").unwrap_err(), "don't know what q is");
+        assert_eq!(Expr::parse("a 1").unwrap_err(), "unexpected 1");
+        assert_eq!(Expr::parse("$1G").unwrap_err(), "$1G is not a number");
+    }
+
+    #[test]
+    fn templates_fill_in_values() {
+        let template = Template::parse("A={a} at {pc}, vector {{$FFFA}}").unwrap();
+        assert_eq!(template.render(&cpu(), (0, 0)), "A=00 at C123, vector C000");
+        assert!(Template::parse("oops {a").is_err());
+    }
+}
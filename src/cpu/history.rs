@@ -0,0 +1,157 @@
+//! Checkpoints of the machine for the debugger to step backwards with.
+//!
+//! Time is counted in instructions. Every `interval` instructions a snapshot of the CPU
+//! and its memory is kept, going back to a point before the current one restores the
+//! last checkpoint before it and runs the program forward again. The CPU does the same
+//! thing every time from the same state, so that lands exactly where the program was.
+//! Anything that changes the machine from outside the program has to be recorded with
+//! `checkpoint`, running forward over that time again puts the change back
+
+use std::collections::VecDeque;
+
+pub struct History<S> {
+    /// Oldest first, each with the time it was taken at
+    checkpoints: VecDeque<(u64, S)>,
+
+    /// Instructions between the regular checkpoints
+    interval: u64,
+
+    /// The oldest checkpoints are dropped past this many, it sets how far back one can go
+    limit: usize,
+
+    /// Instructions run so far
+    now: u64,
+}
+
+impl<S: Clone> History<S> {
+    pub fn new(interval: u64, limit: usize) -> Self {
+        History {
+            checkpoints: VecDeque::new(),
+            interval: interval.max(1),
+            limit: limit.max(1),
+            now: 0,
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// The earliest time that can be gone back to, None before anything has been recorded
+    pub fn oldest(&self) -> Option<u64> {
+        self.checkpoints.front().map(|&(time, _)| time)
+    }
+
+    /// Called before every instruction, takes the state when a checkpoint is due.
+    /// Going forward again over a time that was already recorded hands back the state
+    /// kept for it instead, to be loaded in case it was changed there
+    pub fn record<F: FnOnce() -> S>(&mut self, state: F) -> Option<S> {
+        if self
+            .checkpoints
+            .back()
+            .is_none_or(|&(time, _)| time < self.now)
+        {
+            if self.now.is_multiple_of(self.interval) {
+                self.push(state());
+            }
+            return None;
+        }
+
+        let index = self
+            .checkpoints
+            .binary_search_by_key(&self.now, |&(time, _)| time)
+            .ok()?;
+        Some(self.checkpoints[index].1.clone())
+    }
+
+    /// Moves the clock on after an instruction
+    pub fn advance(&mut self) {
+        self.now += 1;
+    }
+
+    /// Keeps the state as it is now, after something outside the program changed it.
+    /// The checkpoints from here on came from the old state and are dropped
+    pub fn checkpoint(&mut self, state: S) {
+        while self
+            .checkpoints
+            .back()
+            .is_some_and(|&(time, _)| time >= self.now)
+        {
+            self.checkpoints.pop_back();
+        }
+        self.push(state);
+    }
+
+    /// The last checkpoint at or before `time`, to run forward from to get there.
+    /// The clock is set to the time of the checkpoint
+    pub fn rewind(&mut self, time: u64) -> Option<S> {
+        let (at, state) = self.checkpoints.iter().rev().find(|&&(at, _)| at <= time)?;
+        self.now = *at;
+        Some(state.clone())
+    }
+
+    fn push(&mut self, state: S) {
+        self.checkpoints.push_back((self.now, state));
+        if self.checkpoints.len() > self.limit {
+            self.checkpoints.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `count` instructions that each add one to the state
+    fn run(history: &mut History<u64>, state: &mut u64, count: u64) {
+        for _ in 0..count {
+            if let Some(kept) = history.record(|| *state) {
+                *state = kept;
+            }
+            *state += 1;
+            history.advance();
+        }
+    }
+
+    #[test]
+    fn checkpoints_every_interval() {
+        let mut history = History::new(10, 3);
+        let mut state = 0;
+        run(&mut history, &mut state, 25);
+        assert_eq!(history.oldest(), Some(0));
+
+        assert_eq!(history.rewind(17), Some(10));
+        assert_eq!(history.now(), 10);
+
+        // going forward over what is there already doesn't add to it,
+        // and only the newest three are kept
+        state = 10;
+        run(&mut history, &mut state, 25);
+        assert_eq!(history.rewind(29), Some(20));
+        assert_eq!(history.oldest(), Some(10));
+        assert_eq!(history.rewind(5), None);
+    }
+
+    #[test]
+    fn changes_from_outside_replace_the_future() {
+        let mut history = History::new(10, 10);
+        let mut state = 0;
+        run(&mut history, &mut state, 25);
+
+        state = history.rewind(5).unwrap();
+        run(&mut history, &mut state, 5);
+        state = 105;
+        history.checkpoint(state);
+        run(&mut history, &mut state, 10);
+
+        // the old checkpoints at 10 and 20 are gone, the new one at 10 follows the change
+        assert_eq!(history.rewind(14), Some(110));
+        assert_eq!(history.rewind(9), Some(105));
+        assert_eq!(history.rewind(4), Some(0));
+
+        // and running over the change again makes it again
+        state = 0;
+        run(&mut history, &mut state, 8);
+        assert_eq!(state, 108);
+    }
+}
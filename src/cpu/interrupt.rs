@@ -0,0 +1,54 @@
+/// The different ways the program can be interrupted
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InterruptType {
+    /// Non maskable interrupt, the PPU fires this at the start of vblank
+    NMI,
+
+    /// Interrupt request, ignored while the InterruptDisable flag is set
+    IRQ,
+
+    /// Software interrupt from the BRK instruction
+    BRK,
+}
+
+/// Describes how the CPU handles one kind of interrupt
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Interrupt {
+    pub interrupt_type: InterruptType,
+
+    /// Where the CPU reads the address of the handler from
+    pub vector_addr: u16,
+}
+
+pub const NMI: Interrupt = Interrupt {
+    interrupt_type: InterruptType::NMI,
+    vector_addr: 0xFFFA,
+};
+
+pub const IRQ: Interrupt = Interrupt {
+    interrupt_type: InterruptType::IRQ,
+    vector_addr: 0xFFFE,
+};
+
+pub const BRK: Interrupt = Interrupt {
+    interrupt_type: InterruptType::BRK,
+    vector_addr: 0xFFFE,
+};
+
+/// Everything that can hold the IRQ line low.
+/// The line is shared so the CPU sees an interrupt while any of them is asserting it
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum IrqSource {
+    /// The APU frame counter
+    FrameCounter = 0b0000_0001,
+
+    /// The APU delta modulation channel
+    Dmc = 0b0000_0010,
+
+    /// Cartridge hardware like the MMC3 and MMC5 scanline counters
+    Mapper = 0b0000_0100,
+
+    /// Anything else, used by the tests and the debugger
+    External = 0b0000_1000,
+}
@@ -1,3 +1,31 @@
+/// A memory access the hardware behind the address can't do.
+/// The access is dropped and the CPU picks the fault up after the instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusFault {
+    RomWrite { addr: u16, data: u8 },
+}
+
+/// What the CPU wants a byte it reads for, it comes along with every read it makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadKind {
+    /// The first byte of an instruction
+    Opcode,
+
+    /// The first byte of the instruction a `JMP ($nnnn)` went to
+    IndirectOpcode,
+
+    /// The bytes after the opcode
+    Operand,
+
+    Data,
+
+    /// Data found through a pointer, by the `($nn),Y` and `($nn,X)` modes
+    IndirectData,
+
+    /// A read the CPU throws the value of away
+    Dummy,
+}
+
 pub trait Mem {
     fn read_mem_u8(&self, addr: u16) -> u8;
 
@@ -5,14 +33,86 @@ pub trait Mem {
 
     fn read_mem_u16(&self, pos: u16) -> u16 {
         let lo = self.read_mem_u8(pos) as u16;
-        let hi = self.read_mem_u8(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+        let hi = self.read_mem_u8(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
     }
 
     fn write_mem_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.write_mem_u8(pos, lo);
-        self.write_mem_u8(pos + 1, hi);
+        self.write_mem_u8(pos.wrapping_add(1), hi);
+    }
+
+    /// A read the CPU makes while it runs. Devices that log how memory gets used
+    /// hang off this, reads from anywhere else go through read_mem_u8
+    fn read_for(&mut self, addr: u16, _kind: ReadKind) -> u8 {
+        self.read_mem_u8(addr)
+    }
+
+    /// Called once for every CPU cycle, before the access the CPU makes in it.
+    /// Devices that count cycles hang off this
+    fn tick(&mut self) {}
+
+    /// Takes the fault left by the last access that failed, if there was one
+    fn take_fault(&mut self) -> Option<BusFault> {
+        None
+    }
+}
+
+/// Memory whose contents can be copied out and put back later.
+/// The state only needs what a running program can change, the ROM stays where it is
+pub trait Snapshot {
+    type State: Clone;
+
+    fn save_state(&self) -> Self::State;
+
+    fn load_state(&mut self, state: &Self::State);
+}
+
+/// A flat 64K of RAM with nothing mapped into it.
+/// Enough to run plain 6502 programs and the CPU tests without a cartridge.
+/// The first 256 byte page of memory (0x0000 - 0x00FF) is Zero Page
+/// The second page (0x0100-0x01FF) is the system stack
+/// The other reserved parts of the memory map is 0xFFFA to 0xFFFF
+/// that part has to be programed with the interrupt handler (0xFFFA/B)
+/// the power reset location and the BRK/interrupt request handler
+pub struct MemoryMap {
+    pub memory: [u8; 0x10000],
+}
+
+impl MemoryMap {
+    pub fn new() -> MemoryMap {
+        MemoryMap {
+            memory: [0; 0x10000],
+        }
+    }
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryMap::new()
+    }
+}
+
+impl Mem for MemoryMap {
+    fn read_mem_u8(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write_mem_u8(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
+
+impl Snapshot for MemoryMap {
+    type State = Box<[u8; 0x10000]>;
+
+    fn save_state(&self) -> Self::State {
+        Box::new(self.memory)
+    }
+
+    fn load_state(&mut self, state: &Self::State) {
+        self.memory = **state;
     }
 }
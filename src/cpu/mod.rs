@@ -1,7 +1,19 @@
+pub mod asm;
 pub mod bus;
+pub mod cdl;
 /// Defines all the includes to work the CPU of the emulator
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod expr;
+pub mod history;
+pub mod interrupt;
 pub mod memory;
 pub mod opcodes;
 pub mod processor_status;
+pub mod profile;
 pub mod rom;
+pub mod step;
+pub mod symbols;
+pub mod trace;
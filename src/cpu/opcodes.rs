@@ -1,283 +1,711 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
 
 use crate::cpu::cpu::AddressingMode;
-use lazy_static::lazy_static;
 
 use super::cpu::CPU;
+use super::memory::Mem;
+use super::processor_status::ProcessorStatusFlags;
+use Instruction::*;
+
+/// The status flags as bits, for the flags_read and flags_written masks
+pub const CARRY: u8 = ProcessorStatusFlags::CarryFlag as u8;
+pub const ZERO: u8 = ProcessorStatusFlags::ZeroFlag as u8;
+pub const INTERRUPT_DISABLE: u8 = ProcessorStatusFlags::InterruptDisable as u8;
+pub const DECIMAL: u8 = ProcessorStatusFlags::DecimalMode as u8;
+pub const OVERFLOW: u8 = ProcessorStatusFlags::Overflow as u8;
+pub const NEGATIVE: u8 = ProcessorStatusFlags::Negative as u8;
+
+/// Every flag that really exists in the register, the break bits only show up when it is pushed
+pub const ALL_FLAGS: u8 = CARRY | ZERO | INTERRUPT_DISABLE | DECIMAL | OVERFLOW | NEGATIVE;
+
+/// What an instruction does with the memory its operand points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Works on the registers, the flags or the stack and has no memory operand
+    None,
+
+    /// Only reads the operand, these pay the page crossing penalty
+    Read,
+
+    /// Only writes the operand
+    Write,
+
+    /// Reads the operand, writes it back and then writes the new value
+    ReadModifyWrite,
+
+    /// Changes the program counter, the branches, jumps, returns and BRK
+    Jump,
+}
+
+/// Declares the Instruction enum with a variant for every mnemonic
+macro_rules! instructions {
+    ($($name:ident),* $(,)?) => {
+        /// What an opcode does, the CPU has one handler for each of these.
+        /// Several opcodes share an instruction and only differ in addressing mode
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Instruction {
+            $($name),*
+        }
+
+        impl Instruction {
+            pub const fn mnemonic(self) -> &'static str {
+                match self {
+                    $(Instruction::$name => stringify!($name)),*
+                }
+            }
+        }
+    };
+}
 
-#[derive(Debug)]
+instructions!(
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC, CLD, CLI, CLV, CMP, CPX,
+    CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP, JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA, PHP, PLA,
+    PLP, ROL, ROR, RTI, RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA, TXS, TYA,
+    // the unofficial ones
+    ALR, ANC, ARR, AXS, DCP, ISB, JAM, LAX, RLA, RRA, SAX, SLO, SRE,
+);
+
+#[derive(Debug, Clone, Copy)]
 pub struct OpCode {
     pub code: u8,
+    pub instruction: Instruction,
     pub mnemonic: &'static str,
     pub bytes: u8,
     pub cycles: u8,
     pub addressing_mode: AddressingMode,
+
+    /// Set for the undocumented opcodes, the ones nestest prints with a `*`
+    pub illegal: bool,
+
+    pub access: Access,
+
+    /// The status flags the instruction looks at and the ones it can change
+    pub flags_read: u8,
+    pub flags_written: u8,
+
+    /// Cycles added when an indexed read crosses into the next page,
+    /// or when a taken branch lands on a different page
+    pub page_cross_penalty: u8,
+
+    /// Cycles added when a branch is taken
+    pub branch_penalty: u8,
 }
 
-// make a way to match the mnemonic in the cpu match statement instead of the bytes
 impl OpCode {
-    fn new(
+    const fn new(
         code: u8,
-        mnemonic: &'static str,
+        instruction: Instruction,
         bytes: u8,
         cycles: u8,
         addressing_mode: AddressingMode,
     ) -> OpCode {
         OpCode {
             code,
-            mnemonic,
+            instruction,
+            mnemonic: instruction.mnemonic(),
             bytes,
             cycles,
             addressing_mode,
+            illegal: false,
+            access: access(instruction, addressing_mode),
+            flags_read: flags(instruction).0,
+            flags_written: flags(instruction).1,
+            page_cross_penalty: match (access(instruction, addressing_mode), addressing_mode) {
+                (
+                    Access::Read,
+                    AddressingMode::AbsoluteX
+                    | AddressingMode::AbsoluteY
+                    | AddressingMode::IndirectY,
+                ) => 1,
+                (_, AddressingMode::Relative) => 1,
+                _ => 0,
+            },
+            branch_penalty: match addressing_mode {
+                AddressingMode::Relative => 1,
+                _ => 0,
+            },
         }
     }
 
-    pub fn to_string(&self) -> String {
-        return format!(
-            "{} | {} -> addr: {:?}",
-            self.mnemonic, self.code, self.addressing_mode
-        );
+    /// Same as new but for the undocumented opcodes
+    const fn new_illegal(
+        code: u8,
+        instruction: Instruction,
+        bytes: u8,
+        cycles: u8,
+        addressing_mode: AddressingMode,
+    ) -> OpCode {
+        OpCode {
+            illegal: true,
+            ..OpCode::new(code, instruction, bytes, cycles, addressing_mode)
+        }
+    }
+
+    /// Renders the instruction under the program counter with its operand the way nestest.log does,
+    /// e.g. `LDA $0200,X @ 0201 = 05`. Where the operand points and what is there
+    /// is worked out from the registers as they are now
+    pub fn to_string_with_memory<M: Mem>(&self, cpu: &CPU<M>) -> String {
+        self.to_string_with_labels(cpu, &BTreeMap::new())
     }
 
-    pub fn to_string_with_memory(&self, cpu: &CPU) -> String {
-        return format!(
+    /// The same as to_string_with_memory with the label in place of an operand that has one,
+    /// e.g. `LDA player_x,X @ 0011 = 05`
+    pub fn to_string_with_labels<M: Mem>(
+        &self,
+        cpu: &CPU<M>,
+        labels: &BTreeMap<u16, String>,
+    ) -> String {
+        let operand = self.format_operand(cpu, cpu.program_counter, labels);
+        if operand.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operand)
+        }
+    }
+
+    /// Disassembles the operand and shows where it points and what is stored there
+    fn format_operand<M: Mem>(
+        &self,
+        cpu: &CPU<M>,
+        begin: u16,
+        labels: &BTreeMap<u16, String>,
+    ) -> String {
+        let mode = &self.addressing_mode;
+        let operand_addr = begin.wrapping_add(1);
+        let arg = cpu.read_mem_u8(operand_addr);
+        let arg_u16 = cpu.read_mem_u16(operand_addr);
+
+        let byte = |addr: u8| match labels.get(&(addr as u16)) {
+            Some(label) => label.clone(),
+            None => format!("${:02X}", addr),
+        };
+        let word = |addr: u16| match labels.get(&addr) {
+            Some(label) => label.clone(),
+            None => format!("${:04X}", addr),
+        };
+
+        match mode {
+            AddressingMode::NoneAddressing => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", arg),
+            AddressingMode::Relative => {
+                let target = operand_addr.wrapping_add(1).wrapping_add(arg as i8 as u16);
+                word(target)
+            }
+            AddressingMode::ZeroPage => format!("{} = {:02X}", byte(arg), peek(cpu, arg as u16)),
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let addr = cpu.get_absolute_address(mode, operand_addr);
+                format!(
+                    "{},{} @ {:02X} = {:02X}",
+                    byte(arg),
+                    index_register(mode),
+                    addr,
+                    peek(cpu, addr)
+                )
+            }
+            AddressingMode::Absolute => match self.access {
+                // the jumps just show where they go
+                Access::Jump => word(arg_u16),
+                _ => format!("{} = {:02X}", word(arg_u16), peek(cpu, arg_u16)),
+            },
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let addr = cpu.get_absolute_address(mode, operand_addr);
+                format!(
+                    "{},{} @ {:04X} = {:02X}",
+                    word(arg_u16),
+                    index_register(mode),
+                    addr,
+                    peek(cpu, addr)
+                )
+            }
+            AddressingMode::Indirect => {
+                // same page wrap bug as the JMP itself
+                let lo = peek(cpu, arg_u16) as u16;
+                let hi = peek(cpu, (arg_u16 & 0xFF00) | (arg_u16.wrapping_add(1) & 0x00FF)) as u16;
+                format!("({}) = {:04X}", word(arg_u16), hi << 8 | lo)
+            }
+            AddressingMode::IndirectX => {
+                let ptr = arg.wrapping_add(cpu.index_register_x);
+                let addr = peek_u16_zero_page(cpu, ptr);
+                format!(
+                    "({},X) @ {:02X} = {:04X} = {:02X}",
+                    byte(arg),
+                    ptr,
+                    addr,
+                    peek(cpu, addr)
+                )
+            }
+            AddressingMode::IndirectY => {
+                let base = peek_u16_zero_page(cpu, arg);
+                let addr = base.wrapping_add(cpu.index_register_y as u16);
+                format!(
+                    "({}),Y = {:04X} @ {:04X} = {:02X}",
+                    byte(arg),
+                    base,
+                    addr,
+                    peek(cpu, addr)
+                )
+            }
+        }
+    }
+}
+
+fn index_register(mode: &AddressingMode) -> &'static str {
+    match mode {
+        AddressingMode::ZeroPageY | AddressingMode::AbsoluteY | AddressingMode::IndirectY => "Y",
+        _ => "X",
+    }
+}
+
+/// Reads memory for the disassembly without poking at registers that react to being read.
+/// Those show up as FF, the same as in the reference log
+fn peek<M: Mem>(cpu: &CPU<M>, addr: u16) -> u8 {
+    match addr {
+        // the PPU registers and their mirrors, then the APU and IO registers
+        0x2000..=0x4017 => 0xFF,
+        _ => cpu.read_mem_u8(addr),
+    }
+}
+
+fn peek_u16_zero_page<M: Mem>(cpu: &CPU<M>, ptr: u8) -> u16 {
+    let lo = peek(cpu, ptr as u16) as u16;
+    let hi = peek(cpu, ptr.wrapping_add(1) as u16) as u16;
+    hi << 8 | lo
+}
+
+/// How an instruction touches memory in a given addressing mode
+const fn access(instruction: Instruction, mode: AddressingMode) -> Access {
+    match instruction {
+        BCC | BCS | BEQ | BMI | BNE | BPL | BVC | BVS | JMP | JSR | RTS | RTI | BRK => Access::Jump,
+        _ => match mode {
+            AddressingMode::NoneAddressing | AddressingMode::Accumulator => Access::None,
+            _ => match instruction {
+                STA | STX | STY | SAX => Access::Write,
+                ASL | LSR | ROL | ROR | INC | DEC | DCP | ISB | SLO | RLA | SRE | RRA => {
+                    Access::ReadModifyWrite
+                }
+                _ => Access::Read,
+            },
+        },
+    }
+}
+
+/// The flags an instruction reads and the flags it writes
+const fn flags(instruction: Instruction) -> (u8, u8) {
+    const NZ: u8 = NEGATIVE | ZERO;
+    const NZC: u8 = NEGATIVE | ZERO | CARRY;
+    const NVZC: u8 = NEGATIVE | OVERFLOW | ZERO | CARRY;
+
+    match instruction {
+        ADC | SBC | ISB | RRA => (CARRY | DECIMAL, NVZC),
+        AND | ORA | EOR | LDA | LDX | LDY | LAX | PLA => (0, NZ),
+        INC | DEC | INX | INY | DEX | DEY | TAX | TAY | TSX | TXA | TYA => (0, NZ),
+        ASL | LSR | CMP | CPX | CPY | ALR | ANC | AXS | DCP | SLO | SRE => (0, NZC),
+        ROL | ROR | RLA => (CARRY, NZC),
+        ARR => (CARRY, NVZC),
+        BIT => (0, NEGATIVE | OVERFLOW | ZERO),
+        BCC | BCS => (CARRY, 0),
+        BEQ | BNE => (ZERO, 0),
+        BMI | BPL => (NEGATIVE, 0),
+        BVC | BVS => (OVERFLOW, 0),
+        CLC | SEC => (0, CARRY),
+        CLD | SED => (0, DECIMAL),
+        CLI | SEI => (0, INTERRUPT_DISABLE),
+        CLV => (0, OVERFLOW),
+        // these push the whole register
+        PHP => (ALL_FLAGS, 0),
+        BRK => (ALL_FLAGS, INTERRUPT_DISABLE),
+        // and these pull it back
+        PLP | RTI => (0, ALL_FLAGS),
+        JMP | JSR | RTS | NOP | PHA | STA | STX | STY | SAX | TXS | JAM => (0, 0),
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
             "{} | {} -> addr: {:?}",
             self.mnemonic, self.code, self.addressing_mode
-        );
+        )
     }
 }
 
-lazy_static! {
-    static ref CPU_OPCODES: Vec<OpCode> = vec![
-
-        /* ------ LOAD INSTRUCTIONS ------ */
-        OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBD, "LDA", 3, 4, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0xB9, "LDA", 3, 4, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0xB1, "LDA", 2, 5, AddressingMode::IndirectY), // +1 if page crossed
-
-        OpCode::new(0xA2, "LDX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA6, "LDX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB6, "LDX", 2, 4, AddressingMode::ZeroPageY),
-        OpCode::new(0xAE, "LDX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBE, "LDX", 3, 4, AddressingMode::AbsoluteY), // +1 if page crossed
-
-        OpCode::new(0xA0, "LDY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA4, "LDY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0xAC, "LDY", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBC, "LDY", 3, 4, AddressingMode::AbsoluteX), // +1 if page crossed
-
-        /* ------ ADDING SUBTRACTING INSTRUCTIONS ------ */
-        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x6D, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7D, "ADC", 3, 4, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0x79, "ADC", 3, 4, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x71, "ADC", 2, 5, AddressingMode::IndirectY), // +1 if page crossed
-
-        // subtract with carry
-        OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xFD, "SBC", 3, 4, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0xF9, "SBC", 3, 4, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0xF1, "SBC", 2, 5, AddressingMode::IndirectY), // +1 if page crossed
-
-        /* ------ LOGICAL BIT OPERATIONS ------ */
-
-        // and - logical and
-        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x2D, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3D, "AND", 3, 4, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0x39, "AND", 3, 4, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0x21, "AND", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x31, "AND", 2, 5, AddressingMode::IndirectY), // +1 if page crossed
-
-        // shift right (>>)
-        OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::Accumulator),
-        OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::AbsoluteX),
-
-        // shift left (<<)
-        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::Accumulator),
-        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::AbsoluteX),
-
-        // Logical Inclusive OR
-        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x05, "ORA", 2, 2, AddressingMode::ZeroPage),
-        OpCode::new(0x15, "ORA", 2, 2, AddressingMode::ZeroPageX),
-        OpCode::new(0x0D, "ORA", 3, 3, AddressingMode::Absolute),
-        OpCode::new(0x1D, "ORA", 3, 3, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0x19, "ORA", 3, 3, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0x01, "ORA", 2, 2, AddressingMode::IndirectX),
-        OpCode::new(0x11, "ORA", 2, 2, AddressingMode::IndirectY), // +1 if page crossed
-
-        // Exclusive or
-        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x5D, "EOR", 3, 4, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0x59, "EOR", 3, 4, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x51, "EOR", 2, 5, AddressingMode::IndirectY), // +1 if page crossed
-
-        /* ------ BRANCH OPERATIONS ------ */
-        // A lot of branch operations that depend on cpu flags
-        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::Relative),
-        OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::Relative),
-        OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::Relative),
-        OpCode::new(0xD0, "BNE", 2, 2, AddressingMode::Relative),
-        OpCode::new(0x10, "BPL", 2, 2, AddressingMode::Relative),
-        OpCode::new(0x30, "BMI", 2, 2, AddressingMode::Relative),
-        OpCode::new(0x50, "BVC", 2, 2, AddressingMode::Relative),
-        OpCode::new(0x70, "BVS", 2, 2, AddressingMode::Relative),
-
-        // jump
-        OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute),
-        OpCode::new(0x6C, "JMP", 3, 3, AddressingMode::Indirect),
-
-        // jump to subroutine
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
-
-        // return from subroutine
-        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
-
-        /* ------ STATUS OPERATIONS ------ */
-        // clearing flags
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing), // carry
-        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing), // decimal
-        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing), // interrupt
-        OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing), // overflow
-
-        // setting flags
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing), // carry
-        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing), // decimal
-        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing), // interrupt
-
-        /* ------ COMPARING INSTRUCTIONS ------ */
-        OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC5, "CMP", 2, 2, AddressingMode::ZeroPage),
-        OpCode::new(0xD5, "CMP", 2, 2, AddressingMode::ZeroPageX),
-        OpCode::new(0xCD, "CMP", 3, 3, AddressingMode::Absolute),
-        OpCode::new(0xDD, "CMP", 3, 3, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0xD9, "CMP", 3, 3, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0xC1, "CMP", 2, 2, AddressingMode::IndirectX),
-        OpCode::new(0xD1, "CMP", 2, 2, AddressingMode::IndirectY), // +1 if page crossed
-
-        // Compare x register
-        OpCode::new(0xE0, "CPX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE4, "CPX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xEC, "CPX", 3, 4, AddressingMode::Absolute),
-
-        // Compare y register
-        OpCode::new(0xC0, "CPY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC4, "CPY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xCC, "CMY", 3, 4, AddressingMode::Absolute),
-
-        // bit test
-        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
-
-        /* ------ TRANSFER INSTRUCTIONS ------ */
-        OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xBA, "TSX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
-
-        /* ------ INCREMENT AND DECREMENT INSTRUCTIONS ------ */
-        OpCode::new(0xE6, "INC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0xEE, "INC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xFE, "INC", 3, 7, AddressingMode::AbsoluteX),
-
-        OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xC8, "INY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xD6, "DEC", 2, 5, AddressingMode::ZeroPageX),
-        OpCode::new(0xCE, "DEC", 2, 5, AddressingMode::Absolute),
-        OpCode::new(0xDE, "DEC", 2, 5, AddressingMode::AbsoluteX),
-
-        OpCode::new(0xCA, "DEX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
-
-        /* ------ STACK OPERATIONS ------ */
-        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing), // +1 if page crossed
-        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing), // +1 if page crossed
-        OpCode::new(0x28, "PLP", 1, 3, AddressingMode::NoneAddressing), // +1 if page crossed
-
-        /* ------ ROTATE OPERATIONS ------ */
-        OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::Accumulator),
-        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::AbsoluteX),
-
-        // Rotate right
-        OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::Accumulator),
-        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::AbsoluteX),
-
-
-
-        /* ------ STORE OPERATIONS ------ */
-        // store x in a memory address
-        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPageY),
-        OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
-
-        // store y in a memory address
-        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
-
-        // store a in a memory address
-        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x9D, "STA", 3, 5, AddressingMode::AbsoluteX), // +1 if page crossed
-        OpCode::new(0x99, "STA", 3, 5, AddressingMode::AbsoluteY), // +1 if page crossed
-        OpCode::new(0x81, "STA", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x91, "STA", 2, 6, AddressingMode::IndirectY), // +1 if page crossed
-
-        // transfer operations
-
-
-        /* ------ NO OPERATION ------ */
-        OpCode::new(0xEA, "NOP", 1, 2, AddressingMode::NoneAddressing),
-
-        /* ------ BREAK AND INTERRUPT OPERATIONS ------ */
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
-    ];
-
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
-        for code in &*CPU_OPCODES {
-            map.insert(code.code, code);
-        }
-        map
-    };
+// rustfmt would squash the blank lines between the groups
+#[rustfmt::skip]
+const CPU_OPCODES: &[OpCode] = &[
+
+    /* ------ LOAD INSTRUCTIONS ------ */
+    OpCode::new(0xA9, LDA, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA5, LDA, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB5, LDA, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xAD, LDA, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBD, LDA, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xB9, LDA, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xA1, LDA, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xB1, LDA, 2, 5, AddressingMode::IndirectY),
+
+    OpCode::new(0xA2, LDX, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA6, LDX, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB6, LDX, 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0xAE, LDX, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBE, LDX, 3, 4, AddressingMode::AbsoluteY),
+
+    OpCode::new(0xA0, LDY, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA4, LDY, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB4, LDY, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xAC, LDY, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBC, LDY, 3, 4, AddressingMode::AbsoluteX),
+
+    /* ------ ADDING SUBTRACTING INSTRUCTIONS ------ */
+    OpCode::new(0x69, ADC, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x65, ADC, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x75, ADC, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x6D, ADC, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x7D, ADC, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x79, ADC, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x61, ADC, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x71, ADC, 2, 5, AddressingMode::IndirectY),
+
+    // subtract with carry
+    OpCode::new(0xE9, SBC, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE5, SBC, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xF5, SBC, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xED, SBC, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xFD, SBC, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xF9, SBC, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xE1, SBC, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xF1, SBC, 2, 5, AddressingMode::IndirectY),
+
+    /* ------ LOGICAL BIT OPERATIONS ------ */
+
+    // and - logical and
+    OpCode::new(0x29, AND, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x25, AND, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x35, AND, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x2D, AND, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x3D, AND, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x39, AND, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x21, AND, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x31, AND, 2, 5, AddressingMode::IndirectY),
+
+    // shift right (>>)
+    OpCode::new(0x4A, LSR, 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x46, LSR, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x56, LSR, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x4E, LSR, 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5E, LSR, 3, 7, AddressingMode::AbsoluteX),
+
+    // shift left (<<)
+    OpCode::new(0x0A, ASL, 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x06, ASL, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x16, ASL, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x0E, ASL, 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1E, ASL, 3, 7, AddressingMode::AbsoluteX),
+
+    // Logical Inclusive OR
+    OpCode::new(0x09, ORA, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x05, ORA, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x15, ORA, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x0D, ORA, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x1D, ORA, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x19, ORA, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x01, ORA, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x11, ORA, 2, 5, AddressingMode::IndirectY),
+
+    // Exclusive or
+    OpCode::new(0x49, EOR, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x45, EOR, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x55, EOR, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x4D, EOR, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x5D, EOR, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x59, EOR, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x41, EOR, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x51, EOR, 2, 5, AddressingMode::IndirectY),
+
+    /* ------ BRANCH OPERATIONS ------ */
+    // A lot of branch operations that depend on cpu flags
+    OpCode::new(0x90, BCC, 2, 2, AddressingMode::Relative),
+    OpCode::new(0xB0, BCS, 2, 2, AddressingMode::Relative),
+    OpCode::new(0xF0, BEQ, 2, 2, AddressingMode::Relative),
+    OpCode::new(0xD0, BNE, 2, 2, AddressingMode::Relative),
+    OpCode::new(0x10, BPL, 2, 2, AddressingMode::Relative),
+    OpCode::new(0x30, BMI, 2, 2, AddressingMode::Relative),
+    OpCode::new(0x50, BVC, 2, 2, AddressingMode::Relative),
+    OpCode::new(0x70, BVS, 2, 2, AddressingMode::Relative),
+
+    // jump
+    OpCode::new(0x4C, JMP, 3, 3, AddressingMode::Absolute),
+    OpCode::new(0x6C, JMP, 3, 5, AddressingMode::Indirect),
+
+    // jump to subroutine
+    OpCode::new(0x20, JSR, 3, 6, AddressingMode::Absolute),
+
+    // return from subroutine
+    OpCode::new(0x60, RTS, 1, 6, AddressingMode::NoneAddressing),
+
+    /* ------ STATUS OPERATIONS ------ */
+    // clearing flags
+    OpCode::new(0x18, CLC, 1, 2, AddressingMode::NoneAddressing), // carry
+    OpCode::new(0xD8, CLD, 1, 2, AddressingMode::NoneAddressing), // decimal
+    OpCode::new(0x58, CLI, 1, 2, AddressingMode::NoneAddressing), // interrupt
+    OpCode::new(0xB8, CLV, 1, 2, AddressingMode::NoneAddressing), // overflow
+
+    // setting flags
+    OpCode::new(0x38, SEC, 1, 2, AddressingMode::NoneAddressing), // carry
+    OpCode::new(0xF8, SED, 1, 2, AddressingMode::NoneAddressing), // decimal
+    OpCode::new(0x78, SEI, 1, 2, AddressingMode::NoneAddressing), // interrupt
+
+    /* ------ COMPARING INSTRUCTIONS ------ */
+    OpCode::new(0xC9, CMP, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC5, CMP, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xD5, CMP, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xCD, CMP, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xDD, CMP, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xD9, CMP, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xC1, CMP, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xD1, CMP, 2, 5, AddressingMode::IndirectY),
+
+    // Compare x register
+    OpCode::new(0xE0, CPX, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE4, CPX, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xEC, CPX, 3, 4, AddressingMode::Absolute),
+
+    // Compare y register
+    OpCode::new(0xC0, CPY, 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC4, CPY, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xCC, CPY, 3, 4, AddressingMode::Absolute),
+
+    // bit test
+    OpCode::new(0x24, BIT, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x2C, BIT, 3, 4, AddressingMode::Absolute),
+
+    /* ------ TRANSFER INSTRUCTIONS ------ */
+    OpCode::new(0xAA, TAX, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xA8, TAY, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xBA, TSX, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x8A, TXA, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x9A, TXS, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x98, TYA, 1, 2, AddressingMode::NoneAddressing),
+
+    /* ------ INCREMENT AND DECREMENT INSTRUCTIONS ------ */
+    OpCode::new(0xE6, INC, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xF6, INC, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xEE, INC, 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xFE, INC, 3, 7, AddressingMode::AbsoluteX),
+
+    OpCode::new(0xE8, INX, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xC8, INY, 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xC6, DEC, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xD6, DEC, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xCE, DEC, 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xDE, DEC, 3, 7, AddressingMode::AbsoluteX),
+
+    OpCode::new(0xCA, DEX, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x88, DEY, 1, 2, AddressingMode::NoneAddressing),
+
+    /* ------ STACK OPERATIONS ------ */
+    OpCode::new(0x48, PHA, 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x08, PHP, 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x68, PLA, 1, 4, AddressingMode::NoneAddressing),
+    OpCode::new(0x28, PLP, 1, 4, AddressingMode::NoneAddressing),
+
+    /* ------ ROTATE OPERATIONS ------ */
+    OpCode::new(0x2A, ROL, 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x26, ROL, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x36, ROL, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x2E, ROL, 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3E, ROL, 3, 7, AddressingMode::AbsoluteX),
+
+    // Rotate right
+    OpCode::new(0x6A, ROR, 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x66, ROR, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x76, ROR, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x6E, ROR, 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7E, ROR, 3, 7, AddressingMode::AbsoluteX),
+
+    /* ------ STORE OPERATIONS ------ */
+    // store x in a memory address
+    OpCode::new(0x86, STX, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x96, STX, 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0x8E, STX, 3, 4, AddressingMode::Absolute),
+
+    // store y in a memory address
+    OpCode::new(0x84, STY, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x94, STY, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x8C, STY, 3, 4, AddressingMode::Absolute),
+
+    // store a in a memory address
+    OpCode::new(0x85, STA, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x95, STA, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x8D, STA, 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9D, STA, 3, 5, AddressingMode::AbsoluteX),
+    OpCode::new(0x99, STA, 3, 5, AddressingMode::AbsoluteY),
+    OpCode::new(0x81, STA, 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x91, STA, 2, 6, AddressingMode::IndirectY),
+
+    /* ------ NO OPERATION ------ */
+    OpCode::new(0xEA, NOP, 1, 2, AddressingMode::NoneAddressing),
+
+    /* ------ BREAK AND INTERRUPT OPERATIONS ------ */
+    OpCode::new(0x00, BRK, 1, 7, AddressingMode::NoneAddressing),
+    OpCode::new(0x40, RTI, 1, 6, AddressingMode::NoneAddressing),
+
+    /* ------ UNOFFICIAL OPERATIONS ------ */
+    // the stable undocumented opcodes, commercial games and nestest both use these
+
+    // no operations that still read their operand
+    OpCode::new_illegal(0x1A, NOP, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x3A, NOP, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x5A, NOP, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x7A, NOP, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0xDA, NOP, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0xFA, NOP, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x80, NOP, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0x82, NOP, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0x89, NOP, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0xC2, NOP, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0xE2, NOP, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0x04, NOP, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x44, NOP, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x64, NOP, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x14, NOP, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x34, NOP, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x54, NOP, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x74, NOP, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0xD4, NOP, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0xF4, NOP, 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x0C, NOP, 3, 4, AddressingMode::Absolute),
+    OpCode::new_illegal(0x1C, NOP, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0x3C, NOP, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0x5C, NOP, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0x7C, NOP, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0xDC, NOP, 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0xFC, NOP, 3, 4, AddressingMode::AbsoluteX),
+
+    // load a and x with the same value
+    OpCode::new_illegal(0xA7, LAX, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0xB7, LAX, 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new_illegal(0xAF, LAX, 3, 4, AddressingMode::Absolute),
+    OpCode::new_illegal(0xBF, LAX, 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new_illegal(0xA3, LAX, 2, 6, AddressingMode::IndirectX),
+    OpCode::new_illegal(0xB3, LAX, 2, 5, AddressingMode::IndirectY),
+
+    // store a & x
+    OpCode::new_illegal(0x87, SAX, 2, 3, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x97, SAX, 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new_illegal(0x8F, SAX, 3, 4, AddressingMode::Absolute),
+    OpCode::new_illegal(0x83, SAX, 2, 6, AddressingMode::IndirectX),
+
+    // same as the official immediate sbc
+    OpCode::new_illegal(0xEB, SBC, 2, 2, AddressingMode::Immediate),
+
+    // decrement memory then compare with a
+    OpCode::new_illegal(0xC7, DCP, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0xD7, DCP, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0xCF, DCP, 3, 6, AddressingMode::Absolute),
+    OpCode::new_illegal(0xDF, DCP, 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0xDB, DCP, 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new_illegal(0xC3, DCP, 2, 8, AddressingMode::IndirectX),
+    OpCode::new_illegal(0xD3, DCP, 2, 8, AddressingMode::IndirectY),
+
+    // increment memory then subtract it from a
+    OpCode::new_illegal(0xE7, ISB, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0xF7, ISB, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0xEF, ISB, 3, 6, AddressingMode::Absolute),
+    OpCode::new_illegal(0xFF, ISB, 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0xFB, ISB, 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new_illegal(0xE3, ISB, 2, 8, AddressingMode::IndirectX),
+    OpCode::new_illegal(0xF3, ISB, 2, 8, AddressingMode::IndirectY),
+
+    // shift memory left then or it into a
+    OpCode::new_illegal(0x07, SLO, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x17, SLO, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x0F, SLO, 3, 6, AddressingMode::Absolute),
+    OpCode::new_illegal(0x1F, SLO, 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0x1B, SLO, 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new_illegal(0x03, SLO, 2, 8, AddressingMode::IndirectX),
+    OpCode::new_illegal(0x13, SLO, 2, 8, AddressingMode::IndirectY),
+
+    // rotate memory left then and it into a
+    OpCode::new_illegal(0x27, RLA, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x37, RLA, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x2F, RLA, 3, 6, AddressingMode::Absolute),
+    OpCode::new_illegal(0x3F, RLA, 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0x3B, RLA, 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new_illegal(0x23, RLA, 2, 8, AddressingMode::IndirectX),
+    OpCode::new_illegal(0x33, RLA, 2, 8, AddressingMode::IndirectY),
+
+    // shift memory right then exclusive or it into a
+    OpCode::new_illegal(0x47, SRE, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x57, SRE, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x4F, SRE, 3, 6, AddressingMode::Absolute),
+    OpCode::new_illegal(0x5F, SRE, 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0x5B, SRE, 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new_illegal(0x43, SRE, 2, 8, AddressingMode::IndirectX),
+    OpCode::new_illegal(0x53, SRE, 2, 8, AddressingMode::IndirectY),
+
+    // rotate memory right then add it to a
+    OpCode::new_illegal(0x67, RRA, 2, 5, AddressingMode::ZeroPage),
+    OpCode::new_illegal(0x77, RRA, 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new_illegal(0x6F, RRA, 3, 6, AddressingMode::Absolute),
+    OpCode::new_illegal(0x7F, RRA, 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new_illegal(0x7B, RRA, 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new_illegal(0x63, RRA, 2, 8, AddressingMode::IndirectX),
+    OpCode::new_illegal(0x73, RRA, 2, 8, AddressingMode::IndirectY),
+
+    // immediate combos
+    OpCode::new_illegal(0x0B, ANC, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0x2B, ANC, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0x4B, ALR, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0x6B, ARR, 2, 2, AddressingMode::Immediate),
+    OpCode::new_illegal(0xCB, AXS, 2, 2, AddressingMode::Immediate),
+
+    // these lock the CPU up until a reset
+    OpCode::new_illegal(0x02, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x12, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x22, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x32, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x42, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x52, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x62, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x72, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0x92, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0xB2, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0xD2, JAM, 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new_illegal(0xF2, JAM, 1, 2, AddressingMode::NoneAddressing),
+];
+
+/// Every opcode byte and what it decodes to, None for the ones that aren't emulated.
+/// The CPU decodes through this and the trace disassembles through it
+pub static OPCODES: [Option<OpCode>; 256] = {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < CPU_OPCODES.len() {
+        let opcode = CPU_OPCODES[i];
+        assert!(
+            table[opcode.code as usize].is_none(),
+            "an opcode is in CPU_OPCODES twice"
+        );
+        table[opcode.code as usize] = Some(opcode);
+        i += 1;
+    }
+    table
+};
+
+/// Looks up what an opcode byte decodes to
+pub fn lookup(code: u8) -> Option<&'static OpCode> {
+    OPCODES[code as usize].as_ref()
+}
 
+/// Finds the opcode for a mnemonic in an addressing mode, the way an assembler would.
+/// Official opcodes win over the unofficial duplicates like SBC #$xx at 0xEB
+pub fn find(mnemonic: &str, mode: AddressingMode) -> Option<&'static OpCode> {
+    OPCODES
+        .iter()
+        .flatten()
+        .filter(|opcode| opcode.mnemonic == mnemonic && opcode.addressing_mode == mode)
+        .min_by_key(|opcode| opcode.illegal)
 }
@@ -67,7 +67,7 @@ impl fmt::Display for ProcessorStatus {
             }
         }
 
-        return Ok(());
+        Ok(())
     }
 }
 
@@ -113,7 +113,7 @@ impl ProcessorStatus {
     pub fn has_flag_set(&self, flag: ProcessorStatusFlags) -> bool
     {
         let ProcessorStatus(status) = self;
-        return status & (flag as u8) != 0;
+        status & (flag as u8) != 0
     }
 
     /// Sets them all back to zero
@@ -132,13 +132,13 @@ mod tests {
         let mut status = ProcessorStatus(0);
         status.set_flag_true(ProcessorStatusFlags::CarryFlag);
 
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::CarryFlag), true);
+        assert!(status.has_flag_set(ProcessorStatusFlags::CarryFlag));
 
         status.set_flag_true(ProcessorStatusFlags::DecimalMode);
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::DecimalMode), true);
+        assert!(status.has_flag_set(ProcessorStatusFlags::DecimalMode));
 
         status.set_flag_false(ProcessorStatusFlags::DecimalMode);
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::DecimalMode), false);
+        assert!(!status.has_flag_set(ProcessorStatusFlags::DecimalMode));
     }
 
     #[test]
@@ -146,10 +146,10 @@ mod tests {
     {
         let mut status = ProcessorStatus(0);
         status.set_flag_true(ProcessorStatusFlags::BreakCommand);
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::BreakCommand), true);
+        assert!(status.has_flag_set(ProcessorStatusFlags::BreakCommand));
 
         status.toggle_flag(ProcessorStatusFlags::BreakCommand);
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::BreakCommand), false);
+        assert!(!status.has_flag_set(ProcessorStatusFlags::BreakCommand));
     }
 
     #[test]
@@ -157,10 +157,10 @@ mod tests {
         let mut status = ProcessorStatus(0);
 
         status.update_zero_and_negative_flags(2);
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::ZeroFlag), false);
+        assert!(!status.has_flag_set(ProcessorStatusFlags::ZeroFlag));
 
         status.update_zero_and_negative_flags(0);
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::ZeroFlag), true);
+        assert!(status.has_flag_set(ProcessorStatusFlags::ZeroFlag));
     }
 
     #[test]
@@ -168,9 +168,9 @@ mod tests {
         let mut status = ProcessorStatus(0);
 
         status.update_zero_and_negative_flags(0b1000_0010); // this is the binary representation of a u8 with a sign bit (holds -2)
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::Negative), true);
+        assert!(status.has_flag_set(ProcessorStatusFlags::Negative));
 
         status.update_zero_and_negative_flags(30);
-        assert_eq!(status.has_flag_set(ProcessorStatusFlags::Negative), false);
+        assert!(!status.has_flag_set(ProcessorStatusFlags::Negative));
     }
 }
@@ -0,0 +1,567 @@
+//! Counts where the cycles go while a program runs.
+//!
+//! The profiler steps the CPU itself and sorts the cycles of every instruction three ways
+//! - by the address of the instruction, for a histogram of the hot spots
+//! - into a call tree that follows JSR and RTS, with interrupts and BRK as calls into
+//!   their handler and RTI as the way back. Every subroutine gets its inclusive cycles,
+//!   the ones spent in it and everything it called, and its exclusive ones
+//! - by frame, worked out from the cycle count the same way `trace::ppu_frame` does
+//!   until there is a PPU to ask
+//!
+//! A return only pops the calls whose stack pointer it gets back to, so code that
+//! pushes an address and uses RTS to jump doesn't unwind the tree.
+//! The report comes out as text or in the collapsed stack format flamegraph tools read,
+//! one `outer;inner;innermost cycles` line per path through the tree
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::cpu::cpu::CPU;
+use crate::cpu::error::EmuError;
+use crate::cpu::memory::Mem;
+use crate::cpu::opcodes::{Instruction, OpCode};
+use crate::cpu::step::Step;
+use crate::cpu::trace;
+
+/// How many hot spots the text report lists
+const HOT_SPOTS_SHOWN: usize = 20;
+
+/// How many subroutines the text report lists for each frame
+const SUBROUTINES_PER_FRAME: usize = 3;
+
+/// How often one instruction ran and the cycles it took
+#[derive(Debug, Clone, Copy)]
+pub struct Hits {
+    pub opcode: &'static OpCode,
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// The totals for one subroutine over all the places it was called from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subroutine {
+    pub address: u16,
+    pub calls: u64,
+
+    /// Cycles spent in the subroutine and everything it called.
+    /// A recursive call isn't counted twice
+    pub inclusive: u64,
+
+    /// Cycles spent in the subroutine's own instructions
+    pub exclusive: u64,
+}
+
+/// What happened during one frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    pub number: u64,
+    pub cycles: u64,
+    pub instructions: u64,
+
+    /// Exclusive cycles by subroutine
+    pub subroutines: BTreeMap<u16, u64>,
+}
+
+/// One place in the call tree, the same subroutine called from two places gets two
+#[derive(Debug, Clone)]
+struct Node {
+    address: u16,
+    parent: Option<usize>,
+    children: BTreeMap<u16, usize>,
+    calls: u64,
+    cycles: u64,
+}
+
+/// A call that hasn't returned yet
+#[derive(Debug, Clone, Copy)]
+struct Call {
+    node: usize,
+
+    /// The stack pointer before the call, the return brings it back here
+    stack_pointer: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    hot_spots: HashMap<u16, Hits>,
+    nodes: Vec<Node>,
+    calls: Vec<Call>,
+    frames: Vec<Frame>,
+    cycles: u64,
+    instructions: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            hot_spots: HashMap::new(),
+            nodes: Vec::new(),
+            calls: Vec::new(),
+            frames: Vec::new(),
+            cycles: 0,
+            instructions: 0,
+        }
+    }
+
+    /// Executes one instruction and counts its cycles
+    pub fn step<M: Mem>(&mut self, cpu: &mut CPU<M>) -> Result<Step, EmuError> {
+        let stack_pointer = cpu.stack_pointer;
+        let frame = trace::ppu_frame(cpu.current_cycle);
+        let step = cpu.step()?;
+
+        if self.nodes.is_empty() {
+            self.nodes.push(Node::new(step.address, None));
+            self.calls.push(Call {
+                node: 0,
+                stack_pointer,
+            });
+        }
+
+        // the handler's first instruction ran in the same step, so it goes in the call
+        if step.interrupt.is_some() {
+            self.call(step.address, stack_pointer);
+        }
+        let node = self.calls.last().unwrap().node;
+        let address = self.nodes[node].address;
+
+        self.nodes[node].cycles += step.cycles;
+        self.cycles += step.cycles;
+        self.instructions += 1;
+
+        let hits = self.hot_spots.entry(step.address).or_insert(Hits {
+            opcode: step.opcode,
+            count: 0,
+            cycles: 0,
+        });
+        hits.count += 1;
+        hits.cycles += step.cycles;
+
+        if self.frames.last().map(|last| last.number) != Some(frame) {
+            self.frames.push(Frame {
+                number: frame,
+                ..Frame::default()
+            });
+        }
+        let totals = self.frames.last_mut().unwrap();
+        totals.cycles += step.cycles;
+        totals.instructions += 1;
+        *totals.subroutines.entry(address).or_default() += step.cycles;
+
+        match step.opcode.instruction {
+            Instruction::JSR | Instruction::BRK if step.interrupt.is_none() => {
+                self.call(cpu.program_counter, stack_pointer);
+            }
+            Instruction::RTS | Instruction::RTI => {
+                // the outermost call never returns, there is nothing above it
+                while self.calls.len() > 1
+                    && self.calls.last().unwrap().stack_pointer <= cpu.stack_pointer
+                {
+                    self.calls.pop();
+                }
+            }
+            _ => {}
+        }
+
+        Ok(step)
+    }
+
+    /// Runs until at least `cycles` more cycles have gone by, see `CPU::run_for_cycles`
+    pub fn run_for_cycles<M: Mem>(
+        &mut self,
+        cpu: &mut CPU<M>,
+        cycles: u64,
+    ) -> Result<(), EmuError> {
+        let end = cpu.current_cycle + cycles;
+        while cpu.current_cycle < end {
+            self.step(cpu)?;
+        }
+        Ok(())
+    }
+
+    /// Enters the subroutine at `address` from the call on top of the stack
+    fn call(&mut self, address: u16, stack_pointer: u8) {
+        let parent = self.calls.last().unwrap().node;
+        let node = match self.nodes[parent].children.get(&address) {
+            Some(&node) => node,
+            None => {
+                self.nodes.push(Node::new(address, Some(parent)));
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(address, node);
+                node
+            }
+        };
+
+        self.nodes[node].calls += 1;
+        self.calls.push(Call {
+            node,
+            stack_pointer,
+        });
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The instructions that took the most cycles first
+    pub fn hot_spots(&self) -> Vec<(u16, Hits)> {
+        let mut hot_spots: Vec<(u16, Hits)> =
+            self.hot_spots.iter().map(|(&a, &h)| (a, h)).collect();
+        hot_spots.sort_by_key(|&(address, hits)| (std::cmp::Reverse(hits.cycles), address));
+        hot_spots
+    }
+
+    /// Every subroutine in the call tree, the most inclusive cycles first.
+    /// Where the profile started counts as a subroutine called once
+    pub fn subroutines(&self) -> Vec<Subroutine> {
+        let inclusive = self.inclusive_cycles();
+        let mut totals: BTreeMap<u16, Subroutine> = BTreeMap::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            let total = totals.entry(node.address).or_insert(Subroutine {
+                address: node.address,
+                calls: 0,
+                inclusive: 0,
+                exclusive: 0,
+            });
+            total.calls += node.calls.max(node.parent.is_none() as u64);
+            total.exclusive += node.cycles;
+            if !self.recursive(index) {
+                total.inclusive += inclusive[index];
+            }
+        }
+
+        let mut subroutines: Vec<Subroutine> = totals.into_values().collect();
+        subroutines.sort_by_key(|total| (std::cmp::Reverse(total.inclusive), total.address));
+        subroutines
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// The cycles of every node with everything under it
+    fn inclusive_cycles(&self) -> Vec<u64> {
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|node| node.cycles).collect();
+        // children always come after their parent
+        for index in (1..self.nodes.len()).rev() {
+            if let Some(parent) = self.nodes[index].parent {
+                inclusive[parent] += inclusive[index];
+            }
+        }
+        inclusive
+    }
+
+    /// True when the node's subroutine is already further up the tree
+    fn recursive(&self, index: usize) -> bool {
+        let address = self.nodes[index].address;
+        let mut parent = self.nodes[index].parent;
+        while let Some(node) = parent {
+            if self.nodes[node].address == address {
+                return true;
+            }
+            parent = self.nodes[node].parent;
+        }
+        false
+    }
+
+    /// The path from the top of the tree down to the node, names joined with `;`
+    fn stack(&self, index: usize, labels: &BTreeMap<u16, String>) -> String {
+        let mut names = vec![name(self.nodes[index].address, labels)];
+        let mut parent = self.nodes[index].parent;
+        while let Some(node) = parent {
+            names.push(name(self.nodes[node].address, labels));
+            parent = self.nodes[node].parent;
+        }
+        names.reverse();
+        names.join(";")
+    }
+
+    /// One line per path through the call tree with the cycles spent at the end of it,
+    /// e.g. `reset;sub_C100;sub_C200 1520`. This is the format `flamegraph.pl` and
+    /// `inferno-flamegraph` take
+    pub fn collapsed(&self, labels: &BTreeMap<u16, String>) -> String {
+        let mut lines: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.cycles > 0)
+            .map(|(index, node)| format!("{} {}", self.stack(index, labels), node.cycles))
+            .collect();
+        lines.sort();
+
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    /// The subroutines, the hot spots, the call tree and the frames as text tables
+    pub fn report(&self, labels: &BTreeMap<u16, String>) -> String {
+        let mut out = String::new();
+        let percent = |cycles: u64| 100.0 * cycles as f64 / self.cycles.max(1) as f64;
+
+        writeln!(
+            out,
+            "{} cycles, {} instructions",
+            self.cycles, self.instructions
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "\n{:24} {:>8} {:>12} {:>7} {:>12} {:>7}",
+            "subroutine", "calls", "inclusive", "%", "exclusive", "%"
+        )
+        .unwrap();
+        for total in self.subroutines() {
+            writeln!(
+                out,
+                "{:24} {:>8} {:>12} {:>6.1}% {:>12} {:>6.1}%",
+                name(total.address, labels),
+                total.calls,
+                total.inclusive,
+                percent(total.inclusive),
+                total.exclusive,
+                percent(total.exclusive)
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "\n{:24} {:>8} {:>12} {:>7}",
+            "hot spot", "count", "cycles", "%"
+        )
+        .unwrap();
+        for (address, hits) in self.hot_spots().into_iter().take(HOT_SPOTS_SHOWN) {
+            writeln!(
+                out,
+                "{:24} {:>8} {:>12} {:>6.1}%",
+                format!("{:04X}  {}", address, hits.opcode.mnemonic),
+                hits.count,
+                hits.cycles,
+                percent(hits.cycles)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\ncall tree, inclusive and exclusive cycles").unwrap();
+        if !self.nodes.is_empty() {
+            self.write_tree(&mut out, 0, 0, &self.inclusive_cycles(), labels);
+        }
+
+        writeln!(out, "\nframes").unwrap();
+        for frame in &self.frames {
+            let mut busiest: Vec<(&u16, &u64)> = frame.subroutines.iter().collect();
+            busiest.sort_by_key(|&(&address, &cycles)| (std::cmp::Reverse(cycles), address));
+            let busiest: Vec<String> = busiest
+                .into_iter()
+                .take(SUBROUTINES_PER_FRAME)
+                .map(|(&address, cycles)| format!("{} {}", name(address, labels), cycles))
+                .collect();
+
+            writeln!(
+                out,
+                "{:>6} {:>8} cycles {:>8} instructions  {}",
+                frame.number,
+                frame.cycles,
+                frame.instructions,
+                busiest.join(", ")
+            )
+            .unwrap();
+        }
+
+        out
+    }
+
+    fn write_tree(
+        &self,
+        out: &mut String,
+        index: usize,
+        depth: usize,
+        inclusive: &[u64],
+        labels: &BTreeMap<u16, String>,
+    ) {
+        let node = &self.nodes[index];
+        let calls = match node.parent {
+            Some(_) => format!(" x{}", node.calls),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            "{:indent$}{}{} {} {}",
+            "",
+            name(node.address, labels),
+            calls,
+            inclusive[index],
+            node.cycles,
+            indent = depth * 2
+        )
+        .unwrap();
+
+        for &child in node.children.values() {
+            self.write_tree(out, child, depth + 1, inclusive, labels);
+        }
+    }
+}
+
+impl Node {
+    fn new(address: u16, parent: Option<usize>) -> Self {
+        Node {
+            address,
+            parent,
+            children: BTreeMap::new(),
+            calls: 0,
+            cycles: 0,
+        }
+    }
+}
+
+/// The label for the address, or the address in hex when it doesn't have one
+fn name(address: u16, labels: &BTreeMap<u16, String>) -> String {
+    match labels.get(&address) {
+        Some(label) => label.clone(),
+        None => format!("${:04X}", address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::asm;
+    use crate::cpu::memory::MemoryMap;
+
+    // fill runs 4 times a pass, clear once and calls fill itself
+    const PROGRAM: &str = "
+        start:  LDY #$04
+        again:  JSR fill
+                DEY
+                BNE again
+                JSR clear
+                JMP start
+        fill:   LDX #$03
+        loop:   DEX
+                BNE loop
+                RTS
+        clear:  LDA #$00
+                JSR fill
+                RTS
+    ";
+
+    const START: u16 = 0x0600;
+    const FILL: u16 = 0x060E;
+    const CLEAR: u16 = 0x0614;
+
+    fn cpu() -> CPU<MemoryMap> {
+        let program = asm::assemble(PROGRAM, 0x0600).unwrap();
+        let mut memory = MemoryMap::new();
+        memory.memory[0x0600..0x0600 + program.len()].copy_from_slice(&program);
+        memory.write_mem_u16(0xFFFC, 0x0600);
+
+        let mut cpu = CPU::new(memory);
+        cpu.reset();
+        cpu
+    }
+
+    /// Runs whole passes of the loop, the profile ends on the JMP back to the start
+    fn profile(passes: usize) -> Profiler {
+        let mut cpu = cpu();
+        let mut profiler = Profiler::new();
+        for _ in 0..passes {
+            while profiler.step(&mut cpu).unwrap().opcode.instruction != Instruction::JMP {}
+        }
+        profiler
+    }
+
+    #[test]
+    fn subroutine_cycles() {
+        let profiler = profile(1);
+
+        // LDX 2, DEX 2 and BNE 3 twice, DEX 2 and BNE 2, RTS 6
+        let fill = 2 + 2 * (2 + 3) + 2 + 2 + 6;
+        // LDA 2, JSR 6, RTS 6
+        let clear = 2 + 6 + 6;
+        // LDY 2, 4 times JSR 6, DEY 2 and BNE, the last not taken, then JSR 6 and JMP 3
+        let start = 2 + 4 * (6 + 2 + 3) - 1 + 6 + 3;
+
+        let subroutines = profiler.subroutines();
+        assert_eq!(
+            subroutines,
+            vec![
+                Subroutine {
+                    address: START,
+                    calls: 1,
+                    inclusive: start + clear + 5 * fill,
+                    exclusive: start,
+                },
+                Subroutine {
+                    address: FILL,
+                    calls: 5,
+                    inclusive: 5 * fill,
+                    exclusive: 5 * fill,
+                },
+                Subroutine {
+                    address: CLEAR,
+                    calls: 1,
+                    inclusive: clear + fill,
+                    exclusive: clear,
+                },
+            ]
+        );
+        assert_eq!(profiler.cycles(), start + clear + 5 * fill);
+    }
+
+    #[test]
+    fn hot_spots() {
+        let profiler = profile(2);
+        let (address, hits) = profiler.hot_spots()[0];
+
+        // the BNE in fill's loop is taken twice a call, then falls through
+        assert_eq!(address, 0x0611);
+        assert_eq!(hits.opcode.mnemonic, "BNE");
+        assert_eq!(hits.count, 2 * 5 * 3);
+        assert_eq!(hits.cycles, 2 * 5 * (3 + 3 + 2));
+    }
+
+    #[test]
+    fn collapsed_stacks() {
+        let profiler = profile(1);
+        let labels = BTreeMap::from([(START, "start".to_string()), (FILL, "fill".to_string())]);
+
+        assert_eq!(
+            profiler.collapsed(&labels),
+            "start 54\nstart;$0614 14\nstart;$0614;fill 22\nstart;fill 88\n"
+        );
+    }
+
+    #[test]
+    fn interrupts_are_calls() {
+        let mut cpu = cpu();
+        cpu.write_mem_u16(0xFFFA, CLEAR);
+        let mut profiler = Profiler::new();
+
+        profiler.step(&mut cpu).unwrap();
+        cpu.trigger_nmi();
+        // LDA, JSR fill, 8 instructions of fill then the RTS out of clear
+        for _ in 0..11 {
+            profiler.step(&mut cpu).unwrap();
+        }
+
+        let subroutines = profiler.subroutines();
+        let clear = subroutines.iter().find(|s| s.address == CLEAR).unwrap();
+        assert_eq!(clear.calls, 1);
+        assert_eq!(clear.exclusive, 7 + 2 + 6 + 6);
+
+        // the RTS out of clear doesn't return from the NMI, it is still on the stack
+        assert_eq!(profiler.calls.len(), 2);
+    }
+}
@@ -6,6 +6,7 @@ const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
 
 #[derive(Debug, PartialEq)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
@@ -29,14 +30,14 @@ impl Rom {
     pub fn new_from_file(path: String) -> Result<Rom, String> {
         let contents = match fs::read(path) {
             Ok(res) => res,
-            Err(err) => return Err("new_from_file was not able to read rom from file".to_string())
+            Err(_) => return Err("new_from_file was not able to read rom from file".to_string())
         };
 
-        return Ok(Rom::new(&contents).unwrap());
+        Rom::new(&contents)
     }
 
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw[0..4] != NES_TAG {
             return Err("File is not in iNES format".to_string());
         }
 
@@ -63,14 +64,14 @@ impl Rom {
         let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
         let chr_rom_start = prg_rom_start + prg_rom_size;
 
-        println!("prg strt {}", prg_rom_start);
+        log::debug!("prg strt {}", prg_rom_start);
 
         Ok(Rom {
             prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
             chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
 
-            mapper: mapper,
-            screen_mirroring: screen_mirroring,
+            mapper,
+            screen_mirroring,
         })
     }
 }
@@ -110,7 +111,7 @@ pub mod test {
             ],
             trainer: None,
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         Rom::new(&test_rom).unwrap()
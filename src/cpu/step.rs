@@ -0,0 +1,40 @@
+use crate::cpu::interrupt::InterruptType;
+use crate::cpu::opcodes::OpCode;
+
+/// What happened during one call to CPU::step
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    /// Where the instruction was fetched from
+    pub address: u16,
+
+    /// The instruction that was executed
+    pub opcode: &'static OpCode,
+
+    /// The interrupt that was serviced before the instruction, if any
+    pub interrupt: Option<InterruptType>,
+
+    /// Cycles taken by the instruction and the interrupt together
+    pub cycles: u64,
+}
+
+/// Why one of the CPU::run_* functions gave control back to the caller
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StopReason {
+    /// The program counter reached a breakpoint, the instruction there has not run yet
+    Breakpoint(u16),
+
+    /// A BRK instruction was executed, the program counter is at the handler
+    Brk,
+
+    /// A JAM opcode at this address locked the CPU up, it needs a reset
+    Jam(u16),
+
+    /// The cycle budget given to run_for_cycles ran out
+    CycleBudget,
+
+    /// The predicate given to run_until returned true
+    Condition,
+
+    /// The PPU finished drawing a frame
+    FrameComplete,
+}
@@ -0,0 +1,431 @@
+//! Names for addresses, loaded from the label files the assemblers and other emulators write.
+//!
+//! - ca65 `.dbg`, what `ld65 --dbgfile` writes. The labels are taken, the `equ` symbols
+//!   aren't because most of them are constants and not addresses. Cheap locals are
+//!   named after the label they belong to, `reset@loop`
+//! - FCEUX `.nl`, one file per 16K bank named `game.nes.0.nl`, `game.nes.1.nl` and so on,
+//!   and `game.nes.ram.nl` for everything outside the cartridge. A line is `$C000#name#comment`
+//! - Mesen `.mlb`, a line is `P:0123:name:comment` with the memory type in front.
+//!   PRG-ROM labels are given as an offset into the ROM, the rest as the address.
+//!   The Mesen 2 names for the types (`NesPrgRom`, `NesInternalRam`, ...) work too
+//!
+//! A label in PRG-ROM belongs to the bank it is in, the same address in another bank is
+//! something else. The rest of the emulator wants the labels as a map from the address to
+//! the name, `labels` makes one for whichever banks are mapped in
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::cpu::bus::Bus;
+use crate::cpu::disasm;
+use crate::cpu::rom::Rom;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// The iNES header in front of the PRG-ROM, ld65 counts its output offsets from the file start
+const HEADER_SIZE: usize = 16;
+
+/// Where save RAM and work RAM show up for the CPU
+const WORK_RAM: u16 = 0x6000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+
+    /// The 16K PRG bank the label is in, None for RAM and registers
+    pub bank: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    pub symbols: Vec<Symbol>,
+}
+
+/// Splits the `key=value,key="value"` list of a `.dbg` line, quoted values can hold commas
+fn dbg_fields(list: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut rest = list;
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let next = quoted[end..].trim_start_matches('"');
+                (&quoted[..end], next)
+            }
+            None => after.split_once(',').map_or((after, ""), |(v, n)| (v, n)),
+        };
+        fields.insert(key.trim(), value);
+        rest = next.trim_start_matches(',');
+    }
+
+    fields
+}
+
+/// Numbers in label files are hex, with `0x` in `.dbg` files and `$` in `.nl` files
+fn hex(text: &str) -> Option<usize> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+/// Where a PRG-ROM offset shows up for the CPU, laid out the way `disasm::banks` does it
+fn prg_address(offset: usize, prg_banks: usize) -> (u16, usize) {
+    let bank = offset / PRG_BANK_SIZE;
+    let base = if bank + 1 == prg_banks {
+        0xC000
+    } else {
+        0x8000
+    };
+    (base + (offset % PRG_BANK_SIZE) as u16, bank)
+}
+
+impl Symbols {
+    /// Reads the labels from a ca65 `.dbg` file
+    pub fn parse_dbg(text: &str) -> Result<Symbols, String> {
+        // segments give the offset in the ROM file, which is how the bank is found
+        let mut segments: HashMap<&str, (usize, Option<usize>)> = HashMap::new();
+        let mut labels = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let Some((kind, list)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let fields = dbg_fields(list.trim());
+            let field = |key: &str| {
+                fields
+                    .get(key)
+                    .copied()
+                    .ok_or_else(|| format!("line {}: {} has no {}", number + 1, kind, key))
+            };
+
+            match kind {
+                "seg" => {
+                    let start = hex(field("start")?)
+                        .ok_or_else(|| format!("line {}: bad segment start", number + 1))?;
+                    let offset = fields.get("ooffs").and_then(|offset| offset.parse().ok());
+                    segments.insert(field("id")?, (start, offset));
+                }
+                "sym" if fields.get("type") == Some(&"lab") => labels.push(fields),
+                _ => {}
+            }
+        }
+
+        let names: HashMap<&str, &str> = labels
+            .iter()
+            .filter_map(|fields| Some((*fields.get("id")?, *fields.get("name")?)))
+            .collect();
+
+        let mut symbols = Vec::new();
+        for fields in &labels {
+            let (Some(name), Some(value)) = (fields.get("name"), fields.get("val")) else {
+                continue;
+            };
+            let Some(value) = hex(value) else {
+                return Err(format!("{} has a bad value {}", name, value));
+            };
+
+            let name = match fields.get("parent").and_then(|parent| names.get(parent)) {
+                Some(parent) => format!("{}{}", parent, name),
+                None => name.to_string(),
+            };
+            let bank = fields
+                .get("seg")
+                .and_then(|seg| segments.get(seg))
+                .and_then(|&(start, offset)| (offset? + value).checked_sub(start))
+                .filter(|&offset| offset >= HEADER_SIZE)
+                .map(|offset| (offset - HEADER_SIZE) / PRG_BANK_SIZE);
+
+            symbols.push(Symbol {
+                name,
+                address: value as u16,
+                bank,
+            });
+        }
+
+        Ok(Symbols { symbols })
+    }
+
+    /// Reads an FCEUX `.nl` file, `bank` is the one the file is for, None for the RAM one
+    pub fn parse_nl(text: &str, bank: Option<usize>) -> Result<Symbols, String> {
+        let mut symbols = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, '#');
+            let address = parts.next().unwrap_or("");
+            let name = parts.next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+
+            // an array is `$0300/10`, the label goes on the start
+            let address = address.split('/').next().unwrap_or("");
+            let address = hex(address)
+                .filter(|&address| address <= 0xFFFF)
+                .ok_or_else(|| format!("line {}: {} is not an address", number + 1, address))?;
+
+            symbols.push(Symbol {
+                name: name.to_string(),
+                address: address as u16,
+                bank,
+            });
+        }
+
+        Ok(Symbols { symbols })
+    }
+
+    /// Reads a Mesen `.mlb` file. The PRG-ROM offsets need to know how many 16K banks
+    /// the ROM has to work out where the CPU sees them
+    pub fn parse_mlb(text: &str, prg_banks: usize) -> Result<Symbols, String> {
+        let mut symbols = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(4, ':');
+            let (Some(kind), Some(address), Some(name)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(format!("line {}: expected type:address:name", number + 1));
+            };
+            if name.is_empty() {
+                continue;
+            }
+
+            // a range is `0100-0120`, the label goes on the start
+            let start = address.split('-').next().unwrap_or("");
+            let offset = hex(start)
+                .ok_or_else(|| format!("line {}: {} is not an address", number + 1, start))?;
+
+            let (address, bank) = match kind {
+                "P" | "NesPrgRom" => {
+                    let (address, bank) = prg_address(offset, prg_banks);
+                    (address, Some(bank))
+                }
+                "R" | "NesInternalRam" | "G" | "NesMemory" => (offset as u16, None),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => (WORK_RAM + offset as u16, None),
+                // CHR and the rest aren't seen by the CPU
+                _ => continue,
+            };
+
+            symbols.push(Symbol {
+                name: name.to_string(),
+                address,
+                bank,
+            });
+        }
+
+        Ok(Symbols { symbols })
+    }
+
+    /// Loads every label file that sits next to the ROM: `game.dbg`, `game.mlb`
+    /// and the FCEUX `game.nes.*.nl` files
+    pub fn for_rom(rom_path: &str, rom: &Rom) -> Result<Symbols, String> {
+        let prg_banks = rom.prg_rom.len() / PRG_BANK_SIZE;
+        let path = Path::new(rom_path);
+        let read = |path: &Path| std::fs::read_to_string(path).ok();
+        let context = |path: &Path| {
+            let path = path.display().to_string();
+            move |error: String| format!("{}: {}", path, error)
+        };
+
+        let mut symbols = Symbols::default();
+
+        let dbg = path.with_extension("dbg");
+        if let Some(text) = read(&dbg) {
+            symbols.extend(Symbols::parse_dbg(&text).map_err(context(&dbg))?);
+        }
+
+        let banks = (0..prg_banks).map(|bank| (bank.to_string(), Some(bank)));
+        for (suffix, bank) in banks.chain([("ram".to_string(), None)]) {
+            let nl = Path::new(&format!("{}.{}.nl", rom_path, suffix)).to_path_buf();
+            if let Some(text) = read(&nl) {
+                symbols.extend(Symbols::parse_nl(&text, bank).map_err(context(&nl))?);
+            }
+        }
+
+        let mlb = path.with_extension("mlb");
+        if let Some(text) = read(&mlb) {
+            symbols.extend(Symbols::parse_mlb(&text, prg_banks).map_err(context(&mlb))?);
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn extend(&mut self, other: Symbols) {
+        self.symbols.extend(other.symbols);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// The labels for the banks that are mapped in. `bank_of` says which PRG bank the CPU
+    /// sees at an address, or None when it isn't ROM or nothing is known about banks.
+    /// The first label loaded for an address wins
+    pub fn labels(&self, bank_of: impl Fn(u16) -> Option<usize>) -> BTreeMap<u16, String> {
+        let mut labels = BTreeMap::new();
+
+        for symbol in &self.symbols {
+            let mapped = match (symbol.bank, bank_of(symbol.address)) {
+                (Some(bank), Some(mapped)) => bank == mapped,
+                _ => true,
+            };
+            if mapped {
+                labels
+                    .entry(symbol.address)
+                    .or_insert_with(|| symbol.name.clone());
+            }
+        }
+
+        labels
+    }
+
+    /// The labels for RAM and registers and the ones in the given banks,
+    /// for when the banks are known up front like in a listing of the ROM
+    pub fn bank_labels(&self, banks: &[usize]) -> BTreeMap<u16, String> {
+        let mut labels = BTreeMap::new();
+
+        for symbol in &self.symbols {
+            if symbol.bank.is_none_or(|bank| banks.contains(&bank)) {
+                labels
+                    .entry(symbol.address)
+                    .or_insert_with(|| symbol.name.clone());
+            }
+        }
+
+        labels
+    }
+
+    /// Looks a label up by name
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+}
+
+/// Looks the address of a label up in a map made by `Symbols::labels` or `disasm::labels`
+pub fn address_of(labels: &BTreeMap<u16, String>, name: &str) -> Option<u16> {
+    labels
+        .iter()
+        .find(|(_, label)| *label == name)
+        .map(|(&address, _)| address)
+}
+
+/// Everything known about the names in a ROM: the labels from the symbol files next to it,
+/// then `disasm::labels` for the vector handlers and the places the code jumps to
+pub fn labels_for_rom(rom_path: &str, bus: &Bus) -> Result<BTreeMap<u16, String>, String> {
+    let mut labels = Symbols::for_rom(rom_path, bus.rom())?.labels(|addr| bus.prg_bank(addr));
+    for (address, name) in disasm::labels(bus) {
+        labels.entry(address).or_insert(name);
+    }
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBG: &str = "version\tmajor=2,minor=0
+info\tcsym=0,file=1,lib=0,line=4,mod=1,scope=1,seg=3,span=4,sym=5,type=2
+file\tid=0,name=\"game.s\",size=512,mtime=0x5F000000,mod=0
+seg\tid=0,name=\"ZEROPAGE\",start=0x000000,size=0x0002,addrsize=zeropage,type=rw
+seg\tid=1,name=\"CODE\",start=0x008000,size=0x0100,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16
+seg\tid=2,name=\"FIXED\",start=0x00C000,size=0x0100,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16400
+sym\tid=0,name=\"player_x\",addrsize=zeropage,size=1,scope=0,def=1,ref=5,val=0x0,seg=0,type=lab
+sym\tid=1,name=\"update\",addrsize=absolute,scope=0,def=2,ref=6,val=0x8010,seg=1,type=lab
+sym\tid=2,name=\"reset\",addrsize=absolute,scope=0,def=3,val=0xC000,seg=2,type=lab
+sym\tid=3,name=\"@loop\",addrsize=absolute,parent=2,scope=0,def=4,val=0xC005,seg=2,type=lab
+sym\tid=4,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=5,val=0x2000,type=equ
+";
+
+    #[test]
+    fn ca65_debug_info() {
+        let symbols = Symbols::parse_dbg(DBG).unwrap();
+        let found: Vec<(&str, u16, Option<usize>)> = symbols
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.address, symbol.bank))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("player_x", 0x0000, None),
+                ("update", 0x8010, Some(0)),
+                ("reset", 0xC000, Some(1)),
+                ("reset@loop", 0xC005, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn fceux_name_lists() {
+        let symbols = Symbols::parse_nl(
+            "$C000#reset#starts here\n$0300/10#buffer#\n\n$C010##\n",
+            Some(1),
+        )
+        .unwrap();
+        assert_eq!(
+            symbols.symbols,
+            vec![
+                Symbol {
+                    name: "reset".to_string(),
+                    address: 0xC000,
+                    bank: Some(1),
+                },
+                Symbol {
+                    name: "buffer".to_string(),
+                    address: 0x0300,
+                    bank: Some(1),
+                },
+            ]
+        );
+        assert!(Symbols::parse_nl("C0G0#oops#", None).is_err());
+    }
+
+    #[test]
+    fn mesen_labels() {
+        let text = "P:0010:update\nP:4000:reset:comment\nR:0000-0001:player_x\nG:2000:PPUCTRL\nS:0010:save\nP:4100::comment only\n";
+        let symbols = Symbols::parse_mlb(text, 2).unwrap();
+        let found: Vec<(&str, u16, Option<usize>)> = symbols
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.address, symbol.bank))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("update", 0x8010, Some(0)),
+                ("reset", 0xC000, Some(1)),
+                ("player_x", 0x0000, None),
+                ("PPUCTRL", 0x2000, None),
+                ("save", 0x6010, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_follow_the_mapped_banks() {
+        let symbols =
+            Symbols::parse_mlb("P:0010:in_bank_0\nP:4010:in_bank_1\nR:0010:zp\n", 3).unwrap();
+
+        // banks 0 and 1 both sit at $8000
+        let labels = symbols.labels(|addr| (addr >= 0x8000).then_some(1));
+        assert_eq!(labels.get(&0x8010).map(String::as_str), Some("in_bank_1"));
+        assert_eq!(labels.get(&0x0010).map(String::as_str), Some("zp"));
+        assert_eq!(address_of(&labels, "in_bank_1"), Some(0x8010));
+        assert_eq!(address_of(&labels, "in_bank_0"), None);
+    }
+}
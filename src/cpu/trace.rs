@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cpu::cpu::CPU;
+use crate::cpu::disasm;
+use crate::cpu::memory::Mem;
+use crate::cpu::opcodes;
+
+/// How many instructions the CPU keeps in its trace buffer to start with
+pub const TRACE_BUFFER_LENGTH: usize = 256;
+
+/// The PPU draws 341 dots per scanline and 262 scanlines per frame
+const DOTS_PER_SCANLINE: u64 = 341;
+const SCANLINES_PER_FRAME: u64 = 262;
+
+/// The PPU runs 3 dots for every CPU cycle
+const DOTS_PER_CPU_CYCLE: u64 = 3;
+
+/// Formats the instruction under the program counter the way nestest.log does, e.g.
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+/// The registers are shown as they are before the instruction runs
+pub fn trace<M: Mem>(cpu: &CPU<M>) -> String {
+    trace_with_labels(cpu, &BTreeMap::new())
+}
+
+/// The same line with labels in place of the addresses that have one, and the label of
+/// the instruction itself in front of it, e.g. `C000  78        reset: SEI`
+pub fn trace_with_labels<M: Mem>(cpu: &CPU<M>, labels: &BTreeMap<u16, String>) -> String {
+    let begin = cpu.program_counter;
+    let code = cpu.read_mem_u8(begin);
+
+    let (bytes, disassembly) = match opcodes::lookup(code) {
+        Some(opcode) => {
+            let prefix = if opcode.illegal { "*" } else { " " };
+            (
+                opcode.bytes,
+                format!("{}{}", prefix, opcode.to_string_with_labels(cpu, labels)),
+            )
+        }
+        None => (1, " ???".to_string()),
+    };
+
+    let hex_dump: Vec<String> = (0..bytes as u16)
+        .map(|i| format!("{:02X}", cpu.read_mem_u8(begin.wrapping_add(i))))
+        .collect();
+
+    let disassembly = match labels.get(&begin) {
+        Some(label) => format!(" {}: {}", label, disassembly.trim_start()),
+        None => disassembly,
+    };
+    let asm = format!("{:04X}  {:9}{}", begin, hex_dump.join(" "), disassembly);
+
+    let (scanline, dot) = ppu_position(cpu.current_cycle);
+
+    format!(
+        "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        asm.trim_end(),
+        cpu.accumulator,
+        cpu.index_register_x,
+        cpu.index_register_y,
+        cpu.processor_status.0,
+        cpu.stack_pointer,
+        scanline,
+        dot,
+        cpu.current_cycle
+    )
+}
+
+/// One instruction in the trace buffer, the registers as they were before it ran
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TraceEntry {
+    pub program_counter: u16,
+
+    /// The opcode and whatever follows it, filled in as the CPU fetches them.
+    /// Only the ones the instruction uses mean anything
+    pub bytes: [u8; 3],
+
+    pub accumulator: u8,
+    pub index_register_x: u8,
+    pub index_register_y: u8,
+    pub processor_status: u8,
+    pub stack_pointer: u8,
+    pub current_cycle: u64,
+}
+
+impl TraceEntry {
+    /// Copies the registers out of the CPU before it runs the instruction.
+    /// Peeking at the bytes would put reads on the bus the CPU doesn't make, see `fetched`
+    pub fn new<M: Mem>(cpu: &CPU<M>) -> Self {
+        TraceEntry {
+            program_counter: cpu.program_counter,
+            bytes: [0; 3],
+            accumulator: cpu.accumulator,
+            index_register_x: cpu.index_register_x,
+            index_register_y: cpu.index_register_y,
+            processor_status: cpu.processor_status.0,
+            stack_pointer: cpu.stack_pointer,
+            current_cycle: cpu.current_cycle,
+        }
+    }
+}
+
+/// The same line `trace` makes, less the memory the operand points at.
+/// That was only there when the instruction ran, so it isn't kept
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = disasm::decode(self.program_counter, |addr| {
+            self.bytes
+                .get(addr.wrapping_sub(self.program_counter) as usize)
+                .copied()
+        });
+        let disassembly = match line.opcode {
+            Some(opcode) if opcode.illegal => format!("*{}", line),
+            Some(_) => format!(" {}", line),
+            None => " ???".to_string(),
+        };
+
+        let hex_dump: Vec<String> = line
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let asm = format!(
+            "{:04X}  {:9}{}",
+            self.program_counter,
+            hex_dump.join(" "),
+            disassembly
+        );
+        let (scanline, dot) = ppu_position(self.current_cycle);
+
+        write!(
+            f,
+            "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            asm.trim_end(),
+            self.accumulator,
+            self.index_register_x,
+            self.index_register_y,
+            self.processor_status,
+            self.stack_pointer,
+            scanline,
+            dot,
+            self.current_cycle
+        )
+    }
+}
+
+/// The last instructions the CPU ran, oldest first. It is on all the time so a crash
+/// can be looked at without tracing the whole run, only the registers and bytes go in
+/// and the lines are made when it is dumped
+pub struct TraceBuffer {
+    /// All the slots are there from the start, so a push is just a copy
+    entries: Vec<TraceEntry>,
+    len: usize,
+
+    /// Where the next one goes, once it is full that is the oldest
+    next: usize,
+}
+
+impl TraceBuffer {
+    /// A capacity of 0 turns it off
+    pub fn new(capacity: usize) -> Self {
+        TraceBuffer {
+            entries: vec![TraceEntry::default(); capacity],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Adds the instruction, pushing the oldest one out once it is full
+    #[inline]
+    pub fn push(&mut self, entry: TraceEntry) {
+        if let Some(slot) = self.entries.get_mut(self.next) {
+            *slot = entry;
+            self.next += 1;
+            if self.next == self.entries.len() {
+                self.next = 0;
+            }
+            self.len = (self.len + 1).min(self.entries.len());
+        }
+    }
+
+    /// Fills in a byte of the instruction that was pushed last, as the CPU reads it
+    #[inline]
+    pub fn fetched(&mut self, addr: u16, data: u8) {
+        let last = match self.next {
+            0 => self.entries.len().wrapping_sub(1),
+            next => next - 1,
+        };
+        if let Some(entry) = self.entries.get_mut(last) {
+            let offset = addr.wrapping_sub(entry.program_counter) as usize;
+            if let Some(byte) = entry.bytes.get_mut(offset) {
+                *byte = data;
+            }
+        }
+    }
+
+    /// Oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        let (newer, older) = self.entries.split_at(self.next);
+        older[older.len() + newer.len() - self.len..]
+            .iter()
+            .chain(newer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
+
+/// One trace line per instruction
+impl fmt::Display for TraceBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in self.entries() {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// There is no PPU yet so its position is worked out from the CPU cycle count.
+/// This holds as long as rendering is off and no odd frames get their dot skipped
+pub fn ppu_position(cpu_cycle: u64) -> (u64, u64) {
+    let dots = cpu_cycle * DOTS_PER_CPU_CYCLE;
+    (
+        (dots / DOTS_PER_SCANLINE) % SCANLINES_PER_FRAME,
+        dots % DOTS_PER_SCANLINE,
+    )
+}
+
+/// Which frame the PPU is on, worked out the same way as ppu_position
+pub fn ppu_frame(cpu_cycle: u64) -> u64 {
+    cpu_cycle * DOTS_PER_CPU_CYCLE / (DOTS_PER_SCANLINE * SCANLINES_PER_FRAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::asm;
+    use crate::cpu::memory::MemoryMap;
+
+    /// Assembles the program at 0x0600 and starts the CPU on it
+    fn cpu_with_program(source: &str) -> CPU<MemoryMap> {
+        let mut cpu = CPU::new(MemoryMap::new());
+        cpu.load_program(asm::assemble(source, 0x0600).unwrap());
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu
+    }
+
+    #[test]
+    fn format_trace() {
+        let mut cpu = cpu_with_program(
+            "
+            LDX #$01
+            DEX
+            DEY
+            BRK
+            ",
+        );
+        cpu.accumulator = 1;
+        cpu.index_register_x = 2;
+        cpu.index_register_y = 3;
+
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| result.push(trace(cpu)))
+            .unwrap();
+
+        assert_eq!(
+            "0600  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7",
+            result[0]
+        );
+        assert_eq!(
+            "0602  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0, 27 CYC:9",
+            result[1]
+        );
+        assert_eq!(
+            "0603  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 33 CYC:11",
+            result[2]
+        );
+    }
+
+    #[test]
+    fn format_with_labels() {
+        let mut cpu = cpu_with_program("LDA ($33),Y");
+        cpu.write_mem_u8(0x33, 0x00);
+        cpu.write_mem_u8(0x34, 0x04);
+        let labels = BTreeMap::from([(0x0600, "start".to_string()), (0x33, "ptr".to_string())]);
+
+        assert_eq!(
+            "0600  B1 33     start: LDA (ptr),Y = 0400 @ 0400 = 00 A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            trace_with_labels(&cpu, &labels)
+        );
+    }
+
+    #[test]
+    fn format_mem_access() {
+        let mut cpu = cpu_with_program("LDA ($33),Y");
+        cpu.index_register_y = 0;
+        cpu.write_mem_u8(0x33, 0x00);
+        cpu.write_mem_u8(0x34, 0x04);
+        cpu.write_mem_u8(0x400, 0xAA);
+
+        assert_eq!(
+            "0600  B1 33     LDA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn format_illegal_opcode() {
+        // *NOP $A9, by its byte as the assembler picks its own one of the zero page NOPs
+        let mut cpu = cpu_with_program(".byte $04, $A9");
+        cpu.write_mem_u8(0xA9, 0x00);
+
+        assert_eq!(
+            "0600  04 A9    *NOP $A9 = 00                    A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn trace_buffer_keeps_the_last_instructions() {
+        let mut cpu = cpu_with_program(
+            "
+            LDX #$01
+            DEX
+            DEY
+            BRK
+            ",
+        );
+        cpu.trace_buffer = TraceBuffer::new(2);
+
+        let mut lines: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| lines.push(trace(cpu))).unwrap();
+
+        // the DEY and the BRK, the same as the trace made while they ran
+        let kept: Vec<String> = cpu.trace_buffer.entries().map(|e| e.to_string()).collect();
+        assert_eq!(kept, lines[2..]);
+        assert_eq!(cpu.trace_buffer.to_string(), lines[2..].join("\n") + "\n");
+
+        cpu.trace_buffer.clear();
+        assert!(cpu.trace_buffer.is_empty());
+        assert_eq!(TraceBuffer::new(0).len(), 0);
+    }
+
+    #[test]
+    fn trace_buffer_leaves_out_memory() {
+        // *NOP $A9 and then an opcode the CPU doesn't know, both as bytes
+        let mut cpu = cpu_with_program(
+            "
+            LDA ($33),Y
+            .byte $04, $A9, $8B
+            ",
+        );
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.step().is_err());
+
+        let kept: Vec<String> = cpu.trace_buffer.entries().map(|e| e.to_string()).collect();
+        assert_eq!(
+            kept,
+            [
+                "0600  B1 33     LDA ($33),Y                     A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+                "0602  04 A9    *NOP $A9                         A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+                "0604  8B        ???                             A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15",
+            ]
+        );
+    }
+
+    #[test]
+    fn ppu_position_wraps_scanlines() {
+        assert_eq!(ppu_position(7), (0, 21));
+        assert_eq!(ppu_position(26554), (233, 209));
+    }
+}
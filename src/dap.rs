@@ -0,0 +1,709 @@
+//! A Debug Adapter Protocol server, so an editor can debug the 6502 program in a ROM.
+//!
+//! `--dap` talks over stdin and stdout, `--dap <port>` waits for one editor on a localhost
+//! port. The editor launches with `program` set to the `.nes` file and can ask to
+//! `stopOnEntry`. There is no editor extension for it, any client that can talk to a
+//! debug adapter on a port or over a pipe works. With nvim-dap for example
+//!
+//! ```text
+//! dap.adapters.nes = { type = 'server', host = '127.0.0.1', port = 4711 }
+//! dap.configurations.nes = { { type = 'nes', request = 'launch', name = 'NES program',
+//!                              program = 'game.nes', stopOnEntry = true } }
+//! ```
+//!
+//! after starting the adapter with `cargo run -- --dap 4711`. Over the protocol the program gets
+//! - one thread with one stack frame, at the program counter
+//! - the registers and the flags as variables that can be changed
+//! - the disassembly view
+//! - instruction breakpoints, and function breakpoints by label or address. Both take
+//!   conditions and log messages written like the ones for `--debug`
+//! - continue, pause, step in, step over and step out
+//! - step back and reverse continue, from the debugger's history
+//!
+//! The program runs in slices so a pause is picked up while it runs,
+//! a step over or step out that takes more than one instruction runs the same way
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::cpu::bus::Bus;
+use crate::cpu::cpu::CPU;
+use crate::cpu::debugger::{Breakpoint, Debugger, Register, Stop, Trigger, WatchedBus};
+use crate::cpu::disasm;
+use crate::cpu::error::EmuError;
+use crate::cpu::expr::{Expr, Template};
+use crate::cpu::memory::Mem;
+use crate::cpu::processor_status::ProcessorStatusFlags;
+use crate::cpu::rom::Rom;
+use crate::cpu::step::Step;
+use crate::cpu::symbols;
+
+/// Instructions run between looks at the requests while the program runs
+const SLICE: u32 = 10_000;
+
+/// There is only the one CPU
+const THREAD_ID: i64 = 1;
+
+/// What a running step waits for, checked after every instruction
+type Until = Box<dyn FnMut(&CPU<WatchedBus<Bus>>, &Step) -> bool>;
+
+/// The variablesReference of each scope
+const REGISTERS: i64 = 1;
+const FLAGS: i64 = 2;
+
+/// The flags as the variables view shows them, in the order they sit in P
+const FLAG_NAMES: [(&str, u8); 6] = [
+    ("N", ProcessorStatusFlags::Negative as u8),
+    ("V", ProcessorStatusFlags::Overflow as u8),
+    ("D", ProcessorStatusFlags::DecimalMode as u8),
+    ("I", ProcessorStatusFlags::InterruptDisable as u8),
+    ("Z", ProcessorStatusFlags::ZeroFlag as u8),
+    ("C", ProcessorStatusFlags::CarryFlag as u8),
+];
+
+/// Reads one message: a `Content-Length` header, a blank line and that many bytes of JSON.
+/// None once the other end has gone
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "a message without a length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serves one editor until it disconnects or goes away.
+/// The requests are read on their own thread so they can come in while the program runs
+pub fn serve<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(request)) = read_message(&mut input) {
+            if sender.send(request).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut session = Session::new(output);
+    while !session.done {
+        let request = if session.running {
+            match requests.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match requests.recv() {
+                Ok(request) => Some(request),
+                Err(_) => return Ok(()),
+            }
+        };
+
+        if let Some(request) = request {
+            session.handle(&request)?;
+        }
+        if session.running {
+            session.run_slice()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Waits on a localhost port for an editor to connect and serves it
+pub fn listen(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for an editor on {}", listener.local_addr()?);
+
+    let (stream, _) = listener.accept()?;
+    serve(BufReader::new(stream.try_clone()?), stream)
+}
+
+/// Parses an address the way the editor sends them, `0xC000`
+fn parse_reference(reference: &Value) -> Result<u16, String> {
+    let text = reference.as_str().unwrap_or_default();
+    u16::from_str_radix(text.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("{} is not an address", text))
+}
+
+fn reference(addr: u16) -> String {
+    format!("0x{:04X}", addr)
+}
+
+/// Makes the condition and log message a breakpoint came with into a Trigger
+fn trigger(breakpoint: &Value) -> Result<Trigger, String> {
+    let condition = breakpoint["condition"]
+        .as_str()
+        .filter(|condition| !condition.trim().is_empty())
+        .map(Expr::parse)
+        .transpose()?;
+    let log = breakpoint["logMessage"]
+        .as_str()
+        .map(Template::parse)
+        .transpose()?;
+
+    Ok(Trigger {
+        condition,
+        log,
+        ..Trigger::default()
+    })
+}
+
+/// What the reply to a setBreakpoints style request says about one breakpoint
+fn verified(result: &Result<Breakpoint, String>) -> Value {
+    match result {
+        Ok(breakpoint) => json!({
+            "verified": true,
+            "instructionReference": reference(breakpoint.address),
+        }),
+        Err(message) => json!({ "verified": false, "message": message }),
+    }
+}
+
+/// There is no source to put breakpoints in, only the disassembly
+fn source_breakpoints(args: &Value) -> Value {
+    let breakpoints: Vec<Value> = breakpoints(args)
+        .iter()
+        .map(|_| {
+            json!({
+                "verified": false,
+                "message": "set breakpoints in the disassembly or by function name",
+            })
+        })
+        .collect();
+    json!({ "breakpoints": breakpoints })
+}
+
+fn breakpoints(args: &Value) -> &[Value] {
+    args["breakpoints"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// Why the program stopped, as the reason and text of a stopped event.
+/// None when it ran to the end of a slice and should carry on
+fn stopped_by(result: Result<Stop, EmuError>) -> Option<(&'static str, Option<String>)> {
+    match result {
+        Ok(Stop::Done) => None,
+        Ok(Stop::Breakpoint(_)) => Some(("breakpoint", None)),
+        Ok(stop @ Stop::Watch(_)) => Some(("data breakpoint", Some(stop.to_string()))),
+        Ok(stop @ Stop::Jam(_)) => Some(("exception", Some(stop.to_string()))),
+        Ok(stop @ Stop::HistoryStart) => Some(("step", Some(stop.to_string()))),
+        Err(error) => Some(("exception", Some(error.to_string()))),
+    }
+}
+
+struct Session<W: Write> {
+    output: W,
+    seq: i64,
+
+    /// Events to send once the response to the request being handled is out
+    events: Vec<Value>,
+
+    /// None until the editor launches a ROM
+    debugger: Option<Debugger<Bus>>,
+    labels: BTreeMap<u16, String>,
+    stop_on_entry: bool,
+
+    /// The editor sends each kind as a whole new list, the debugger gets them both
+    instruction_breakpoints: Vec<Breakpoint>,
+    function_breakpoints: Vec<Breakpoint>,
+
+    running: bool,
+
+    /// Set while a step over or step out runs, None when continuing
+    until: Option<Until>,
+
+    /// The next slice is the first since a stop, it runs past a breakpoint it starts on
+    resuming: bool,
+    done: bool,
+}
+
+impl<W: Write> Session<W> {
+    fn new(output: W) -> Self {
+        Session {
+            output,
+            seq: 0,
+            events: Vec::new(),
+            debugger: None,
+            labels: BTreeMap::new(),
+            stop_on_entry: false,
+            instruction_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+            running: false,
+            until: None,
+            resuming: false,
+            done: false,
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.events
+            .push(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+        self.running = false;
+        self.until = None;
+        self.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+                "text": text,
+            }),
+        );
+    }
+
+    /// Sends what the logging breakpoints wrote, then the queued events
+    fn flush(&mut self) -> io::Result<()> {
+        let log = self
+            .debugger
+            .as_mut()
+            .map(|debugger| std::mem::take(&mut debugger.log))
+            .unwrap_or_default();
+        for line in log {
+            self.send(json!({
+                "type": "event",
+                "event": "output",
+                "body": { "category": "console", "output": line + "\n" },
+            }))?;
+        }
+
+        for event in std::mem::take(&mut self.events) {
+            self.send(event)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsLogPoints": true,
+                "supportsDisassembleRequest": true,
+                "supportsSetVariable": true,
+                "supportsSteppingGranularity": true,
+                "supportsStepBack": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(source_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "6502" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Flags", "variablesReference": FLAGS, "expensive": false },
+            ] })),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => self
+                .resume(None)
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "pause" => {
+                if self.running {
+                    self.stopped("pause", None);
+                }
+                Ok(Value::Null)
+            }
+            "next" => self.step_over(),
+            "stepIn" => self.step(|debugger| debugger.step(1)),
+            "stepOut" => self.step_out(),
+            "stepBack" => self.step(|debugger| debugger.step_back(1)),
+            "reverseContinue" => self.step(|debugger| debugger.reverse_continue()),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            other => Err(format!("{} is not supported", other)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)?;
+        self.flush()
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger<Bus>, String> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| "no ROM has been launched".to_string())
+    }
+
+    fn launched(&self) -> Result<&Debugger<Bus>, String> {
+        self.debugger
+            .as_ref()
+            .ok_or_else(|| "no ROM has been launched".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["program"]
+            .as_str()
+            .ok_or("launch needs the program, the path to a .nes file")?;
+        let rom = Rom::new_from_file(path.to_string())?;
+
+        let mut debugger = Debugger::new(Bus::new(rom));
+        debugger.cpu.reset();
+        self.labels = symbols::labels_for_rom(path, &debugger.cpu.bus.inner)?;
+        self.debugger = Some(debugger);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        // ready for the breakpoints now there is something to put them in
+        self.event("initialized", Value::Null);
+        Ok(Value::Null)
+    }
+
+    fn update_breakpoints(&mut self) -> Result<(), String> {
+        let breakpoints = [
+            self.instruction_breakpoints.clone(),
+            self.function_breakpoints.clone(),
+        ]
+        .concat();
+        self.debugger()?.breakpoints = breakpoints;
+        Ok(())
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let results: Vec<Result<Breakpoint, String>> = breakpoints(args)
+            .iter()
+            .map(|breakpoint| {
+                let offset = breakpoint["offset"].as_i64().unwrap_or(0) as u16;
+                Ok(Breakpoint {
+                    address: parse_reference(&breakpoint["instructionReference"])?
+                        .wrapping_add(offset),
+                    trigger: trigger(breakpoint)?,
+                })
+            })
+            .collect();
+
+        self.instruction_breakpoints = results.iter().flatten().cloned().collect();
+        self.update_breakpoints()?;
+        Ok(json!({ "breakpoints": results.iter().map(verified).collect::<Vec<_>>() }))
+    }
+
+    /// A function is a label or anything the expressions can work out, like `$C000`
+    /// or `{$FFFA}` for wherever the NMI vector points
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let labels = &self.labels;
+        let cpu = &self.launched()?.cpu;
+
+        let results: Vec<Result<Breakpoint, String>> = breakpoints(args)
+            .iter()
+            .map(|breakpoint| {
+                let name = breakpoint["name"].as_str().unwrap_or_default();
+                let address = match labels.iter().find(|(_, label)| *label == name) {
+                    Some((address, _)) => *address,
+                    None => Expr::parse(name)
+                        .map_err(|_| format!("there is no function called {}", name))?
+                        .eval(cpu, (cpu.program_counter, 0)) as u16,
+                };
+                Ok(Breakpoint {
+                    address,
+                    trigger: trigger(breakpoint)?,
+                })
+            })
+            .collect();
+
+        self.function_breakpoints = results.iter().flatten().cloned().collect();
+        self.update_breakpoints()?;
+        Ok(json!({ "breakpoints": results.iter().map(verified).collect::<Vec<_>>() }))
+    }
+
+    fn configuration_done(&mut self) -> Result<Value, String> {
+        self.launched()?;
+        if self.stop_on_entry {
+            self.stopped("entry", None);
+        } else {
+            // a breakpoint on the first instruction counts
+            self.running = true;
+            self.resuming = false;
+        }
+        Ok(Value::Null)
+    }
+
+    /// Starts the program running from where it stopped, until `until` if it is set.
+    /// The slices are run between the requests
+    fn resume(&mut self, until: Option<Until>) -> Result<Value, String> {
+        self.launched()?;
+        self.running = true;
+        self.resuming = true;
+        self.until = until;
+        Ok(Value::Null)
+    }
+
+    fn run_slice(&mut self) -> io::Result<()> {
+        let first_slice = std::mem::replace(&mut self.resuming, false);
+        let until = &mut self.until;
+        let result = match self.debugger.as_mut() {
+            Some(debugger) => debugger.resume_until(SLICE, first_slice, |cpu, step| {
+                until.as_mut().is_some_and(|until| until(cpu, step))
+            }),
+            None => return Ok(()),
+        };
+
+        let result = match result {
+            Ok(None) => return self.flush(),
+            Ok(Some(stop)) => Ok(stop),
+            Err(error) => Err(error),
+        };
+        let (reason, text) = stopped_by(result).unwrap_or(("step", None));
+        self.stopped(reason, text);
+        self.flush()
+    }
+
+    /// A JSR runs in slices until it is back, anything else is one step
+    fn step_over(&mut self) -> Result<Value, String> {
+        match self.debugger()?.over_call() {
+            Some(until) => self.resume(Some(Box::new(until))),
+            None => self.step(|debugger| debugger.step(1)),
+        }
+    }
+
+    fn step_out(&mut self) -> Result<Value, String> {
+        let until = self.debugger()?.out_of_call();
+        self.resume(Some(Box::new(until)))
+    }
+
+    fn step<F>(&mut self, step: F) -> Result<Value, String>
+    where
+        F: FnOnce(&mut Debugger<Bus>) -> Result<Stop, EmuError>,
+    {
+        let result = step(self.debugger()?);
+        let (reason, text) = stopped_by(result).unwrap_or(("step", None));
+        self.stopped(reason, text);
+        Ok(Value::Null)
+    }
+
+    /// The label the address is at, or the nearest one before it and how far past it is
+    fn symbol(&self, addr: u16) -> String {
+        match self.labels.range(..=addr).next_back() {
+            Some((&label_addr, label)) if label_addr == addr => label.clone(),
+            Some((&label_addr, label)) => format!("{}+{}", label, addr - label_addr),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let pc = self.launched()?.cpu.program_counter;
+        Ok(json!({
+            "stackFrames": [{
+                "id": 0,
+                "name": self.symbol(pc),
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": reference(pc),
+            }],
+            "totalFrames": 1,
+        }))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let cpu = &self.launched()?.cpu;
+        let variable = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        let variables = match args["variablesReference"].as_i64() {
+            Some(REGISTERS) => vec![
+                variable("A", format!("${:02X}", cpu.accumulator)),
+                variable("X", format!("${:02X}", cpu.index_register_x)),
+                variable("Y", format!("${:02X}", cpu.index_register_y)),
+                variable("SP", format!("${:02X}", cpu.stack_pointer)),
+                variable("P", format!("${:02X}", cpu.processor_status.0)),
+                variable("PC", format!("${:04X}", cpu.program_counter)),
+                variable("cycle", cpu.current_cycle.to_string()),
+            ],
+            Some(FLAGS) => FLAG_NAMES
+                .iter()
+                .map(|(name, mask)| {
+                    variable(
+                        name,
+                        ((cpu.processor_status.0 & mask != 0) as u8).to_string(),
+                    )
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// The new value can be anything the expressions can work out, `$10`, `a + 1` and so on
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or_default();
+        let debugger = self.debugger()?;
+        let cpu = &debugger.cpu;
+        let value = Expr::parse(args["value"].as_str().unwrap_or_default())?
+            .eval(cpu, (cpu.program_counter, 0));
+
+        if let Some((_, mask)) = FLAG_NAMES.iter().find(|(flag, _)| *flag == name) {
+            let status = match value {
+                0 => debugger.cpu.processor_status.0 & !mask,
+                _ => debugger.cpu.processor_status.0 | mask,
+            };
+            debugger.set_register(Register::Status, status as u16)?;
+            return Ok(json!({ "value": ((value != 0) as u8).to_string() }));
+        }
+
+        let register = match name {
+            "A" => Register::A,
+            "X" => Register::X,
+            "Y" => Register::Y,
+            "SP" => Register::StackPointer,
+            "P" => Register::Status,
+            "PC" => Register::ProgramCounter,
+            _ => return Err(format!("{} can't be changed", name)),
+        };
+        let value = u16::try_from(value).map_err(|_| format!("{} is out of range", value))?;
+        debugger.set_register(register, value)?;
+
+        let value = match register {
+            Register::ProgramCounter => format!("${:04X}", value),
+            _ => format!("${:02X}", value),
+        };
+        Ok(json!({ "value": value }))
+    }
+
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let cpu = &self.launched()?.cpu;
+        let value = Expr::parse(args["expression"].as_str().unwrap_or_default())?
+            .eval(cpu, (cpu.program_counter, 0));
+
+        Ok(json!({ "result": format!("${:X} ({})", value, value), "variablesReference": 0 }))
+    }
+
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let offset = args["offset"].as_i64().unwrap_or(0) as u16;
+        let base = parse_reference(&args["memoryReference"])?.wrapping_add(offset);
+        let instruction_offset = args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_u64().unwrap_or(0);
+
+        let cpu = &self.launched()?.cpu;
+        let read = |addr: u16| Some(cpu.read_mem_u8(addr));
+
+        let mut address = if instruction_offset < 0 {
+            instructions_before(base, instruction_offset.unsigned_abs(), &self.labels, read)
+        } else {
+            base
+        };
+        for _ in 0..instruction_offset.max(0) {
+            address = address.wrapping_add(disasm::decode(address, read).len());
+        }
+
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            let line = disasm::decode(address, read);
+            let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let mut instruction = json!({
+                "address": reference(address),
+                "instructionBytes": bytes.join(" "),
+                "instruction": line.to_string_with_labels(&self.labels),
+            });
+            if let Some(label) = self.labels.get(&address) {
+                instruction["symbol"] = json!(label);
+            }
+            instructions.push(instruction);
+            address = address.wrapping_add(line.len());
+        }
+
+        Ok(json!({ "instructions": instructions }))
+    }
+}
+
+/// Finds where the instruction `count` instructions before `addr` starts.
+/// Code can't be decoded backwards, so this decodes forwards from as far back as
+/// the instructions could go and keeps the starts that line up with `addr`.
+/// Data in front of the code lines up just as well, so the run that passes
+/// through the most labels (places the code is known to go) wins, then the longest
+fn instructions_before(
+    addr: u16,
+    count: u64,
+    labels: &BTreeMap<u16, String>,
+    read: impl Fn(u16) -> Option<u8>,
+) -> u16 {
+    let addr = addr as u64;
+    let mut best: Option<(usize, u16)> = None;
+    for back in (count..=count * 3).rev() {
+        let Some(mut at) = addr.checked_sub(back) else {
+            continue;
+        };
+
+        let mut starts = Vec::new();
+        while at < addr {
+            starts.push(at as u16);
+            at += disasm::decode(at as u16, &read).len() as u64;
+        }
+        if at != addr || (starts.len() as u64) < count {
+            continue;
+        }
+
+        let known = starts.iter().filter(|at| labels.contains_key(at)).count();
+        if best.is_none_or(|(most, _)| known > most) {
+            best = Some((known, starts[starts.len() - count as usize]));
+        }
+    }
+
+    if let Some((_, start)) = best {
+        return start;
+    }
+
+    addr.saturating_sub(count) as u16
+}
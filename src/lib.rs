@@ -0,0 +1,5 @@
+#![allow(clippy::upper_case_acronyms, clippy::module_inception)]
+
+pub mod conformance;
+pub mod cpu;
+pub mod dap;
@@ -1,87 +1,222 @@
-mod cpu;
-
-use cpu::cpu::CPU;
+use nes_emulator::conformance;
+use nes_emulator::dap;
+use nes_emulator::cpu::cdl::CodeDataLog;
+use nes_emulator::cpu::cpu::CPU;
+use nes_emulator::cpu::debugger::{Command, Debugger};
+use nes_emulator::cpu::disasm;
+use nes_emulator::cpu::profile::Profiler;
+use nes_emulator::cpu::step::StopReason;
+use nes_emulator::cpu::symbols::{self, Symbols};
+use nes_emulator::cpu::trace;
 
 extern crate env_logger;
-use cpu::bus::Bus;
-use cpu::memory::Mem;
+use nes_emulator::cpu::bus::Bus;
 
-use cpu::rom::{self, test};
+use nes_emulator::cpu::rom;
 pub use log::{debug, error, info, log_enabled, Level};
-use rand::Rng;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::{event::Event, keyboard::Keycode, EventPump};
-
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. }
-            | Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => std::process::exit(0),
-            Event::KeyDown {
-                keycode: Some(Keycode::W),
-                ..
-            } => {
-                cpu.write_mem_u8(0xff, 0x77);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
-                ..
-            } => {
-                cpu.write_mem_u8(0xff, 0x73);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::A),
-                ..
-            } => {
-                cpu.write_mem_u8(0xff, 0x61);
+use std::io::Write;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+
+/// Prints the instructions from the trace buffer, the last one is where it went wrong
+fn dump_trace(cpu: &CPU) {
+    println!("last {} instructions:", cpu.trace_buffer.len());
+    print!("{}", cpu.trace_buffer);
+}
+
+/// Runs the ROM headless against its reference log, see `conformance::run`
+fn run_conformance(rom_path: String, log_path: String) {
+    let rom = rom::Rom::new_from_file(rom_path).unwrap();
+    let reference = std::fs::read_to_string(&log_path).unwrap();
+
+    match conformance::run(rom, &reference) {
+        Ok(report) => {
+            println!(
+                "{} lines match {}, result codes ${:02X} ${:02X}",
+                report.lines, log_path, report.result.0, report.result.1
+            );
+            if !report.passed() {
+                std::process::exit(1);
             }
-            Event::KeyDown {
-                keycode: Some(Keycode::D),
-                ..
-            } => {
-                cpu.write_mem_u8(0xff, 0x64);
+        }
+        Err(mismatch) => {
+            println!("{}", mismatch);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Writes the whole ROM out as a `.s` listing, see `disasm::disassemble_rom`
+fn run_disasm(rom_path: String, out_path: Option<String>) {
+    let out_path = out_path.unwrap_or_else(|| {
+        std::path::Path::new(&rom_path)
+            .with_extension("s")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let rom = rom::Rom::new_from_file(rom_path.clone()).unwrap();
+    let symbols = Symbols::for_rom(&rom_path, &rom).unwrap();
+
+    let listing = disasm::disassemble_rom(&rom, &symbols);
+    std::fs::write(&out_path, listing).unwrap();
+    println!(
+        "{} PRG banks written to {}",
+        rom.prg_rom.len() / 0x4000,
+        out_path
+    );
+}
+
+/// Runs the ROM under the command line debugger, `help` at the prompt lists the commands
+fn run_debug(rom_path: String) {
+    let rom = rom::Rom::new_from_file(rom_path.clone()).unwrap();
+    let mut debugger = Debugger::new(Bus::new(rom));
+    debugger.cpu.reset();
+    debugger.labels = symbols::labels_for_rom(&rom_path, &debugger.cpu.bus.inner).unwrap();
+    println!("{}", debugger.run_command(Command::Registers));
+
+    let stdin = std::io::stdin();
+    let mut last: Option<Command> = None;
+    loop {
+        print!("> ");
+        std::io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line).unwrap() == 0 {
+            return;
+        }
+
+        // an empty line does the last command again, handy for stepping
+        let command = match (line.trim(), &last) {
+            ("", Some(last)) => Ok(last.clone()),
+            ("", None) => continue,
+            (line, _) => Command::parse(line, &debugger.labels),
+        };
+
+        match command {
+            Ok(Command::Quit) => return,
+            Ok(command) => {
+                println!("{}", debugger.run_command(command.clone()));
+                last = Some(command);
             }
-            _ => { /* do nothing */ }
+            Err(error) => println!("{}", error),
         }
     }
 }
 
-fn color(byte: u8) -> sdl2::pixels::Color {
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
+/// Runs the ROM for a number of frames and prints where the cycles went, see `profile`.
+/// The call tree can also be written out as collapsed stacks for a flamegraph
+fn run_profile(rom_path: String, frames: u64, collapsed_path: Option<String>) {
+    let rom = rom::Rom::new_from_file(rom_path.clone()).unwrap();
+    let mut cpu = CPU::new(Bus::new(rom));
+    cpu.reset();
+    let labels = symbols::labels_for_rom(&rom_path, &cpu.bus).unwrap();
+
+    let mut profiler = Profiler::new();
+    while trace::ppu_frame(cpu.current_cycle) < frames {
+        if let Err(error) = profiler.step(&mut cpu) {
+            dump_trace(&cpu);
+            println!("{}", error);
+            break;
+        }
+    }
+
+    print!("{}", profiler.report(&labels));
+    if let Some(path) = collapsed_path {
+        std::fs::write(&path, profiler.collapsed(&labels)).unwrap();
+        println!("\ncollapsed stacks written to {}", path);
+    }
+}
+
+/// Runs the ROM for a number of frames with the code/data logger on and saves the log,
+/// next to the ROM unless a path is given. A log that is already there gets added to
+fn run_cdl(rom_path: String, frames: u64, out_path: Option<String>) {
+    let out_path = out_path.unwrap_or_else(|| {
+        std::path::Path::new(&rom_path)
+            .with_extension("cdl")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let rom = rom::Rom::new_from_file(rom_path).unwrap();
+
+    let cdl = match std::fs::read(&out_path) {
+        Ok(bytes) => CodeDataLog::from_bytes(&rom, &bytes).unwrap(),
+        Err(_) => CodeDataLog::new(&rom),
+    };
+    let mut bus = Bus::new(rom);
+    bus.cdl = Some(cdl);
+    let mut cpu = CPU::new(bus);
+    cpu.reset();
+
+    while trace::ppu_frame(cpu.current_cycle) < frames {
+        if let Err(error) = cpu.step() {
+            dump_trace(&cpu);
+            println!("{}", error);
+            break;
+        }
     }
+
+    let cdl = cpu.bus.cdl.take().unwrap();
+    cdl.save(&out_path).unwrap();
+    let coverage = cdl.coverage();
+    println!(
+        "{} bytes of code, {} of data and {} not used yet written to {}",
+        coverage.code, coverage.data, coverage.unused, out_path
+    );
 }
 
-fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200..0x600 {
-        let color_idx = cpu.read_mem_u8(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
-        }
-        frame_idx += 3;
+/// Serves the program to an editor over the debug adapter protocol,
+/// on stdin and stdout or on a localhost port, see `dap`
+fn run_dap(port: Option<String>) {
+    let result = match port {
+        Some(port) => dap::listen(port.parse().expect("the port has to be a number")),
+        None => dap::serve(std::io::BufReader::new(std::io::stdin()), std::io::stdout()),
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
     }
-    update
 }
 
 fn main() {
-    std::env::set_var("RUST_LOG", "trace");
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("--conformance") => {
+            let rom_path = args.next().unwrap_or("test-roms/mmc5test.nes".to_string());
+            let log_path = args.next().unwrap_or("test-roms/mmc5test.log".to_string());
+            run_conformance(rom_path, log_path);
+            return;
+        }
+        Some("--debug") => {
+            let rom_path = args.next().unwrap_or("test-roms/mmc5test.nes".to_string());
+            run_debug(rom_path);
+            return;
+        }
+        Some("--dap") => {
+            run_dap(args.next());
+            return;
+        }
+        Some("--profile") => {
+            let rom_path = args.next().unwrap_or("test-roms/mmc5test.nes".to_string());
+            let frames = args.next().map_or(60, |frames| frames.parse().expect("the frame count has to be a number"));
+            run_profile(rom_path, frames, args.next());
+            return;
+        }
+        Some("--cdl") => {
+            let rom_path = args.next().unwrap_or("test-roms/mmc5test.nes".to_string());
+            let frames = args.next().map_or(60, |frames| frames.parse().expect("the frame count has to be a number"));
+            run_cdl(rom_path, frames, args.next());
+            return;
+        }
+        Some("--disasm") => {
+            let rom_path = args.next().unwrap_or("test-roms/mmc5test.nes".to_string());
+            run_disasm(rom_path, args.next());
+            return;
+        }
+        _ => {}
+    }
+
     env_logger::init();
 
     let bus = Bus::new(rom::Rom::new_from_file("test-roms/mmc5test.nes".to_string()).unwrap());
@@ -91,9 +226,43 @@ fn main() {
     cpu.reset();
     cpu.program_counter = 0xC000;
 
-    cpu.run_with_callback(move |cpu| {
-        println!("{}", cpu);
-
-        ::std::thread::sleep(std::time::Duration::new(0, 1_000_000_000));
+    // pressing enter dumps the trace buffer and lets the program carry on
+    let (dump, dump_requested) = mpsc::channel();
+    thread::spawn(move || {
+        for _ in std::io::stdin().lines() {
+            if dump.send(()).is_err() {
+                return;
+            }
+        }
     });
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| loop {
+        match cpu.run_frame() {
+            Ok(StopReason::FrameComplete) => {
+                if dump_requested.try_recv().is_ok() {
+                    dump_trace(&cpu);
+                }
+            }
+            stopped => break stopped,
+        }
+    }));
+
+    // the panic message is already out, the trace goes after it
+    match result {
+        Ok(Ok(StopReason::Jam(addr))) => {
+            dump_trace(&cpu);
+            println!("the CPU jammed at ${:04X}", addr);
+            std::process::exit(1);
+        }
+        Ok(Ok(_)) => {}
+        Ok(Err(error)) => {
+            dump_trace(&cpu);
+            println!("{}", error);
+            std::process::exit(1);
+        }
+        Err(payload) => {
+            dump_trace(&cpu);
+            panic::resume_unwind(payload);
+        }
+    }
 }
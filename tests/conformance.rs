@@ -0,0 +1,37 @@
+use std::fs;
+
+use nes_emulator::conformance;
+use nes_emulator::cpu::rom::Rom;
+use nes_emulator::cpu::trace::trace;
+
+const ROM: &str = "test-roms/mmc5test.nes";
+const LOG: &str = "test-roms/mmc5test.log";
+
+#[test]
+#[ignore = "test-roms/mmc5test.log is the nestest.log reference, it needs nestest.nes in place of mmc5test.nes"]
+fn bundled_rom_matches_reference_log() {
+    let reference = fs::read_to_string(LOG).unwrap();
+    let load = || Rom::new_from_file(ROM.to_string()).unwrap();
+
+    // the log only tells us something if it was recorded from this ROM,
+    // fail on the first instruction rather than on a confusing mismatch later
+    let first_line = reference.lines().next().unwrap();
+    let first_trace = trace(&conformance::boot(load(), &reference));
+    assert_eq!(
+        &first_trace[..16],
+        &first_line[..16],
+        "{} was not recorded from {}",
+        LOG,
+        ROM
+    );
+
+    match conformance::run(load(), &reference) {
+        Ok(report) => assert!(
+            report.passed(),
+            "ROM reported failure codes ${:02X} ${:02X}",
+            report.result.0,
+            report.result.1
+        ),
+        Err(mismatch) => panic!("{}", mismatch),
+    }
+}
@@ -0,0 +1,241 @@
+//! Drives the debug adapter the way an editor would, over a localhost socket
+
+use std::collections::VecDeque;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use nes_emulator::cpu::asm;
+use nes_emulator::dap;
+
+const PROGRAM: &str = "
+        .org $C000
+reset:  LDX #$00
+loop:   JSR count
+        INX
+        JMP loop
+count:  INC $10
+        LDA $10
+        RTS
+nmi:    RTI
+
+        .org $FFFA
+        .word nmi, reset, nmi
+";
+
+/// Assembles the program into a 16K NROM image and writes it out for the launch request
+fn rom_file() -> String {
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(asm::assemble(PROGRAM, 0xC000).unwrap());
+
+    let path = std::env::temp_dir().join(format!("dap-test-{}.nes", std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: i64,
+
+    /// Events that came in while waiting for a response
+    events: VecDeque<Value>,
+}
+
+impl Client {
+    fn connect() -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            dap::serve(BufReader::new(stream.try_clone().unwrap()), stream).unwrap();
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            seq: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn read(&mut self) -> Value {
+        dap::read_message(&mut self.reader).unwrap().unwrap()
+    }
+
+    /// Sends a request and waits for the response, which has to be a success
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let request = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        dap::write_message(&mut self.writer, &request).unwrap();
+
+        loop {
+            let message = self.read();
+            if message["type"] == "event" {
+                self.events.push_back(message);
+            } else if message["request_seq"] == self.seq {
+                assert_eq!(message["success"], true, "{}", message);
+                return message["body"].clone();
+            }
+        }
+    }
+
+    fn event(&mut self, event: &str) -> Value {
+        let message = match self.events.pop_front() {
+            Some(message) => message,
+            None => self.read(),
+        };
+        assert_eq!(message["event"], event, "{}", message);
+        message["body"].clone()
+    }
+
+    fn stopped(&mut self, reason: &str) {
+        assert_eq!(self.event("stopped")["reason"], reason);
+    }
+
+    /// The name and address of the only stack frame
+    fn frame(&mut self) -> (String, String) {
+        let trace = self.request("stackTrace", json!({ "threadId": 1 }));
+        let frame = &trace["stackFrames"][0];
+        (
+            frame["name"].as_str().unwrap().to_string(),
+            frame["instructionPointerReference"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        )
+    }
+
+    fn register(&mut self, name: &str) -> Value {
+        let variables = self.request("variables", json!({ "variablesReference": 1 }));
+        variables["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|variable| variable["name"] == name)
+            .unwrap()["value"]
+            .clone()
+    }
+}
+
+#[test]
+fn debug_a_rom_over_the_protocol() {
+    let rom = rom_file();
+    let mut client = Client::connect();
+
+    let capabilities = client.request("initialize", json!({ "adapterID": "nes" }));
+    assert_eq!(capabilities["supportsDisassembleRequest"], true);
+    assert_eq!(capabilities["supportsStepBack"], true);
+
+    client.request("launch", json!({ "program": rom, "stopOnEntry": true }));
+    client.event("initialized");
+
+    let breakpoints = client.request(
+        "setFunctionBreakpoints",
+        json!({ "breakpoints": [{ "name": "sub_C009" }, { "name": "nowhere" }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+    assert_eq!(
+        breakpoints["breakpoints"][0]["instructionReference"],
+        "0xC009"
+    );
+    assert_eq!(breakpoints["breakpoints"][1]["verified"], false);
+
+    client.request(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": "0xC005", "condition": "x == 2" }] }),
+    );
+
+    client.request("configurationDone", json!({}));
+    client.stopped("entry");
+    assert_eq!(client.frame(), ("reset".to_string(), "0xC000".to_string()));
+
+    client.request("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    assert_eq!(
+        client.frame(),
+        ("sub_C009".to_string(), "0xC009".to_string())
+    );
+
+    client.request("stepOut", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(
+        client.frame(),
+        ("L_C002+3".to_string(), "0xC005".to_string())
+    );
+
+    // twice more through the subroutine before the condition on $C005 holds
+    for _ in 0..2 {
+        client.request("continue", json!({ "threadId": 1 }));
+        client.stopped("breakpoint");
+        assert_eq!(client.frame().1, "0xC009");
+    }
+    client.request("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    assert_eq!(client.frame().1, "0xC005");
+    assert_eq!(client.register("X"), "$02");
+
+    let result = client.request("evaluate", json!({ "expression": "[$10] + 1" }));
+    assert_eq!(result["result"], "$4 (4)");
+
+    let result = client.request(
+        "setVariable",
+        json!({ "variablesReference": 1, "name": "A", "value": "$42" }),
+    );
+    assert_eq!(result["value"], "$42");
+    assert_eq!(client.register("A"), "$42");
+
+    client.request("next", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.frame().1, "0xC006");
+
+    // back over the INX, then to the last call before the breakpoint
+    client.request("stepBack", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.frame().1, "0xC005");
+    assert_eq!(client.register("A"), "$42");
+    client.request("reverseContinue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    assert_eq!(client.frame().1, "0xC009");
+
+    // running forward again gets back to the A that was set by hand
+    client.request("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    assert_eq!(client.frame().1, "0xC005");
+    assert_eq!(client.register("A"), "$42");
+
+    let disassembly = client.request(
+        "disassemble",
+        json!({ "memoryReference": "0xC005", "instructionOffset": -2, "instructionCount": 4 }),
+    );
+    let instructions = disassembly["instructions"].as_array().unwrap();
+    assert_eq!(instructions[0]["address"], "0xC000");
+    assert_eq!(instructions[0]["symbol"], "reset");
+    assert_eq!(instructions[1]["instruction"], "JSR sub_C009");
+    assert_eq!(instructions[1]["instructionBytes"], "20 09 C0");
+    assert_eq!(instructions[3]["instruction"], "JMP L_C002");
+
+    // with the breakpoints gone it only stops when asked to
+    client.request("setFunctionBreakpoints", json!({ "breakpoints": [] }));
+    client.request("setInstructionBreakpoints", json!({ "breakpoints": [] }));
+
+    // at the top level there is nothing to step out of, it runs until the pause
+    client.request("stepOut", json!({ "threadId": 1 }));
+    client.request("pause", json!({ "threadId": 1 }));
+    client.stopped("pause");
+
+    client.request("continue", json!({ "threadId": 1 }));
+    client.request("pause", json!({ "threadId": 1 }));
+    client.stopped("pause");
+
+    client.request("disconnect", json!({}));
+    std::fs::remove_file(rom).unwrap();
+}
@@ -0,0 +1,94 @@
+//! Klaus Dormann's 6502 test suites, https://github.com/Klaus2m5/6502_65C02_functional_tests
+//!
+//! The binaries are not checked in with the repo, so the tests are ignored by default.
+//! Fetch `bin_files/6502_functional_test.bin` from the repo above, assemble
+//! `6502_decimal_test.a65` with as65 into `6502_decimal_test.bin`, put both in
+//! `test-roms/` and run `cargo test --test klaus -- --ignored`
+
+use std::fs;
+
+use nes_emulator::cpu::cpu::CPU;
+use nes_emulator::cpu::memory::{Mem, MemoryMap};
+
+const FUNCTIONAL_TEST: &str = "test-roms/6502_functional_test.bin";
+const FUNCTIONAL_START: u16 = 0x0400;
+/// Where the default build of the functional test loops once every test passed
+const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+/// The functional test keeps the number of the test it is on here
+const FUNCTIONAL_TEST_CASE: u16 = 0x0200;
+
+const DECIMAL_TEST: &str = "test-roms/6502_decimal_test.bin";
+const DECIMAL_START: u16 = 0x0200;
+/// The decimal test leaves 0 here when every result matched
+const DECIMAL_ERROR: u16 = 0x000B;
+
+/// Both suites run for well under this, it stops a broken CPU from spinning forever
+const CYCLE_LIMIT: u64 = 200_000_000;
+
+/// Loads the image at 0x0000
+fn load(path: &str, start: u16) -> CPU<MemoryMap> {
+    let image = fs::read(path)
+        .unwrap_or_else(|error| panic!("{} is missing, see the top of klaus.rs: {}", path, error));
+
+    let mut memory = MemoryMap::new();
+    memory.memory[..image.len()].copy_from_slice(&image);
+
+    let mut cpu = CPU::new(memory);
+    cpu.decimal_mode = true;
+    cpu.reset();
+    cpu.program_counter = start;
+    cpu
+}
+
+/// Runs until the program counter gets stuck on an instruction that jumps or branches to itself.
+/// That is how both suites stop, on success and on failure
+fn run_until_trap(cpu: &mut CPU<MemoryMap>) -> u16 {
+    loop {
+        let step = cpu.step().unwrap_or_else(|error| panic!("{}", error));
+
+        if cpu.program_counter == step.address {
+            return step.address;
+        }
+
+        assert!(
+            cpu.current_cycle < CYCLE_LIMIT,
+            "no trap after {} cycles, at {:#06x}",
+            CYCLE_LIMIT,
+            cpu.program_counter
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs test-roms/6502_functional_test.bin, see the top of klaus.rs"]
+fn functional_test() {
+    let mut cpu = load(FUNCTIONAL_TEST, FUNCTIONAL_START);
+
+    let trap = run_until_trap(&mut cpu);
+
+    assert_eq!(
+        trap,
+        FUNCTIONAL_SUCCESS,
+        "trapped at {:#06x} in test case {:#04x}\n{}",
+        trap,
+        cpu.read_mem_u8(FUNCTIONAL_TEST_CASE),
+        cpu
+    );
+}
+
+/// Only for CPU variants with BCD, the NES runs with decimal_mode off
+#[test]
+#[ignore = "needs test-roms/6502_decimal_test.bin, see the top of klaus.rs"]
+fn decimal_test() {
+    let mut cpu = load(DECIMAL_TEST, DECIMAL_START);
+
+    let trap = run_until_trap(&mut cpu);
+
+    assert_eq!(
+        cpu.read_mem_u8(DECIMAL_ERROR),
+        0,
+        "decimal test failed, trapped at {:#06x}\n{}",
+        trap,
+        cpu
+    );
+}
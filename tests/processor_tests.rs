@@ -0,0 +1,289 @@
+//! Single step tests in the ProcessorTests format, https://github.com/SingleStepTests/ProcessorTests
+//!
+//! Every file is named after the opcode it covers, `a9.json` and so on, and holds a list of
+//! vectors with the registers and RAM before and after the instruction plus every bus access
+//! it made. Only a small sample is checked in and run by default. The full suite is
+//! ignored, clone the repo above, set PROCESSOR_TESTS to its `nes6502/v1` directory and
+//! run `cargo test --test processor_tests -- --ignored`
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use nes_emulator::cpu::cpu::CPU;
+use nes_emulator::cpu::memory::Mem;
+use nes_emulator::cpu::opcodes;
+use nes_emulator::cpu::processor_status::ProcessorStatus;
+
+const SAMPLE_DIR: &str = "test-roms/processor_tests";
+
+/// One bus access in the order the CPU made it
+#[derive(Debug, PartialEq)]
+struct Access {
+    addr: u16,
+    data: u8,
+    write: bool,
+}
+
+/// 64K of RAM that writes down every read and write going through it
+struct RecordingBus {
+    memory: Vec<u8>,
+    accesses: RefCell<Vec<Access>>,
+}
+
+impl Mem for RecordingBus {
+    fn read_mem_u8(&self, addr: u16) -> u8 {
+        let data = self.memory[addr as usize];
+        self.accesses.borrow_mut().push(Access {
+            addr,
+            data,
+            write: false,
+        });
+        data
+    }
+
+    fn write_mem_u8(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+        self.accesses.borrow_mut().push(Access {
+            addr,
+            data,
+            write: true,
+        });
+    }
+}
+
+/// How one opcode did across all of its vectors
+#[derive(Default)]
+struct Tally {
+    vectors: usize,
+    state: usize,
+    cycles: usize,
+    bus: usize,
+    errors: usize,
+    first_failure: Option<String>,
+}
+
+impl Tally {
+    fn passed(&self) -> bool {
+        self.state == self.vectors && self.cycles == self.vectors && self.bus == self.vectors
+    }
+}
+
+fn field(state: &Value, name: &str) -> u64 {
+    state[name]
+        .as_u64()
+        .unwrap_or_else(|| panic!("vector is missing {}", name))
+}
+
+fn setup(initial: &Value) -> CPU<RecordingBus> {
+    let mut memory = vec![0; 0x10000];
+    for entry in initial["ram"].as_array().unwrap() {
+        memory[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+
+    let mut cpu = CPU::new(RecordingBus {
+        memory,
+        accesses: RefCell::new(Vec::new()),
+    });
+    cpu.program_counter = field(initial, "pc") as u16;
+    cpu.stack_pointer = field(initial, "s") as u8;
+    cpu.accumulator = field(initial, "a") as u8;
+    cpu.index_register_x = field(initial, "x") as u8;
+    cpu.index_register_y = field(initial, "y") as u8;
+    cpu.processor_status = ProcessorStatus(field(initial, "p") as u8);
+    cpu
+}
+
+/// Lists what differs between the CPU and the final state of the vector
+fn compare_state(cpu: &CPU<RecordingBus>, expected: &Value) -> Vec<String> {
+    let mut differences = Vec::new();
+    let registers = [
+        ("pc", cpu.program_counter as u64),
+        ("s", cpu.stack_pointer as u64),
+        ("a", cpu.accumulator as u64),
+        ("x", cpu.index_register_x as u64),
+        ("y", cpu.index_register_y as u64),
+        ("p", cpu.processor_status.0 as u64),
+    ];
+
+    for (name, actual) in registers {
+        if actual != field(expected, name) {
+            differences.push(format!(
+                "{} is {:#x}, expected {:#x}",
+                name,
+                actual,
+                field(expected, name)
+            ));
+        }
+    }
+
+    for entry in expected["ram"].as_array().unwrap() {
+        let addr = entry[0].as_u64().unwrap() as usize;
+        let value = entry[1].as_u64().unwrap() as u8;
+        if cpu.bus.memory[addr] != value {
+            differences.push(format!(
+                "ram[{:#06x}] is {:#04x}, expected {:#04x}",
+                addr, cpu.bus.memory[addr], value
+            ));
+        }
+    }
+
+    differences
+}
+
+fn expected_accesses(vector: &Value) -> Vec<Access> {
+    vector["cycles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cycle| Access {
+            addr: cycle[0].as_u64().unwrap() as u16,
+            data: cycle[1].as_u64().unwrap() as u8,
+            write: cycle[2].as_str() == Some("write"),
+        })
+        .collect()
+}
+
+fn run_vector(vector: &Value, tally: &mut Tally) {
+    let name = vector["name"].as_str().unwrap_or("?").to_string();
+    let mut cpu = setup(&vector["initial"]);
+    tally.vectors += 1;
+
+    let step = match cpu.step() {
+        Ok(step) => step,
+        Err(error) => {
+            tally.errors += 1;
+            tally
+                .first_failure
+                .get_or_insert(format!("{}: {}", name, error));
+            return;
+        }
+    };
+
+    let differences = compare_state(&cpu, &vector["final"]);
+    if differences.is_empty() {
+        tally.state += 1;
+    } else {
+        tally
+            .first_failure
+            .get_or_insert(format!("{}: {}", name, differences.join(", ")));
+    }
+
+    let expected = expected_accesses(vector);
+    if step.cycles == expected.len() as u64 {
+        tally.cycles += 1;
+    } else {
+        tally.first_failure.get_or_insert(format!(
+            "{}: took {} cycles, expected {}",
+            name,
+            step.cycles,
+            expected.len()
+        ));
+    }
+
+    if *cpu.bus.accesses.borrow() == expected {
+        tally.bus += 1;
+    } else {
+        tally.first_failure.get_or_insert(format!(
+            "{}: bus accesses {:?}, expected {:?}",
+            name,
+            cpu.bus.accesses.borrow(),
+            expected
+        ));
+    }
+}
+
+/// Runs every `<opcode>.json` in `dir`, returns how each opcode did
+fn run_dir(dir: &Path) -> BTreeMap<u8, Tally> {
+    let entries =
+        fs::read_dir(dir).unwrap_or_else(|error| panic!("can't read {}: {}", dir.display(), error));
+
+    let mut results: BTreeMap<u8, Tally> = BTreeMap::new();
+    for entry in entries {
+        let path = entry.unwrap().path();
+        let code = match path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| u8::from_str_radix(stem, 16).ok())
+        {
+            Some(code) if path.extension().is_some_and(|ext| ext == "json") => code,
+            _ => continue,
+        };
+
+        let vectors: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let tally = results.entry(code).or_default();
+        for vector in vectors.as_array().unwrap() {
+            run_vector(vector, tally);
+        }
+    }
+    results
+}
+
+/// Prints a table of the results and fails on any opcode that didn't pass
+fn report(results: &BTreeMap<u8, Tally>) {
+    println!("op  mnemonic  vectors  state  cycles    bus  errors");
+    for (code, tally) in results {
+        let mnemonic = opcodes::lookup(*code).map_or("???", |opcode| opcode.mnemonic);
+        println!(
+            "{:02x}  {:8}  {:7}  {:5}  {:6}  {:5}  {:6}  {}",
+            code,
+            mnemonic,
+            tally.vectors,
+            tally.state,
+            tally.cycles,
+            tally.bus,
+            tally.errors,
+            if tally.passed() { "ok" } else { "FAIL" }
+        );
+    }
+
+    let failures: Vec<String> = results
+        .iter()
+        .filter(|(_, tally)| !tally.passed())
+        .map(|(code, tally)| {
+            format!(
+                "{:02x} {}",
+                code,
+                tally.first_failure.as_deref().unwrap_or("")
+            )
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} opcodes failed:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn sample_vectors() {
+    let results = run_dir(Path::new(SAMPLE_DIR));
+    assert!(!results.is_empty(), "no vectors in {}", SAMPLE_DIR);
+    report(&results);
+}
+
+#[test]
+#[ignore = "needs the full ProcessorTests suite in PROCESSOR_TESTS, see the top of processor_tests.rs"]
+fn processor_tests() {
+    let dir = std::env::var("PROCESSOR_TESTS")
+        .map(PathBuf::from)
+        .expect("PROCESSOR_TESTS has to point at the nes6502/v1 directory of the suite");
+
+    let results = run_dir(&dir);
+    let missing: Vec<String> = (0..=0xffu8)
+        .filter(|code| !results.contains_key(code))
+        .map(|code| format!("{:02x}", code))
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "{} is missing the files for {}",
+        dir.display(),
+        missing.join(" ")
+    );
+    report(&results);
+}